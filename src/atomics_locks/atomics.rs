@@ -3,16 +3,21 @@ fn main() {
 }
 
 fn example_load_store_stop_flag() {
-    use std::sync::atomic::{AtomicBool, Ordering};
-    static STOP: AtomicBool = AtomicBool::new(false);
+    // Originally a `static STOP: AtomicBool` polled every 500ms; a cancellation token is the same
+    // flag, but `cancel()` also wakes the worker out of its sleep.
+    use plygnd::sync::CancellationToken;
+    use std::time::Duration;
+
+    let stop = CancellationToken::new();
 
     println!("Type 'help' for a list of commands.");
 
     // Spawn a thread to do the work.
-    let background_thread = std::thread::spawn(move || {
-        while !STOP.load(Ordering::Relaxed) {
-            // Do some work.
-            std::thread::sleep(std::time::Duration::from_millis(500));
+    let background_thread = std::thread::spawn({
+        let stop = stop.clone();
+        move || {
+            // Do some work, then wait 500ms or until cancelled.
+            while !stop.sleep_or_cancel(Duration::from_millis(500)) {}
         }
     });
 
@@ -25,7 +30,7 @@ fn example_load_store_stop_flag() {
         }
     }
 
-    STOP.store(true, Ordering::Relaxed);
+    stop.cancel();
 
     background_thread.join().unwrap();
 }
@@ -1,6 +1,6 @@
 #![feature(thread_id_value)]
 
-use std::sync::atomic::{AtomicU64, Ordering};
+use plygnd::sync::OnceLock;
 
 fn main() {
     // One time initialization.
@@ -15,28 +15,20 @@ fn main() {
 }
 
 fn get_key() -> u64 {
-    static KEY: AtomicU64 = AtomicU64::new(0);
-    let key = KEY.load(Ordering::Relaxed);
+    static KEY: OnceLock<u64> = OnceLock::new();
     let tid = std::thread::current().id().as_u64();
-    if key == 0 {
-        println!("Trying initialis key on thread@{tid} ...");
-        let new_key = generate_key();
-        match KEY.compare_exchange(0, new_key, Ordering::Relaxed, Ordering::Relaxed) {
-            Ok(_) => {
-                println!("Key initialized by thread@{tid}");
-                new_key
-            }
-            Err(k) => {
-                // If we get here, another thread has already initialized the key.
-                // We just return the key that the other thread has initialized.
-                println!("Key is already initialised by other thread -- key: {}", k);
-                k
-            }
-        }
-    } else {
+    if let Some(&key) = KEY.get() {
         println!("Key already initialized.");
-        key
+        return key;
     }
+    // Unlike a bare `compare_exchange`, only one thread ever runs the initialiser. Any thread that
+    // arrives while it runs sleeps until the key is ready instead of generating a key of its own.
+    *KEY.get_or_init(|| {
+        println!("Trying initialis key on thread@{tid} ...");
+        let key = generate_key();
+        println!("Key initialized by thread@{tid}");
+        key
+    })
 }
 
 const fn generate_key() -> u64 {
@@ -1,3 +1,4 @@
+use plygnd::sync::{IdAllocator, Overflow};
 use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
 
 fn increment(a: &AtomicU32) {
@@ -36,6 +37,28 @@ fn main() {
             println!("Thread@{tid:?} got ID {id}");
         })
         .join()
-        .unwrap();
+        .unwrap_or_else(|_| println!("Thread panicked: IDs exhausted"));
     }
+
+    // The same, with an allocator that reports exhaustion as an error instead of panicking,
+    // and takes IDs back once a thread is done with them.
+    let ids = IdAllocator::<AtomicU8>::recycling(100, Overflow::Error);
+    std::thread::scope(|s| {
+        for i in 0..150 {
+            let ids = &ids;
+            s.spawn(move || {
+                let tid = std::thread::current().id();
+                match ids.allocate() {
+                    Ok(id) => {
+                        println!("Thread@{tid:?} got ID {id}");
+                        // Every other thread hands its ID back for reuse.
+                        if i % 2 == 0 {
+                            ids.release(id);
+                        }
+                    }
+                    Err(e) => println!("Thread@{tid:?} got no ID: {e}"),
+                }
+            });
+        }
+    });
 }
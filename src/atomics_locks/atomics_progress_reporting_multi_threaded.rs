@@ -1,33 +1,42 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
+use plygnd::progress::{cli, Tracker};
 use std::thread;
+use std::time::{Duration, Instant};
 
-// Fetch-and-Modify
+// Fetch-and-Modify, through `progress::Tracker`.
+//
+// Usage: atomics_progress_reporting_multi_threaded [workers] [items-per-worker] [--json]
 
 fn main() {
-    let num_done = &AtomicUsize::new(0);
+    let ([workers, items], json) =
+        cli::parse_args("[workers] [items-per-worker] [--json]", [4, 25]);
+    let tracker = &Tracker::new(workers * items);
+
+    // Typing 'stop' cancels the remaining work.
+    let stop = &cli::stop_on_stdin();
 
     thread::scope(|s| {
-        // Spawn four threads to do the work then report progress.
-        for t in 0..4 {
+        // Spawn the worker threads to do the work then report progress.
+        for t in 0..workers {
             s.spawn(move || {
-                for i in 0..25 {
-           n         // Do some work.
-                    thread::sleep(std::time::Duration::from_secs_f32(
-                        (t * 25 + i) as f32 * 0.01,
-                    ));
-                    num_done.fetch_add(1, Ordering::Relaxed);
+                for i in 0..items {
+                    let start = Instant::now();
+                    // Do some work, unless cancelled.
+                    if stop.sleep_or_cancel(Duration::from_millis((t * items + i) * 10)) {
+                        return;
+                    }
+                    tracker.record(start.elapsed());
                 }
             });
         }
 
-        // The main thread shows status updates, every half second.
-        loop {
-            let n = num_done.load(Ordering::Relaxed);
-            if n == 100 {
-                break;
+        // The main thread shows status updates every half second, and is woken early once the
+        // last item is done.
+        tracker.observe_until(stop, Duration::from_millis(500), |snapshot| {
+            if json {
+                println!("{}", snapshot.to_json());
+            } else {
+                println!("{snapshot}");
             }
-            println!("Progress: {n}/100 done");
-            thread::sleep(std::time::Duration::from_secs_f32(0.5));
-        }
+        });
     });
 }
@@ -1,51 +1,58 @@
-use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use plygnd::progress::{cli, Tracker};
 use std::thread;
+use std::time::{Duration, Instant};
 
-// Fetch-and-Modify
+// Sharded seqlocks
+//
+// Every `record` updates the count, total and max together under the seqlock of the worker's own
+// shard, plus one relaxed bucket increment in its histogram shard and one shared relaxed
+// increment that spots the last item. Workers on different shards never wait for each other, and
+// the observer never holds them up: it reads each shard optimistically, retrying if a write got in
+// between, so every report adds up consistent (count, total, max) triples.
+//
+// Usage: atomics_progress_reporting_multi_threaded_statistics [workers] [items-per-worker] [--json]
 
 fn main() {
-    let num_done = &AtomicUsize::new(0);
-    let total_time = &AtomicU64::new(0);
-    let max_time = &AtomicU64::new(0);
+    let ([workers, items], json) =
+        cli::parse_args("[workers] [items-per-worker] [--json]", [4, 25]);
+    let tracker = &Tracker::new(workers * items);
+
+    // Typing 'stop' cancels the remaining work.
+    let stop = &cli::stop_on_stdin();
 
     thread::scope(|s| {
         s.spawn(move || {
-            // Spawn four threads to do the work then report progress.
-            for t in 0..4 {
+            // Spawn the worker threads to do the work then report progress.
+            for t in 0..workers {
                 s.spawn(move || {
-                    for i in 0..25 {
-                        let start = std::time::Instant::now();
-                        // Do some work.
-                        thread::sleep(std::time::Duration::from_millis((t * 25 + i) as u64 * 10));
-                        let time_taken = start.elapsed().as_millis() as u64;
-
-                        num_done.fetch_add(1, Ordering::Relaxed);
-                        total_time.fetch_add(time_taken, Ordering::Relaxed);
-                        max_time.fetch_max(time_taken, Ordering::Relaxed);
+                    for i in 0..items {
+                        let start = Instant::now();
+                        // Do some work, unless cancelled.
+                        if stop.sleep_or_cancel(Duration::from_millis((t * items + i) * 10)) {
+                            return;
+                        }
+                        tracker.record(start.elapsed());
                     }
                 });
             }
         });
 
         // The main thread shows status updates, every half second.
-        loop {
-            let total_time = total_time.load(Ordering::Relaxed);
-            let max_time = max_time.load(Ordering::Relaxed);
-            let n = num_done.load(Ordering::Relaxed);
-            if n == 0 {
-                println!("Working... nothing done yet.")
-            } else if n == 100 {
-                break;
+        tracker.observe_until(stop, Duration::from_secs_f32(0.5), |snapshot| {
+            if json {
+                println!("{}", snapshot.to_json());
             } else {
-                println!(
-                    "Progress: {n}/100 done, {:?}ms average, {:?}ms peek",
-                    total_time / n as u64,
-                    max_time
-                );
+                println!("{snapshot}");
             }
-            thread::sleep(std::time::Duration::from_secs_f32(0.5));
-        }
+        });
     });
 
-    print!("Done!");
+    println!(
+        "{}",
+        if stop.is_cancelled() {
+            "Stopped."
+        } else {
+            "Done!"
+        }
+    );
 }
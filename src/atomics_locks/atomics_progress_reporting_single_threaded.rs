@@ -1,31 +1,47 @@
+use plygnd::progress::{cli, Tracker};
+use std::time::{Duration, Instant};
+
+// Usage: atomics_progress_reporting_single_threaded [items] [--json]
+
 fn main() {
-    use std::sync::atomic::{AtomicUsize, Ordering};
+    let ([items], json) = cli::parse_args("[items] [--json]", [100]);
+
+    let tracker = Tracker::new(items);
 
-    let main_thread = std::thread::current();
+    // Typing 'stop' cancels the remaining items.
+    let stop = cli::stop_on_stdin();
 
-    let num_done = AtomicUsize::new(0);
     std::thread::scope(|s| {
         s.spawn(|| {
-            for i in 0..100 {
+            for i in 0..items {
+                let start = Instant::now();
                 {
                     println!("Process item {i}");
-                    std::thread::sleep(std::time::Duration::from_secs_f32(0.1));
+                    if stop.sleep_or_cancel(Duration::from_secs_f32(0.1)) {
+                        return;
+                    }
                 }
-                num_done.store(i + 1, Ordering::Relaxed);
-                main_thread.unpark(); // Wake up the main thread.
+                tracker.record(start.elapsed());
             }
         });
 
-        // The main thread shows status updats.
-        loop {
-            let n = num_done.load(Ordering::Relaxed);
-            if n == 100 {
-                break;
+        // The main thread shows status updates; the tracker wakes it up (unpark) as soon as the
+        // last item is done.
+        tracker.observe_until(&stop, Duration::from_secs(1), |snapshot| {
+            if json {
+                println!("{}", snapshot.to_json());
+            } else {
+                println!("{snapshot}");
             }
-            println!("Progress: {n}/100 done");
-            std::thread::park_timeout(std::time::Duration::from_secs(1));
-        }
+        });
     });
 
-    println!("Done!");
+    println!(
+        "{}",
+        if stop.is_cancelled() {
+            "Stopped."
+        } else {
+            "Done!"
+        }
+    );
 }
@@ -1,9 +1,11 @@
 #![feature(thread_id_value)]
 
+use plygnd::reclaim::epoch::{self, AtomicBox};
+use plygnd::sync::{AtomicTaggedPtr, RaceOnce, TaggedPtr};
 use std::collections::HashMap;
-use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::atomic::Ordering;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Data {
     a: HashMap<u32, u32>,
     b: HashMap<u32, u32>,
@@ -20,42 +22,108 @@ impl Default for Data {
 }
 
 fn get_data() -> &'static Data {
-    static DATA: AtomicPtr<Data> = AtomicPtr::new(std::ptr::null_mut());
+    // An `AtomicPtr<Data>` behind a safe interface: the winning `compare_exchange` publishes its
+    // Box with Release, and the losers free theirs and Acquire the winner's.
+    static DATA: RaceOnce<Data> = RaceOnce::new();
 
-    let mut p = DATA.load(Ordering::Acquire);
     let tid = std::thread::current().id().as_u64();
-
-    if p.is_null() {
-        let data = Box::new(Data::default());
-        p = Box::into_raw(data);
-        match DATA.compare_exchange(
-            std::ptr::null_mut(),
-            p,
-            Ordering::Release, // success ordering (store)
-            Ordering::Acquire, // failure ordering (load)
-        ) {
-            Ok(k) => {
-                println!("Data initialized by thread@{tid}");
-                assert!(k.is_null());
-            }
-            Err(e) => {
-                // If we get here, another thread has already initialized the key.
-                println!(
-                    "Data is already initialised by other thread -- data : {:?}",
-                    e
-                );
-                // Failed to store the pointer.
-                // Another thread must have stored a pointer first.
-                // We have to free the pointer we created.
-                // Safety: p comes from Box::into_raw, and wasn't shared with other threads.
-                drop(unsafe { Box::from_raw(p) });
-                p = e;
-            }
+    if let Some(data) = DATA.get() {
+        return data;
+    }
+    match DATA.set(Box::default()) {
+        Ok(data) => {
+            println!("Data initialized by thread@{tid}");
+            data
+        }
+        Err(_ours) => {
+            // If we get here, another thread has already initialized the data,
+            // and the Box we built is dropped.
+            let data = DATA.get().unwrap();
+            println!("Data is already initialised by other thread -- data : {data:?}");
+            data
         }
     }
+}
+
+/// `RaceOnce` can't replace its data: a reader may still be using the old Box. An `AtomicBox`
+/// can, because its readers pin themselves, and an old Box is only dropped once every thread that
+/// was pinned when it was swapped out has unpinned.
+fn republish() {
+    let data = AtomicBox::new(Data::default());
+    std::thread::scope(|s| {
+        for _ in 0..3 {
+            s.spawn(|| {
+                for _ in 0..5 {
+                    let guard = epoch::pin();
+                    let current = data.load(&guard);
+                    let tid = std::thread::current().id().as_u64();
+                    println!("thread@{tid} reads a[1] = {}", current.a[&1]);
+                    drop(guard);
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                }
+            });
+        }
+        for version in 3..8 {
+            let mut next = Data::default();
+            next.a.insert(1, version);
+            data.store(next);
+            println!("Published a[1] = {version}");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    });
+}
 
-    // Safety: p is not null, and points to a properly initialized Data.
-    unsafe { &*p }
+/// Like `republish`, but every thread publishes, each building on the version it read. A
+/// publication must not overwrite one it didn't build on, and the compare-exchange's pointer
+/// comparison is enough to tell: the Box a publisher read stays pinned until it is done, so it
+/// can't be freed and its address handed to a newer version in the meantime. The tag only numbers
+/// the versions, so each publisher can say which one it made.
+fn republish_versioned() {
+    const PUBLISHERS: usize = 3;
+    const UPDATES: usize = 5;
+    let data = AtomicTaggedPtr::new(TaggedPtr::new(Box::into_raw(Box::<Data>::default()), 0));
+    std::thread::scope(|s| {
+        for _ in 0..PUBLISHERS {
+            s.spawn(|| {
+                let tid = std::thread::current().id().as_u64();
+                for _ in 0..UPDATES {
+                    let guard = epoch::pin();
+                    let mut current = data.load(Ordering::Acquire);
+                    loop {
+                        // Safety: old versions are only freed once every thread pinned when they
+                        // were replaced has unpinned.
+                        let mut next = unsafe { &*current.ptr() }.clone();
+                        *next.a.get_mut(&1).unwrap() += 1;
+                        let next = Box::into_raw(Box::new(next));
+                        match data.compare_exchange(
+                            current,
+                            current.next(next),
+                            Ordering::AcqRel,
+                            Ordering::Acquire,
+                        ) {
+                            Ok(old) => {
+                                println!("thread@{tid} published version {}", old.tag() + 1);
+                                // Safety: `old` is no longer reachable through `data`.
+                                unsafe { guard.defer_destroy(old.ptr()) };
+                                break;
+                            }
+                            Err(newer) => {
+                                // Someone published first: build on theirs instead.
+                                // Safety: `next` was never shared.
+                                drop(unsafe { Box::from_raw(next) });
+                                current = newer;
+                            }
+                        }
+                    }
+                }
+            });
+        }
+    });
+    let last = data.into_inner();
+    // Safety: the threads are done, and the last version was never replaced.
+    let last = unsafe { Box::from_raw(last.ptr()) };
+    assert_eq!(last.a[&1], 2 + (PUBLISHERS * UPDATES) as u32);
+    println!("version {}: a[1] = {}", (PUBLISHERS * UPDATES), last.a[&1]);
 }
 
 fn main() {
@@ -68,4 +136,8 @@ fn main() {
         .join()
         .unwrap();
     }
+
+    // Publishing new versions.
+    republish();
+    republish_versioned();
 }
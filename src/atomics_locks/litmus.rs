@@ -0,0 +1,52 @@
+//! Runs the classic litmus tests from `plygnd::litmus::catalog` and prints how often each outcome
+//! showed up. Exits with an error if a forbidden outcome was observed.
+//!
+//! Usage: litmus [sb|mp|lb|iriw|2+2w|corr]... [--strength relaxed|rel-acq|seq-cst]
+//!               [--iterations N] [--jitter N] [--no-pin]
+
+use plygnd::litmus::catalog::{self, Classic, Strength};
+use plygnd::litmus::Config;
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: litmus [sb|mp|lb|iriw|2+2w|corr]... \
+    [--strength relaxed|rel-acq|seq-cst] [--iterations N] [--jitter N] [--no-pin]";
+
+fn main() -> ExitCode {
+    let mut config = Config::default();
+    let mut strengths = Strength::ALL.to_vec();
+    let mut names = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().expect(USAGE);
+        match arg.as_str() {
+            "--strength" => strengths = vec![value().parse().expect(USAGE)],
+            "--iterations" => config.iterations = value().parse().expect(USAGE),
+            "--jitter" => config.jitter = value().parse().expect(USAGE),
+            "--no-pin" => config.pin = false,
+            _ => names.push(arg.to_lowercase()),
+        }
+    }
+
+    let mut failed = false;
+    for strength in strengths {
+        for test in catalog::all(strength) {
+            if !names.is_empty() && !names.iter().any(|n| short_name(&test) == *n) {
+                continue;
+            }
+            let report = test.run(&config);
+            print!("{report}");
+            failed |= !report.is_ok();
+        }
+    }
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// "SB (relaxed)" -> "sb".
+fn short_name(test: &Classic) -> String {
+    let name = test.name();
+    name.split(' ').next().unwrap_or(name).to_lowercase()
+}
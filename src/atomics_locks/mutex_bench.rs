@@ -0,0 +1,63 @@
+//! Compares `std::sync::Mutex`, the futex-backed `plygnd::sync::Mutex` and the spinning
+//! `plygnd::sync::SpinLock` under the `mutex()` workload from `threads.rs`: every thread locks,
+//! bumps a shared counter 100 times and unlocks, over and over.
+
+use plygnd::sync::{Mutex, SpinLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const ROUNDS: usize = 100_000;
+const THREAD_COUNTS: [usize; 5] = [1, 2, 4, 8, 16];
+
+fn bench(threads: usize, critical_section: impl Fn() + Sync) -> Duration {
+    let start = Instant::now();
+    thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| {
+                for _ in 0..ROUNDS {
+                    critical_section();
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+fn main() {
+    println!(
+        "{:>8} {:>14} {:>14} {:>14}",
+        "threads", "std", "futex", "spin"
+    );
+    for threads in THREAD_COUNTS {
+        let std_mutex = std::sync::Mutex::new(0u64);
+        let std_time = bench(threads, || {
+            let mut n = std_mutex.lock().unwrap();
+            for _ in 0..100 {
+                *n += 1;
+            }
+        });
+
+        let futex_mutex = Mutex::new(0u64);
+        let futex_time = bench(threads, || {
+            let mut n = futex_mutex.lock().unwrap();
+            for _ in 0..100 {
+                *n += 1;
+            }
+        });
+
+        let spin_lock = SpinLock::new(0u64);
+        let spin_time = bench(threads, || {
+            let mut n = spin_lock.lock();
+            for _ in 0..100 {
+                *n += 1;
+            }
+        });
+
+        let expected = (threads * ROUNDS * 100) as u64;
+        assert_eq!(std_mutex.into_inner().unwrap(), expected);
+        assert_eq!(futex_mutex.into_inner().unwrap(), expected);
+        assert_eq!(spin_lock.into_inner(), expected);
+
+        println!("{threads:>8} {std_time:>14.2?} {futex_time:>14.2?} {spin_time:>14.2?}");
+    }
+}
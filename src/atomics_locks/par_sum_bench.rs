@@ -0,0 +1,49 @@
+//! The `numbers.iter().sum()` average from `threads.rs`, as a parallel reduction: the slice is
+//! split in halves with `pool::join` until the pieces are small, and the pool's workers steal
+//! halves from each other. Compared with the sequential sum for 1 to 16 pool threads.
+
+use plygnd::pool::{self, ThreadPool};
+use std::time::{Duration, Instant};
+
+const NUMBERS: u64 = 10_000_000;
+/// Below this, splitting costs more than it gains.
+const SEQUENTIAL_BELOW: usize = 64 * 1024;
+const THREAD_COUNTS: [usize; 5] = [1, 2, 4, 8, 16];
+const RUNS: u32 = 5;
+
+fn sum(numbers: &[u64]) -> u64 {
+    if numbers.len() <= SEQUENTIAL_BELOW {
+        return numbers.iter().sum();
+    }
+    let (left, right) = numbers.split_at(numbers.len() / 2);
+    let (a, b) = pool::join(|| sum(left), || sum(right));
+    a + b
+}
+
+/// The best of a few runs, to keep the first touch of the numbers out of the timings.
+fn best(mut f: impl FnMut() -> u64, expected: u64) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            assert_eq!(f(), expected);
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let numbers = Vec::from_iter(0..NUMBERS);
+    let expected = NUMBERS * (NUMBERS - 1) / 2;
+
+    let sequential = best(|| numbers.iter().sum(), expected);
+    println!("{:>8} {:>14} {:>10}", "threads", "time", "speed-up");
+    println!("{:>8} {sequential:>14.2?} {:>10}", "seq", "1.00x");
+    for threads in THREAD_COUNTS {
+        let pool = ThreadPool::new(threads);
+        let parallel = best(|| pool.install(|| sum(&numbers)), expected);
+        let speed_up = sequential.as_secs_f64() / parallel.as_secs_f64();
+        println!("{threads:>8} {parallel:>14.2?} {speed_up:>9.2}x");
+    }
+    println!("Average: {}", expected as f64 / NUMBERS as f64);
+}
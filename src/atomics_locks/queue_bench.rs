@@ -0,0 +1,70 @@
+//! Compares `plygnd::lockfree::Queue` with the `Mutex<VecDeque>` that `thread_condvar()` in
+//! `threads.rs` started out with, for 1 to 16 producers and as many consumers. Consumers yield
+//! when they find the queue empty, so neither side pays for a condition variable.
+
+use plygnd::lockfree::Queue;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const ITEMS_PER_PRODUCER: usize = 50_000;
+const THREAD_COUNTS: [usize; 5] = [1, 2, 4, 8, 16];
+
+/// Runs `threads` producers pushing `ITEMS_PER_PRODUCER` items each and `threads` consumers
+/// popping until every item is consumed. Returns the time taken and the sum of the items popped.
+fn bench(
+    threads: usize,
+    push: impl Fn(usize) + Sync,
+    pop: impl Fn() -> Option<usize> + Sync,
+) -> (Duration, usize) {
+    let total = threads * ITEMS_PER_PRODUCER;
+    let consumed = AtomicUsize::new(0);
+    let sum = AtomicUsize::new(0);
+    let start = Instant::now();
+    thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| {
+                for i in 0..ITEMS_PER_PRODUCER {
+                    push(i);
+                }
+            });
+            s.spawn(|| {
+                let mut local = 0;
+                while consumed.load(Ordering::Relaxed) < total {
+                    match pop() {
+                        Some(item) => {
+                            local += item;
+                            consumed.fetch_add(1, Ordering::Relaxed);
+                        }
+                        None => thread::yield_now(),
+                    }
+                }
+                sum.fetch_add(local, Ordering::Relaxed);
+            });
+        }
+    });
+    (start.elapsed(), sum.into_inner())
+}
+
+fn main() {
+    println!("{:>8} {:>14} {:>14}", "threads", "mutex", "lock-free");
+    for threads in THREAD_COUNTS {
+        let expected = threads * ITEMS_PER_PRODUCER * (ITEMS_PER_PRODUCER - 1) / 2;
+
+        let deque = Mutex::new(VecDeque::new());
+        let (mutex_time, sum) = bench(
+            threads,
+            |i| deque.lock().unwrap().push_back(i),
+            || deque.lock().unwrap().pop_front(),
+        );
+        assert_eq!(sum, expected);
+
+        let queue = Queue::new();
+        let (queue_time, sum) = bench(threads, |i| queue.push(i), || queue.pop());
+        assert_eq!(sum, expected);
+
+        println!("{threads:>8} {mutex_time:>14.2?} {queue_time:>14.2?}");
+    }
+}
@@ -1,7 +1,10 @@
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use plygnd::race::{self, AtomicBool, Shared};
+use plygnd::sync::SpinLock;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 static DATA_ATOMIC: AtomicU64 = AtomicU64::new(0);
-static mut DATA_NON_ATOMIC: u64 = 0;
+// Formerly a `static mut`; the race detector checks that the read is ordered after the write.
+static DATA_NON_ATOMIC: Shared<u64> = Shared::new(0);
 
 static READY: AtomicBool = AtomicBool::new(false);
 
@@ -30,7 +33,7 @@ fn main() {
 }
 
 fn atomic_data() {
-    std::thread::spawn(|| {
+    race::thread::spawn(|| {
         DATA_ATOMIC.store(42, Ordering::Relaxed);
         READY.store(true, Ordering::Release); // Everything from before this store..
     });
@@ -45,12 +48,9 @@ fn atomic_data() {
 }
 
 fn non_atomic_data() {
-    std::thread::spawn(|| {
-        // Safety: nothing else is accessing DATA_NON_ATOMIC.
-        // because we haven't set the READY flag yet.
-        unsafe {
-            DATA_NON_ATOMIC = 1234;
-        }
+    race::thread::spawn(|| {
+        // Nothing else is accessing DATA_NON_ATOMIC, because we haven't set the READY flag yet.
+        DATA_NON_ATOMIC.write(|d| *d = 1234);
         READY.store(true, Ordering::Release);
     });
 
@@ -59,32 +59,21 @@ fn non_atomic_data() {
         println!("Waiting for data to be ready...");
     }
 
-    // Safety: nothing else is accessing DATA_NON_ATOMIC.
-    println!("{}", unsafe { DATA_NON_ATOMIC });
+    // Acquiring READY ordered this read after the write; a Relaxed load above would panic here.
+    println!("{}", DATA_NON_ATOMIC.read(|d| *d));
 }
 
-static mut DATA_MUTEX: String = String::new();
-static LOCKED: AtomicBool = AtomicBool::new(false);
+static DATA_MUTEX: SpinLock<String> = SpinLock::new(String::new());
 
 fn mutex_lock() {
     std::thread::scope(|s| {
         for _ in 0..100 {
             s.spawn(|| {
-                if LOCKED
-                    .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
-                    .is_ok()
-                {
-                    // Safety: nothing else is accessing DATA_MUTEX.
-                    unsafe {
-                        DATA_MUTEX.push_str("!");
-                    }
-                    LOCKED.store(false, Ordering::Release);
-                }
+                // Blocks until the lock is free instead of giving up after one attempt,
+                // so every thread gets to push its `!`.
+                DATA_MUTEX.lock().push('!');
             });
         }
     });
-    // Safety: nothing else is accessing DATA_MUTEX.
-    unsafe {
-        println!("{}", DATA_MUTEX);
-    }
+    println!("{}", *DATA_MUTEX.lock());
 }
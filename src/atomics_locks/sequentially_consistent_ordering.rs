@@ -1,31 +1,30 @@
-use std::sync::atomic::{AtomicBool, Ordering};
+use plygnd::race::{self, AtomicBool, Shared};
+use std::sync::atomic::Ordering;
 
 static A: AtomicBool = AtomicBool::new(false);
 static B: AtomicBool = AtomicBool::new(false);
 
-static mut S: String = String::new();
+// Formerly a `static mut`. With SeqCst at most one thread gets to push; the race detector would
+// catch two unordered pushes.
+static S: Shared<String> = Shared::new(String::new());
 
 fn main() {
-    let ta = std::thread::spawn(|| {
+    let ta = race::thread::spawn(|| {
         A.store(true, Ordering::SeqCst);
         if !B.load(Ordering::SeqCst) {
-            unsafe {
-                S.push_str("!");
-            }
+            S.write(|s| s.push('!'));
         }
     });
 
-    let tb = std::thread::spawn(|| {
+    let tb = race::thread::spawn(|| {
         B.store(true, Ordering::SeqCst);
         if !A.load(Ordering::SeqCst) {
-            unsafe {
-                S.push_str("?");
-            }
+            S.write(|s| s.push('?'));
         }
     });
 
     ta.join().unwrap();
     tb.join().unwrap();
 
-    println!("{}", unsafe { &S });
+    println!("{}", S.read(|s| s.clone()));
 }
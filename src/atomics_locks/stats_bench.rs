@@ -0,0 +1,83 @@
+//! Compares the shared atomics that `atomics_progress_reporting_multi_threaded_statistics.rs`
+//! started out with (one `fetch_add` for the count, one for the total and one `fetch_max`, plus a
+//! shared histogram) against `plygnd::stats`, for 1 to 64 threads recording the same values.
+
+use plygnd::progress::histogram::{Histogram, BUCKETS};
+use plygnd::stats::{ShardedCounter, ShardedHistogram, ShardedMax};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const RECORDS_PER_THREAD: u64 = 200_000;
+const THREAD_COUNTS: [u64; 7] = [1, 2, 4, 8, 16, 32, 64];
+
+/// What both designs report at the end: count, total, max and histogram.
+type Totals = (u64, u64, u64, [u64; BUCKETS]);
+
+/// Runs `threads` threads, each calling `record` with `RECORDS_PER_THREAD` values, and returns
+/// the time taken.
+fn bench(threads: u64, record: impl Fn(u64) + Sync) -> Duration {
+    let start = Instant::now();
+    thread::scope(|s| {
+        for t in 0..threads {
+            let record = &record;
+            s.spawn(move || {
+                for i in 0..RECORDS_PER_THREAD {
+                    // Durations-like values that spread over a few histogram buckets.
+                    record((t + 1) * (i % 1_000));
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+fn contended(threads: u64) -> (Duration, Totals) {
+    let count = AtomicU64::new(0);
+    let total = AtomicU64::new(0);
+    let max = AtomicU64::new(0);
+    let histogram = Histogram::new();
+    let time = bench(threads, |v| {
+        count.fetch_add(1, Ordering::Relaxed);
+        total.fetch_add(v, Ordering::Relaxed);
+        max.fetch_max(v, Ordering::Relaxed);
+        histogram.record(v);
+    });
+    let totals = (
+        count.into_inner(),
+        total.into_inner(),
+        max.into_inner(),
+        histogram.counts(),
+    );
+    (time, totals)
+}
+
+fn sharded(threads: u64) -> (Duration, Totals) {
+    let count = ShardedCounter::new();
+    let total = ShardedCounter::new();
+    let max = ShardedMax::new();
+    let histogram = ShardedHistogram::new();
+    let time = bench(threads, |v| {
+        count.increment();
+        total.add(v);
+        max.record(v);
+        histogram.record(v);
+    });
+    let totals = (
+        count.sum(),
+        total.sum(),
+        max.max().unwrap_or(0),
+        histogram.counts(),
+    );
+    (time, totals)
+}
+
+fn main() {
+    println!("{:>8} {:>14} {:>14}", "threads", "contended", "sharded");
+    for threads in THREAD_COUNTS {
+        let (contended_time, expected) = contended(threads);
+        let (sharded_time, totals) = sharded(threads);
+        assert!(totals == expected, "the two designs disagree");
+        println!("{threads:>8} {contended_time:>14.2?} {sharded_time:>14.2?}");
+    }
+}
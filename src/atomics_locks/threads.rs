@@ -1,9 +1,9 @@
 #![feature(thread_id_value)]
 
+use plygnd::sync::channel;
 use std::{
     cell::{Cell, RefCell},
-    collections::VecDeque,
-    sync::{Arc, Condvar, Mutex},
+    sync::{Arc, Mutex},
     thread,
     time::Duration,
 };
@@ -64,7 +64,7 @@ fn main() {
 
     mutex();
 
-    // thread_parking();
+    thread_parking();
 
     thread_condvar();
 }
@@ -146,7 +146,15 @@ fn interior_mutability() {
     arr.borrow_mut().push(4);
     println!("RefCell: arr = {arr:?}");
 
-    // RwLock - Concurrent version of RefCell.
+    // RwLock - Concurrent version of RefCell, many readers or one writer at a time.
+    let arr = plygnd::sync::RwLock::new(vec![1, 2, 3]);
+    thread::scope(|s| {
+        for _ in 0..2 {
+            s.spawn(|| println!("RwLock: reader sees {:?}", *arr.read()));
+        }
+        s.spawn(|| arr.write().push(4));
+    });
+    println!("RwLock: arr = {:?}", *arr.read());
 }
 
 fn mutex() {
@@ -165,55 +173,49 @@ fn mutex() {
 }
 
 fn thread_parking() {
-    let queue = Mutex::new(VecDeque::new());
+    // The consumer used to park itself whenever the queue was empty. `recv` now does the sleeping,
+    // and the loop ends once the producer drops its `Sender`.
+    let (sender, receiver) = channel::unbounded();
     thread::scope(|s| {
         // Consumer
-        let consumer = s.spawn(|| loop {
-            let item = queue.lock().unwrap().pop_front();
-            if let Some(item) = item {
+        s.spawn(move || {
+            for item in receiver.iter() {
                 println!("Consume: {item:?}");
-            } else {
-                println!("Queue is empty ==> parking");
-                thread::park();
             }
+            println!("Producer is gone ==> stop consuming");
         });
 
         // Producer
-        for i in 0.. {
+        for i in 0..5 {
             println!("Produce: {i:?}");
-            queue.lock().unwrap().push_back(i);
-            consumer.thread().unpark();
-            thread::sleep(Duration::from_secs(1));
+            sender.send(i).unwrap();
+            thread::sleep(Duration::from_millis(200));
         }
+        drop(sender);
     });
 }
 
 fn thread_condvar() {
-    let queue = Mutex::new(VecDeque::new());
-    let not_empty = Condvar::new();
+    // The Mutex<VecDeque> + Condvar pair now lives inside a bounded channel, which also blocks
+    // the producer whenever the consumers fall behind.
+    let (sender, receiver) = channel::bounded(2);
 
     thread::scope(|s| {
-        s.spawn(|| loop {
-            let mut q = queue.lock().unwrap();
-            let item = loop {
-                if let Some(item) = q.pop_front() {
-                    break item;
-                } else {
-                    // Unlocking, waiting, and relocking is all done by the wait() method.
-                    // The wait() method takes a MutexGuard and returns a new MutexGuard.
-                    println!("Queue is empty ==> waiting");
-                    q = not_empty.wait(q).unwrap();
+        for c in 0..2 {
+            let receiver = receiver.clone();
+            s.spawn(move || {
+                while let Ok(item) = receiver.recv() {
+                    println!("Consumer-{c} consume: {item:?}");
                 }
-            };
-            drop(q); // Explicitly drop the guard to release the lock before consuming the item.
-            println!("Consume: {item:?}");
-        });
+            });
+        }
 
-        for i in 0.. {
+        for i in 0..10 {
             println!("Produce: {i:?}");
-            queue.lock().unwrap().push_back(i);
-            not_empty.notify_one();
-            thread::sleep(Duration::from_secs(1));
+            sender.send(i).unwrap();
+            thread::sleep(Duration::from_millis(100));
         }
+        // Dropping the only sender disconnects the channel, which ends both consumer loops.
+        drop(sender);
     });
 }
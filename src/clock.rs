@@ -0,0 +1,35 @@
+//! Vector clocks, shared by the model checker and the race detector.
+
+/// A vector clock: entry `i` is the number of operations of thread `i` that happen before the
+/// point in time the clock describes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct VectorClock(Vec<u32>);
+
+impl VectorClock {
+    pub(crate) const fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    pub(crate) fn get(&self, thread: usize) -> u32 {
+        self.0.get(thread).copied().unwrap_or(0)
+    }
+
+    /// Counts one more operation of `thread` and returns its timestamp.
+    pub(crate) fn tick(&mut self, thread: usize) -> u32 {
+        if self.0.len() <= thread {
+            self.0.resize(thread + 1, 0);
+        }
+        self.0[thread] += 1;
+        self.0[thread]
+    }
+
+    /// Everything that happened before `other` now happens before `self` too.
+    pub(crate) fn join(&mut self, other: &VectorClock) {
+        if self.0.len() < other.0.len() {
+            self.0.resize(other.0.len(), 0);
+        }
+        for (a, &b) in self.0.iter_mut().zip(&other.0) {
+            *a = (*a).max(b);
+        }
+    }
+}
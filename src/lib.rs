@@ -0,0 +1,12 @@
+//! Reusable concurrency primitives distilled from the `atomics_locks` experiments.
+
+mod clock;
+pub mod litmus;
+pub mod lockfree;
+pub mod model;
+pub mod pool;
+pub mod progress;
+pub mod race;
+pub mod reclaim;
+pub mod stats;
+pub mod sync;
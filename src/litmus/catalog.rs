@@ -0,0 +1,237 @@
+//! The classic litmus tests, each parameterised by how strong its atomic operations are.
+//!
+//! All tests share [`Vars`]: two locations `x` and `y`, and registers `r` that threads load into.
+//! Outcomes are the registers (or, for 2+2W, the final values of `x` and `y`) in order. What is
+//! allowed and forbidden follows the C++/Rust memory model, not any particular CPU: x86 will never
+//! show some of the outcomes that are allowed with [`Strength::Relaxed`], but ARM may.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use super::Test;
+
+/// Locations and registers shared by the tests in this module. All zero to begin with.
+#[derive(Debug, Default)]
+pub struct Vars {
+    pub x: AtomicU32,
+    pub y: AtomicU32,
+    pub r: [AtomicU32; 4],
+}
+
+impl Vars {
+    fn regs<const N: usize>(&self) -> Vec<u32> {
+        self.r[..N]
+            .iter()
+            .map(|r| r.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    fn set(&self, reg: usize, v: u32) {
+        self.r[reg].store(v, Ordering::Relaxed);
+    }
+}
+
+pub type Classic = Test<Vars, Vec<u32>>;
+
+/// The orderings used for the loads and stores under test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strength {
+    /// `Relaxed` everywhere.
+    Relaxed,
+    /// `Release` stores and `Acquire` loads.
+    ReleaseAcquire,
+    /// `SeqCst` everywhere.
+    SeqCst,
+}
+
+impl Strength {
+    pub const ALL: [Strength; 3] = [Self::Relaxed, Self::ReleaseAcquire, Self::SeqCst];
+
+    fn load(self) -> Ordering {
+        match self {
+            Self::Relaxed => Ordering::Relaxed,
+            Self::ReleaseAcquire => Ordering::Acquire,
+            Self::SeqCst => Ordering::SeqCst,
+        }
+    }
+
+    fn store(self) -> Ordering {
+        match self {
+            Self::Relaxed => Ordering::Relaxed,
+            Self::ReleaseAcquire => Ordering::Release,
+            Self::SeqCst => Ordering::SeqCst,
+        }
+    }
+}
+
+impl fmt::Display for Strength {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Self::Relaxed => "relaxed",
+            Self::ReleaseAcquire => "rel-acq",
+            Self::SeqCst => "seq-cst",
+        })
+    }
+}
+
+impl FromStr for Strength {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "relaxed" => Ok(Self::Relaxed),
+            "rel-acq" => Ok(Self::ReleaseAcquire),
+            "seq-cst" => Ok(Self::SeqCst),
+            _ => Err(format!(
+                "unknown strength {s:?} (expected relaxed, rel-acq or seq-cst)"
+            )),
+        }
+    }
+}
+
+/// Store buffering, the scenario from `sequentially_consistent_ordering.rs`: each thread stores
+/// to one location, then loads the other. Both loads seeing 0 takes `SeqCst` to rule out.
+pub fn sb(strength: Strength) -> Classic {
+    let (ld, st) = (strength.load(), strength.store());
+    Test::new(format!("SB ({strength})"), Vars::regs::<2>)
+        .thread(move |v| {
+            v.x.store(1, st);
+            v.set(0, v.y.load(ld));
+        })
+        .thread(move |v| {
+            v.y.store(1, st);
+            v.set(1, v.x.load(ld));
+        })
+        .allowed([vec![0, 1], vec![1, 0], vec![1, 1]])
+        .split_on(strength != Strength::SeqCst, [vec![0, 0]])
+}
+
+/// Message passing, the scenario from `release_acquire_ordering.rs`: data is written (relaxed),
+/// then a flag; the reader loads the flag, then the data. Seeing the flag but not the data is
+/// ruled out by release/acquire.
+pub fn mp(strength: Strength) -> Classic {
+    let (ld, st) = (strength.load(), strength.store());
+    Test::new(format!("MP ({strength})"), Vars::regs::<2>)
+        .thread(move |v| {
+            v.x.store(1, Ordering::Relaxed);
+            v.y.store(1, st);
+        })
+        .thread(move |v| {
+            v.set(0, v.y.load(ld));
+            v.set(1, v.x.load(Ordering::Relaxed));
+        })
+        .allowed([vec![0, 0], vec![0, 1], vec![1, 1]])
+        .split_on(strength == Strength::Relaxed, [vec![1, 0]])
+}
+
+/// Load buffering, the shape of `out_of_thin_air_values.rs`: each thread loads one location, then
+/// stores to the other. Both loads seeing the other thread's (later) store is allowed for relaxed
+/// atomics, though hardware hardly ever shows it.
+pub fn lb(strength: Strength) -> Classic {
+    let (ld, st) = (strength.load(), strength.store());
+    Test::new(format!("LB ({strength})"), Vars::regs::<2>)
+        .thread(move |v| {
+            v.set(0, v.x.load(ld));
+            v.y.store(1, st);
+        })
+        .thread(move |v| {
+            v.set(1, v.y.load(ld));
+            v.x.store(1, st);
+        })
+        .allowed([vec![0, 0], vec![0, 1], vec![1, 0]])
+        .split_on(strength == Strength::Relaxed, [vec![1, 1]])
+}
+
+/// Independent reads of independent writes: two readers disagree about which of two unrelated
+/// stores happened first. Only `SeqCst`'s single total order forbids that.
+pub fn iriw(strength: Strength) -> Classic {
+    let (ld, st) = (strength.load(), strength.store());
+    let all: Vec<Vec<u32>> = (0..16u32)
+        .map(|bits| (0..4).map(|i| bits >> (3 - i) & 1).collect())
+        .collect();
+    let disagree = vec![1, 0, 1, 0];
+    Test::new(format!("IRIW ({strength})"), Vars::regs::<4>)
+        .thread(move |v| v.x.store(1, st))
+        .thread(move |v| v.y.store(1, st))
+        .thread(move |v| {
+            v.set(0, v.x.load(ld));
+            v.set(1, v.y.load(ld));
+        })
+        .thread(move |v| {
+            v.set(2, v.y.load(ld));
+            v.set(3, v.x.load(ld));
+        })
+        .allowed(all.into_iter().filter(|o| *o != disagree))
+        .split_on(strength != Strength::SeqCst, [disagree])
+}
+
+/// 2+2W: each thread writes both locations, in opposite orders. Ending with both locations holding
+/// each thread's *first* write means the two coherence orders contradict program order, which only
+/// `SeqCst` forbids. The outcome is the final `[x, y]`.
+pub fn two_plus_two_w(strength: Strength) -> Classic {
+    let st = strength.store();
+    Test::new(format!("2+2W ({strength})"), |v: &Vars| {
+        vec![v.x.load(Ordering::Relaxed), v.y.load(Ordering::Relaxed)]
+    })
+    .thread(move |v| {
+        v.x.store(1, st);
+        v.y.store(2, st);
+    })
+    .thread(move |v| {
+        v.y.store(1, st);
+        v.x.store(2, st);
+    })
+    .allowed([vec![1, 2], vec![2, 1], vec![2, 2]])
+    .split_on(strength != Strength::SeqCst, [vec![1, 1]])
+}
+
+/// Coherence of read-read pairs: two loads of the same location in one thread can't go back in
+/// time, at any strength.
+pub fn corr(strength: Strength) -> Classic {
+    let (ld, st) = (strength.load(), strength.store());
+    Test::new(format!("CoRR ({strength})"), Vars::regs::<2>)
+        .thread(move |v| v.x.store(1, st))
+        .thread(move |v| {
+            v.set(0, v.x.load(ld));
+            v.set(1, v.x.load(ld));
+        })
+        .allowed([vec![0, 0], vec![0, 1], vec![1, 1]])
+        .forbidden([vec![1, 0]])
+}
+
+/// Every test in this module at `strength`.
+pub fn all(strength: Strength) -> Vec<Classic> {
+    vec![
+        sb(strength),
+        mp(strength),
+        lb(strength),
+        iriw(strength),
+        two_plus_two_w(strength),
+        corr(strength),
+    ]
+}
+
+impl Classic {
+    /// Adds `outcomes` to the allowed set if `allowed`, to the forbidden set otherwise.
+    fn split_on(self, allowed: bool, outcomes: impl IntoIterator<Item = Vec<u32>>) -> Self {
+        if allowed {
+            self.allowed(outcomes)
+        } else {
+            self.forbidden(outcomes)
+        }
+    }
+}
+
+#[test]
+fn catalog_forbidden_outcomes_never_show_up() {
+    let config = super::Config {
+        iterations: 2_000,
+        batch: 500,
+        ..super::Config::default()
+    };
+    for test in all(Strength::SeqCst).iter().chain(&all(Strength::Relaxed)) {
+        let report = test.run(&config);
+        assert!(report.is_ok(), "{report}");
+    }
+}
@@ -0,0 +1,301 @@
+//! A runner for memory-model litmus tests.
+//!
+//! `sequentially_consistent_ordering.rs` and friends run their two threads once, which almost
+//! always shows the boring interleaving: the first thread finishes before the second one starts.
+//! The interesting outcomes need the threads to race within a few nanoseconds of each other, so a
+//! [`Test`] is run many times over, with every iteration started from a spinning barrier and
+//! offset by a small random delay per thread. Threads are re-pinned to a shuffled set of CPUs for
+//! every batch, so different pairs of cores (and caches) get to race.
+//!
+//! A test is a shared state `S` (usually a few atomics, all zero), one closure per thread that
+//! runs against it, and an extractor that turns the final state into an outcome once every thread
+//! is done. The [`Report`] is a histogram of outcomes, checked against the outcomes the memory
+//! model allows and forbids. The classic tests live in [`catalog`].
+
+pub mod catalog;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::hint;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+type Body<S> = Box<dyn Fn(&S) + Sync>;
+type Extract<S, O> = Box<dyn Fn(&S) -> O + Sync>;
+
+pub struct Test<S, O> {
+    name: String,
+    threads: Vec<Body<S>>,
+    extract: Extract<S, O>,
+    allowed: BTreeSet<O>,
+    forbidden: BTreeSet<O>,
+}
+
+/// How a [`Test`] is run.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub iterations: u64,
+    /// Iterations per batch. Each batch gets fresh states and freshly pinned threads.
+    pub batch: usize,
+    /// Each thread spins for a random `0..jitter` iterations before running its body.
+    pub jitter: u32,
+    /// Pin threads to CPUs, shuffled per batch. Only has an effect on Linux.
+    pub pin: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            iterations: 1_000_000,
+            batch: 10_000,
+            jitter: 64,
+            pin: true,
+        }
+    }
+}
+
+impl<S: Default + Sync, O: Ord + Clone + fmt::Debug> Test<S, O> {
+    /// An empty test; add threads with [`Test::thread`].
+    pub fn new(name: impl Into<String>, extract: impl Fn(&S) -> O + Sync + 'static) -> Self {
+        Self {
+            name: name.into(),
+            threads: Vec::new(),
+            extract: Box::new(extract),
+            allowed: BTreeSet::new(),
+            forbidden: BTreeSet::new(),
+        }
+    }
+
+    pub fn thread(mut self, body: impl Fn(&S) + Sync + 'static) -> Self {
+        self.threads.push(Box::new(body));
+        self
+    }
+
+    /// Outcomes the memory model permits. Observing anything that is neither allowed nor
+    /// forbidden is reported as unexpected, which usually means the test itself is wrong.
+    pub fn allowed(mut self, outcomes: impl IntoIterator<Item = O>) -> Self {
+        self.allowed.extend(outcomes);
+        self
+    }
+
+    /// Outcomes the memory model rules out.
+    pub fn forbidden(mut self, outcomes: impl IntoIterator<Item = O>) -> Self {
+        self.forbidden.extend(outcomes);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn run(&self, config: &Config) -> Report<O> {
+        assert!(!self.threads.is_empty(), "a litmus test needs threads");
+        assert!(config.batch > 0, "batch size must be non-zero");
+        let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+        let mut rng = Rng::new(0x9e37_79b9_7f4a_7c15);
+        let mut counts = BTreeMap::new();
+        let mut done = 0;
+        while done < config.iterations {
+            let len = (config.iterations - done).min(config.batch as u64) as usize;
+            let states: Vec<S> = (0..len).map(|_| S::default()).collect();
+            let barrier = SpinBarrier::new(self.threads.len(), cpus);
+            let cpu_offset = rng.below(cpus as u32) as usize;
+            let seed = rng.next();
+            thread::scope(|s| {
+                for (i, body) in self.threads.iter().enumerate() {
+                    let (states, barrier) = (&states, &barrier);
+                    s.spawn(move || {
+                        if config.pin {
+                            pin_to_cpu((cpu_offset + i) % cpus);
+                        }
+                        let mut rng = Rng::new(seed ^ (i as u64 + 1).wrapping_mul(0xff51afd7));
+                        for state in states {
+                            barrier.wait();
+                            for _ in 0..rng.below(config.jitter) {
+                                hint::spin_loop();
+                            }
+                            body(state);
+                        }
+                    });
+                }
+            });
+            // Joining the threads makes all of their effects visible here.
+            for state in &states {
+                *counts.entry((self.extract)(state)).or_insert(0) += 1;
+            }
+            done += len as u64;
+        }
+        Report {
+            name: self.name.clone(),
+            runs: done,
+            counts,
+            allowed: self.allowed.clone(),
+            forbidden: self.forbidden.clone(),
+        }
+    }
+}
+
+/// The outcome histogram of a [`Test`] run.
+#[derive(Debug, Clone)]
+pub struct Report<O> {
+    pub name: String,
+    pub runs: u64,
+    pub counts: BTreeMap<O, u64>,
+    pub allowed: BTreeSet<O>,
+    pub forbidden: BTreeSet<O>,
+}
+
+impl<O: Ord> Report<O> {
+    pub fn count(&self, outcome: &O) -> u64 {
+        self.counts.get(outcome).copied().unwrap_or(0)
+    }
+
+    /// How many runs ended in a forbidden outcome.
+    pub fn forbidden_observed(&self) -> u64 {
+        self.forbidden.iter().map(|o| self.count(o)).sum()
+    }
+
+    /// How many runs ended in an outcome that is neither allowed nor forbidden.
+    pub fn unexpected_observed(&self) -> u64 {
+        self.counts
+            .iter()
+            .filter(|(o, _)| !self.allowed.contains(o) && !self.forbidden.contains(o))
+            .map(|(_, n)| n)
+            .sum()
+    }
+
+    /// Whether every run ended in an allowed outcome.
+    pub fn is_ok(&self) -> bool {
+        self.forbidden_observed() == 0 && self.unexpected_observed() == 0
+    }
+}
+
+/// One line per outcome that is allowed, forbidden or was observed, e.g.
+/// `  [0, 0]      1523  forbidden  <-- !!!`.
+impl<O: Ord + fmt::Debug> fmt::Display for Report<O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}: {} runs", self.name, self.runs)?;
+        let outcomes: BTreeSet<_> = self
+            .allowed
+            .iter()
+            .chain(&self.forbidden)
+            .chain(self.counts.keys())
+            .collect();
+        for outcome in outcomes {
+            let n = self.count(outcome);
+            let (verdict, mark) = if self.forbidden.contains(outcome) {
+                ("forbidden", if n > 0 { "  <-- !!!" } else { "" })
+            } else if self.allowed.contains(outcome) {
+                ("allowed", "")
+            } else {
+                ("unexpected", "  <-- ???")
+            };
+            writeln!(
+                f,
+                "  {:<16} {n:>10}  {verdict}{mark}",
+                format!("{outcome:?}")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A barrier that spins instead of sleeping, so that threads leave it within nanoseconds of each
+/// other. Falls back to yielding if it has to wait long, and yields right away if there are more
+/// threads than CPUs, since then the last thread can't arrive while we're spinning.
+struct SpinBarrier {
+    threads: usize,
+    spin_limit: u32,
+    arrived: AtomicUsize,
+    generation: AtomicUsize,
+}
+
+impl SpinBarrier {
+    fn new(threads: usize, cpus: usize) -> Self {
+        Self {
+            threads,
+            spin_limit: if threads > cpus { 0 } else { 10_000 },
+            arrived: AtomicUsize::new(0),
+            generation: AtomicUsize::new(0),
+        }
+    }
+
+    fn wait(&self) {
+        // The generation can't move on until we have arrived, so reading it first is safe.
+        let generation = self.generation.load(Ordering::Acquire);
+        if self.arrived.fetch_add(1, Ordering::AcqRel) + 1 == self.threads {
+            self.arrived.store(0, Ordering::Relaxed);
+            self.generation.fetch_add(1, Ordering::Release);
+            return;
+        }
+        let mut spins = 0u32;
+        while self.generation.load(Ordering::Acquire) == generation {
+            if spins < self.spin_limit {
+                spins += 1;
+                hint::spin_loop();
+            } else {
+                thread::yield_now();
+            }
+        }
+    }
+}
+
+/// xorshift64*: plenty for jitter and shuffling, and deterministic per seed.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A value in `0..n`, or 0 if `n` is 0.
+    fn below(&mut self, n: u32) -> u32 {
+        (((self.next() >> 32) * n as u64) >> 32) as u32
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn pin_to_cpu(cpu: usize) {
+    // Safety: `set` is a properly initialised cpu_set_t, and pid 0 means the calling thread.
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+        // Failure (e.g. a restricted cpuset) only means less variety; the test still runs.
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_to_cpu(_cpu: usize) {}
+
+#[test]
+fn litmus_counts_every_run() {
+    use std::sync::atomic::AtomicU32;
+
+    let test = Test::new("counter", |x: &AtomicU32| x.load(Ordering::Relaxed))
+        .thread(|x| {
+            x.fetch_add(1, Ordering::Relaxed);
+        })
+        .thread(|x| {
+            x.fetch_add(1, Ordering::Relaxed);
+        })
+        .allowed([2])
+        .forbidden([0, 1]);
+    let config = Config {
+        iterations: 2_500,
+        batch: 1_000,
+        ..Config::default()
+    };
+    let report = test.run(&config);
+    assert_eq!(report.runs, 2_500);
+    assert_eq!(report.count(&2), 2_500);
+    assert!(report.is_ok());
+    assert!(report.to_string().contains("forbidden"));
+}
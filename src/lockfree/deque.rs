@@ -0,0 +1,354 @@
+//! The Chase–Lev work-stealing deque.
+//!
+//! One [`Worker`] pushes and pops at the bottom, LIFO, without contention as long as there is
+//! more than one item; any number of [`Stealer`]s take items from the top, FIFO, with a
+//! `compare_exchange` on `top`. The only race the owner has to join is over the very last item.
+//! The orderings are the ones from Lê et al., "Correct and Efficient Work-Stealing for Weak Memory
+//! Models" (PPoPP 2013).
+//!
+//! The buffer is a power-of-two ring that the worker doubles when it fills up. A stealer may still
+//! be reading the old buffer, so that is retired through [`epoch`](crate::reclaim::epoch), and
+//! stealers stay pinned while they use it. As in [`SeqLock`](crate::sync::SeqLock), a stealer can
+//! read a slot while the worker overwrites it (after the slot's item was taken and the ring
+//! wrapped), so slots are copied with volatile reads into a `MaybeUninit` that is only assumed
+//! initialised once the `compare_exchange` on `top` proves the copy was current.
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{fence, AtomicIsize, AtomicPtr, Ordering};
+use std::sync::Arc;
+
+use crate::reclaim::epoch;
+
+const MIN_CAPACITY: usize = 16;
+
+struct Buffer<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+}
+
+impl<T> Buffer<T> {
+    fn alloc(capacity: usize) -> *mut Self {
+        debug_assert!(capacity.is_power_of_two());
+        let slots = (0..capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect();
+        Box::into_raw(Box::new(Buffer { slots }))
+    }
+
+    fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn slot(&self, index: isize) -> *mut MaybeUninit<T> {
+        self.slots[index as usize & (self.capacity() - 1)].get()
+    }
+
+    /// # Safety
+    ///
+    /// The copy is only initialised if the slot held an item that nobody took or overwrote
+    /// during the read.
+    unsafe fn read(&self, index: isize) -> MaybeUninit<T> {
+        self.slot(index).read_volatile()
+    }
+
+    /// # Safety
+    ///
+    /// Only the worker writes, and only to slots outside `top..bottom`.
+    unsafe fn write(&self, index: isize, value: MaybeUninit<T>) {
+        self.slot(index).write_volatile(value)
+    }
+}
+
+struct Inner<T> {
+    /// The next item to steal.
+    top: AtomicIsize,
+    /// Where the worker pushes next.
+    bottom: AtomicIsize,
+    buffer: AtomicPtr<Buffer<T>>,
+}
+
+impl<T> Drop for Inner<T> {
+    fn drop(&mut self) {
+        let buffer = *self.buffer.get_mut();
+        // Safety: the worker and all stealers are gone; `top..bottom` are the items left.
+        unsafe {
+            for i in *self.top.get_mut()..*self.bottom.get_mut() {
+                (*buffer).read(i).assume_init_drop();
+            }
+            drop(Box::from_raw(buffer));
+        }
+    }
+}
+
+/// The owner's end of a deque.
+pub struct Worker<T> {
+    inner: Arc<Inner<T>>,
+    /// Push and pop assume they're the only ones changing `bottom` and `buffer`.
+    _not_sync: PhantomData<std::cell::Cell<()>>,
+}
+
+/// A handle for taking items from the top of someone else's deque.
+pub struct Stealer<T> {
+    inner: Arc<Inner<T>>,
+}
+
+/// The result of [`Stealer::steal`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Steal<T> {
+    Empty,
+    Success(T),
+    /// Another thread took the item first; there may be more.
+    Retry,
+}
+
+impl<T> Steal<T> {
+    pub fn success(self) -> Option<T> {
+        match self {
+            Steal::Success(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+// Safety: items are moved between threads, never shared.
+unsafe impl<T: Send> Send for Worker<T> {}
+unsafe impl<T: Send> Send for Stealer<T> {}
+unsafe impl<T: Send> Sync for Stealer<T> {}
+
+impl<T> Worker<T> {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                top: AtomicIsize::new(0),
+                bottom: AtomicIsize::new(0),
+                buffer: AtomicPtr::new(Buffer::alloc(MIN_CAPACITY)),
+            }),
+            _not_sync: PhantomData,
+        }
+    }
+
+    pub fn stealer(&self) -> Stealer<T> {
+        Stealer {
+            inner: self.inner.clone(),
+        }
+    }
+
+    pub fn push(&self, value: T) {
+        let inner = &*self.inner;
+        let bottom = inner.bottom.load(Ordering::Relaxed);
+        let top = inner.top.load(Ordering::Acquire);
+        let mut buffer = inner.buffer.load(Ordering::Relaxed);
+        // Safety: only we replace the buffer, and only we free it (through the collector).
+        if bottom - top >= unsafe { (*buffer).capacity() } as isize {
+            buffer = self.grow(buffer, top, bottom);
+        }
+        // Safety: `bottom` is outside `top..bottom`.
+        unsafe { (*buffer).write(bottom, MaybeUninit::new(value)) };
+        // Publishes the item before the new `bottom` that makes it stealable.
+        fence(Ordering::Release);
+        inner.bottom.store(bottom + 1, Ordering::Relaxed);
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let inner = &*self.inner;
+        let bottom = inner.bottom.load(Ordering::Relaxed) - 1;
+        let buffer = inner.buffer.load(Ordering::Relaxed);
+        inner.bottom.store(bottom, Ordering::Relaxed);
+        // Orders the claim on `bottom` before reading `top`, against the reverse in `steal`:
+        // a stealer and we can't both miss each other's claim on the last item.
+        fence(Ordering::SeqCst);
+        let top = inner.top.load(Ordering::Relaxed);
+        if top > bottom {
+            inner.bottom.store(bottom + 1, Ordering::Relaxed);
+            return None;
+        }
+        // Safety: `bottom` is ours unless it's also `top`, which is settled below.
+        let value = unsafe { (*buffer).read(bottom) };
+        if top == bottom {
+            // The last item: whoever moves `top` past it gets it.
+            let won = inner
+                .top
+                .compare_exchange(top, top + 1, Ordering::SeqCst, Ordering::Relaxed)
+                .is_ok();
+            inner.bottom.store(bottom + 1, Ordering::Relaxed);
+            if !won {
+                return None;
+            }
+        }
+        // Safety: the item was ours, and no one else will take it.
+        Some(unsafe { value.assume_init() })
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Replaces the full buffer with one twice its size.
+    fn grow(&self, old: *mut Buffer<T>, top: isize, bottom: isize) -> *mut Buffer<T> {
+        // Safety: only we free buffers, and `old` is the current one.
+        let new = Buffer::alloc(unsafe { (*old).capacity() } * 2);
+        for i in top..bottom {
+            // Safety: copies the items in `top..bottom`. A stealer reading `old` gets the same
+            // bits, and `top` still decides who owns each item.
+            unsafe { (*new).write(i, (*old).read(i)) };
+        }
+        let guard = epoch::pin();
+        // Release: stealers that load the new buffer see its items.
+        self.inner.buffer.store(new, Ordering::Release);
+        // Safety: `old` came from `Box::into_raw` and is no longer current. Dropping a buffer
+        // doesn't drop its items, so it's fine on any thread.
+        unsafe { guard.defer_destroy(old) };
+        new
+    }
+}
+
+impl<T> Stealer<T> {
+    pub fn steal(&self) -> Steal<T> {
+        let inner = &*self.inner;
+        let top = inner.top.load(Ordering::Acquire);
+        // See `pop`.
+        fence(Ordering::SeqCst);
+        let bottom = inner.bottom.load(Ordering::Acquire);
+        if top >= bottom {
+            return Steal::Empty;
+        }
+        let guard = epoch::pin();
+        let buffer = inner.buffer.load(Ordering::Acquire);
+        // Safety: we're pinned, so the buffer isn't freed under us; the copy is checked below.
+        let value = unsafe { (*buffer).read(top) };
+        drop(guard);
+        if inner
+            .top
+            .compare_exchange(top, top + 1, Ordering::SeqCst, Ordering::Relaxed)
+            .is_err()
+        {
+            return Steal::Retry;
+        }
+        // Safety: `top` was still ours to take, so the copy was of an item nobody else took.
+        Steal::Success(unsafe { value.assume_init() })
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Inner<T> {
+    fn len(&self) -> usize {
+        let top = self.top.load(Ordering::Relaxed);
+        let bottom = self.bottom.load(Ordering::Relaxed);
+        (bottom - top).max(0) as usize
+    }
+}
+
+impl<T> Default for Worker<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Clone for Stealer<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Worker<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Worker")
+            .field("len", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Debug for Stealer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Stealer")
+            .field("len", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[test]
+fn deque_worker_is_lifo_and_stealers_fifo() {
+    let worker = Worker::new();
+    let stealer = worker.stealer();
+    assert_eq!(stealer.steal(), Steal::Empty);
+    // Enough to grow the buffer a few times.
+    for i in 0..100 {
+        worker.push(i.to_string());
+    }
+    assert_eq!(worker.len(), 100);
+    assert_eq!(worker.pop().as_deref(), Some("99"));
+    assert_eq!(stealer.steal().success().as_deref(), Some("0"));
+    assert_eq!(stealer.steal().success().as_deref(), Some("1"));
+    assert_eq!(worker.pop().as_deref(), Some("98"));
+    // The rest are dropped with the deque.
+    drop(worker);
+    assert_eq!(stealer.len(), 96);
+}
+
+#[test]
+fn deque_loses_and_duplicates_nothing_under_stealing() {
+    const ITEMS: usize = 100_000;
+    let worker = Worker::new();
+    let stolen: Vec<Vec<usize>> = std::thread::scope(|s| {
+        let stealers: Vec<_> = (0..3)
+            .map(|_| {
+                let stealer = worker.stealer();
+                s.spawn(move || {
+                    let mut stolen = Vec::new();
+                    let mut empty = 0;
+                    // The worker is done once the deque stays empty for a while.
+                    while empty < 1_000 {
+                        match stealer.steal() {
+                            Steal::Success(v) => {
+                                stolen.push(v);
+                                empty = 0;
+                            }
+                            Steal::Retry => {}
+                            Steal::Empty => {
+                                empty += 1;
+                                std::thread::yield_now();
+                            }
+                        }
+                    }
+                    stolen
+                })
+            })
+            .collect();
+
+        // Push in bursts and pop some back, so pops race with steals over the last items, and
+        // the buffer grows while stealers read it.
+        let mut popped = Vec::new();
+        for chunk in (0..ITEMS).collect::<Vec<_>>().chunks(1_000) {
+            for &v in chunk {
+                worker.push(v);
+            }
+            for _ in 0..500 {
+                popped.extend(worker.pop());
+            }
+        }
+        while let Some(v) = worker.pop() {
+            popped.push(v);
+        }
+        let mut all: Vec<_> = stealers.into_iter().map(|h| h.join().unwrap()).collect();
+        all.push(popped);
+        all
+    });
+    let mut all: Vec<_> = stolen.into_iter().flatten().collect();
+    all.sort_unstable();
+    assert_eq!(all, (0..ITEMS).collect::<Vec<_>>());
+}
@@ -0,0 +1,16 @@
+//! Lock-free data structures.
+//!
+//! `atomics_compare_and_exchange.rs` shows a single `compare_exchange` retry loop on a counter.
+//! The structures here run the same loop on pointers, which brings two problems a counter
+//! doesn't have: a thread can't free a node it unlinked while another thread may still be
+//! reading it, and a pointer that was freed and reallocated can make a stale `compare_exchange`
+//! succeed (the ABA problem). Each structure documents how it deals with both.
+
+pub mod deque;
+mod queue;
+mod quiescence;
+pub mod spsc;
+mod stack;
+
+pub use queue::Queue;
+pub use stack::Stack;
@@ -0,0 +1,241 @@
+//! The Michael–Scott queue: a singly linked list with a sentinel node at the head, pushed onto at
+//! the tail and popped from the head, both with `compare_exchange`.
+//!
+//! `head` always points to the sentinel, whose value has already been popped (or never existed);
+//! popping moves `head` to the next node, which becomes the new sentinel, and takes its value.
+//! `tail` points to the last node or, briefly, the one before it: a push first links its node
+//! after the last one and only then swings `tail`, and any thread that finds `tail` lagging
+//! swings it on the pusher's behalf. A pop never moves `head` past `tail`, so `tail` never points
+//! to a retired node.
+//!
+//! Old sentinels are reclaimed through [`Quiescence`]. Pushes dereference the tail node, so they
+//! run in sections too.
+
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::atomic::{AtomicIsize, AtomicPtr, Ordering};
+
+use super::quiescence::{Quiescence, Retire};
+
+pub struct Queue<T> {
+    head: AtomicPtr<Node<T>>,
+    tail: AtomicPtr<Node<T>>,
+    /// Pushes minus pops. Each is counted after the fact, so it can briefly dip below zero.
+    len: AtomicIsize,
+    quiescence: Quiescence<Node<T>>,
+}
+
+struct Node<T> {
+    /// Initialised, until the node is popped and becomes the sentinel.
+    value: MaybeUninit<T>,
+    next: AtomicPtr<Node<T>>,
+    garbage_next: *mut Node<T>,
+}
+
+impl<T> Node<T> {
+    fn new(value: MaybeUninit<T>) -> *mut Self {
+        Box::into_raw(Box::new(Node {
+            value,
+            next: AtomicPtr::new(ptr::null_mut()),
+            garbage_next: ptr::null_mut(),
+        }))
+    }
+}
+
+impl<T> Retire for Node<T> {
+    unsafe fn garbage_next(node: *mut Self) -> *mut *mut Self {
+        ptr::addr_of_mut!((*node).garbage_next)
+    }
+}
+
+// Safety: values are moved in by one thread and out by another, but never shared.
+unsafe impl<T: Send> Send for Queue<T> {}
+unsafe impl<T: Send> Sync for Queue<T> {}
+
+impl<T> Queue<T> {
+    pub fn new() -> Self {
+        let sentinel = Node::new(MaybeUninit::uninit());
+        Self {
+            head: AtomicPtr::new(sentinel),
+            tail: AtomicPtr::new(sentinel),
+            len: AtomicIsize::new(0),
+            quiescence: Quiescence::new(),
+        }
+    }
+
+    pub fn push(&self, value: T) {
+        let node = Node::new(MaybeUninit::new(value));
+        let _section = self.quiescence.enter();
+        // All SeqCst, as `Quiescence` requires. That includes the links between nodes: a pop that
+        // reaches a node through them may take its value.
+        loop {
+            let tail = self.tail.load(Ordering::SeqCst);
+            // Safety: we're in a section, and `tail` never points to a retired node.
+            let next = unsafe { &(*tail).next };
+            let after_tail = next.load(Ordering::SeqCst);
+            if after_tail.is_null() {
+                if next
+                    .compare_exchange(after_tail, node, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+                {
+                    // If this fails, another thread already swung `tail` for us.
+                    self.swing_tail(tail, node);
+                    break;
+                }
+            } else {
+                self.swing_tail(tail, after_tail);
+            }
+        }
+        self.len.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let section = self.quiescence.enter();
+        loop {
+            let head = self.head.load(Ordering::SeqCst);
+            let tail = self.tail.load(Ordering::SeqCst);
+            // Safety: we're in a section, so `head` hasn't been freed, even if another pop has
+            // moved past it since we loaded it.
+            let next = unsafe { (*head).next.load(Ordering::SeqCst) };
+            if next.is_null() {
+                return None;
+            }
+            if head == tail {
+                // The push that linked `next` hasn't swung `tail` yet. Do it for them, so that
+                // `tail` doesn't end up pointing at the node we're about to retire.
+                self.swing_tail(tail, next);
+                continue;
+            }
+            if self
+                .head
+                .compare_exchange(head, next, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+            {
+                // Safety: moving `head` to `next` made it the sentinel, and us the only thread
+                // that takes its value. It can't be retired while we're in our section.
+                let value = unsafe { (*next).value.assume_init_read() };
+                self.len.fetch_sub(1, Ordering::Relaxed);
+                // Safety: we unlinked the old sentinel, whose value was taken long ago.
+                unsafe { section.retire(head) };
+                return Some(value);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let _section = self.quiescence.enter();
+        let head = self.head.load(Ordering::SeqCst);
+        // Safety: we're in a section.
+        unsafe { (*head).next.load(Ordering::SeqCst).is_null() }
+    }
+
+    /// The number of values in the queue, give or take the pushes and pops in progress.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed).max(0) as usize
+    }
+
+    fn swing_tail(&self, tail: *mut Node<T>, next: *mut Node<T>) {
+        let _ = self
+            .tail
+            .compare_exchange(tail, next, Ordering::SeqCst, Ordering::SeqCst);
+    }
+}
+
+impl<T> Default for Queue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> std::fmt::Debug for Queue<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Queue")
+            .field("len", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> Drop for Queue<T> {
+    fn drop(&mut self) {
+        // Safety: `&mut self`, so the queue and its nodes are ours alone. Only the sentinel has
+        // no value.
+        let sentinel = unsafe { Box::from_raw(*self.head.get_mut()) };
+        let mut node = sentinel.next.load(Ordering::Relaxed);
+        while !node.is_null() {
+            let mut boxed = unsafe { Box::from_raw(node) };
+            unsafe { boxed.value.assume_init_drop() };
+            node = *boxed.next.get_mut();
+        }
+        self.quiescence.free_garbage();
+    }
+}
+
+#[test]
+fn queue_is_fifo() {
+    let queue = Queue::new();
+    assert!(queue.is_empty());
+    assert_eq!(queue.pop(), None);
+    for i in 0..3 {
+        queue.push(i.to_string());
+    }
+    assert_eq!(queue.len(), 3);
+    assert_eq!(queue.pop().as_deref(), Some("0"));
+    queue.push("3".into());
+    assert_eq!(queue.pop().as_deref(), Some("1"));
+    assert_eq!(queue.len(), 2);
+    assert!(!queue.is_empty());
+    // The remaining values are dropped with the queue.
+}
+
+#[test]
+fn queue_mpmc_keeps_each_producers_order() {
+    const PRODUCERS: usize = 4;
+    const PER_PRODUCER: usize = 20_000;
+    let queue = Queue::new();
+    let consumed = std::sync::atomic::AtomicUsize::new(0);
+    let popped: Vec<Vec<(usize, usize)>> = std::thread::scope(|s| {
+        for p in 0..PRODUCERS {
+            let queue = &queue;
+            s.spawn(move || {
+                for i in 0..PER_PRODUCER {
+                    queue.push((p, i));
+                }
+            });
+        }
+        let consumers: Vec<_> = (0..PRODUCERS)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut popped = Vec::new();
+                    while consumed.load(Ordering::Relaxed) < PRODUCERS * PER_PRODUCER {
+                        match queue.pop() {
+                            Some(v) => {
+                                popped.push(v);
+                                consumed.fetch_add(1, Ordering::Relaxed);
+                            }
+                            None => std::thread::yield_now(),
+                        }
+                    }
+                    popped
+                })
+            })
+            .collect();
+        consumers.into_iter().map(|c| c.join().unwrap()).collect()
+    });
+
+    // Each consumer sees each producer's values in the order they were pushed.
+    for values in &popped {
+        let mut next = [0; PRODUCERS];
+        for &(p, i) in values {
+            assert!(i >= next[p]);
+            next[p] = i + 1;
+        }
+    }
+    let mut all: Vec<_> = popped.into_iter().flatten().collect();
+    all.sort_unstable();
+    let expected: Vec<_> = (0..PRODUCERS)
+        .flat_map(|p| (0..PER_PRODUCER).map(move |i| (p, i)))
+        .collect();
+    assert_eq!(all, expected);
+    assert!(queue.is_empty());
+    assert_eq!(queue.len(), 0);
+}
@@ -0,0 +1,144 @@
+//! Node reclamation by counting the operations in progress.
+//!
+//! An operation that may dereference shared nodes runs inside a [`Section`]. A node it unlinks is
+//! freed straight away if its section is the only one running, since every later section starts
+//! from pointers past it. Otherwise it goes on a garbage list, and the whole list is freed by the
+//! next section that retires a node while alone. Because a node is never freed while a section
+//! that may have loaded it is running, its address can't be reused under that section either,
+//! which rules out ABA. The price is that under non-stop overlapping operations the garbage list
+//! keeps growing until there is a quiet moment.
+//!
+//! Every operation on `active` and `garbage` is `SeqCst`, and so must be the loads and
+//! `compare_exchange`s of the structure's entry pointers inside a section: the argument above
+//! compares one thread's load of an entry pointer with another's load of `active`, which only a
+//! single total order over both makes meaningful.
+
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+/// A node that can wait on the garbage list.
+pub(super) trait Retire: Sized {
+    /// The node's garbage list link. Only the retiring thread touches it, and nothing else may
+    /// read the field while the node is still reachable.
+    ///
+    /// # Safety
+    ///
+    /// `node` must point to a live node.
+    unsafe fn garbage_next(node: *mut Self) -> *mut *mut Self;
+}
+
+pub(super) struct Quiescence<N> {
+    /// How many sections are running.
+    active: AtomicUsize,
+    /// Unlinked nodes waiting for a moment without other sections.
+    garbage: AtomicPtr<N>,
+}
+
+impl<N: Retire> Quiescence<N> {
+    pub(super) const fn new() -> Self {
+        Self {
+            active: AtomicUsize::new(0),
+            garbage: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Starts an operation. Nodes loaded from the structure during the section stay allocated
+    /// until it ends.
+    pub(super) fn enter(&self) -> Section<'_, N> {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        Section { quiescence: self }
+    }
+
+    /// Frees the garbage list, for the structure's `Drop`.
+    pub(super) fn free_garbage(&mut self) {
+        // Safety: `&mut self` means no section is running.
+        unsafe { free_list(std::mem::replace(self.garbage.get_mut(), ptr::null_mut())) };
+    }
+
+    /// Puts the chain `first..=last` on the garbage list.
+    fn defer(&self, first: *mut N, last: *mut N) {
+        let mut garbage = self.garbage.load(Ordering::SeqCst);
+        loop {
+            // Safety: the chain isn't on the list, so nobody else touches its links.
+            unsafe { *N::garbage_next(last) = garbage };
+            match self.garbage.compare_exchange_weak(
+                garbage,
+                first,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => return,
+                Err(current) => garbage = current,
+            }
+        }
+    }
+}
+
+/// A running operation; ends when dropped.
+pub(super) struct Section<'a, N: Retire> {
+    quiescence: &'a Quiescence<N>,
+}
+
+impl<N: Retire> Section<'_, N> {
+    /// Frees `node` as soon as no other section can be reading it, and ends the section.
+    ///
+    /// # Safety
+    ///
+    /// `node` must come from `Box::into_raw`, and this section must have unlinked it, so that
+    /// sections starting from now on can't reach it. Dropping the box must not drop anything
+    /// that still belongs to someone else.
+    pub(super) unsafe fn retire(self, node: *mut N) {
+        let quiescence = self.quiescence;
+        std::mem::forget(self);
+        if quiescence.active.load(Ordering::SeqCst) != 1 {
+            quiescence.defer(node, node);
+            quiescence.active.fetch_sub(1, Ordering::SeqCst);
+            return;
+        }
+        // Sections that start from here on can't reach anything on the garbage list, but
+        // sections that started before the swap might, so the list is only ours to free if we're
+        // still alone.
+        let garbage = quiescence.garbage.swap(ptr::null_mut(), Ordering::SeqCst);
+        if quiescence.active.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // Safety: no other section is running, and every node on the list was unlinked.
+            unsafe { free_list(garbage) };
+        } else if !garbage.is_null() {
+            // Safety: we took the list, so it's ours to relink.
+            let last = unsafe { last(garbage) };
+            quiescence.defer(garbage, last);
+        }
+        // Safety: we were the only section when we unlinked it, so later ones never loaded it.
+        drop(unsafe { Box::from_raw(node) });
+    }
+}
+
+impl<N: Retire> Drop for Section<'_, N> {
+    fn drop(&mut self) {
+        self.quiescence.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The last node of a garbage chain.
+///
+/// # Safety
+///
+/// `node` must start a valid chain that nobody else is modifying.
+unsafe fn last<N: Retire>(mut node: *mut N) -> *mut N {
+    while !(*N::garbage_next(node)).is_null() {
+        node = *N::garbage_next(node);
+    }
+    node
+}
+
+/// Frees a garbage chain.
+///
+/// # Safety
+///
+/// Nobody else may be able to reach any node of the chain.
+unsafe fn free_list<N: Retire>(mut node: *mut N) {
+    while !node.is_null() {
+        let next = *N::garbage_next(node);
+        drop(Box::from_raw(node));
+        node = next;
+    }
+}
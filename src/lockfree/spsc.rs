@@ -0,0 +1,626 @@
+//! A bounded single-producer single-consumer ring buffer.
+//!
+//! Only the [`Producer`] moves `tail` and only the [`Consumer`] moves `head`, so neither needs a
+//! read-modify-write: `push` and `pop` are a few loads and one store each, and wait-free. The
+//! indices count up forever and wrap around `usize`; the slot is the index masked by the
+//! power-of-two capacity.
+//!
+//! This is `release_acquire_ordering.rs` twice over. The producer writes a slot, then releases
+//! it with its store to `tail`; the consumer acquires `tail` before reading the slot. In the
+//! other direction the consumer releases the slot with its store to `head`, and the producer
+//! acquires `head` before writing over the slot again.
+//!
+//! `head` and `tail` live on separate cache lines, and each side keeps a copy of the other's
+//! index that it only refreshes when that copy shows too little room (or too few items). While
+//! the ring is neither nearly full nor nearly empty, most pushes and pops only touch their own
+//! side's cache line.
+
+use std::alloc::{self, Layout};
+use std::fmt;
+use std::marker::PhantomData;
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::sync::CachePadded;
+
+/// What the ring is made of: the indices and the slots. [`Std`] is atomics and raw memory; the
+/// tests also run the very same ring on the model checker's atomics.
+trait Memory<T> {
+    type Index;
+    type Slots;
+
+    fn index() -> Self::Index;
+    fn load(index: &Self::Index, order: Ordering) -> usize;
+    fn store(index: &Self::Index, value: usize, order: Ordering);
+
+    /// Room for `capacity` items, a power of two.
+    fn slots(capacity: usize) -> Self::Slots;
+
+    /// Writes `value` to slot `i`.
+    ///
+    /// # Safety
+    ///
+    /// `i` must be in bounds, and nobody else may access the slot meanwhile. Whatever was in it
+    /// isn't dropped.
+    unsafe fn write(slots: &Self::Slots, i: usize, value: T);
+
+    /// Moves the item out of slot `i`.
+    ///
+    /// # Safety
+    ///
+    /// As for `write`, and the slot must hold an item, which is afterwards only a copy.
+    unsafe fn read(slots: &Self::Slots, i: usize) -> T;
+
+    /// Drops the item in slot `i` in place.
+    ///
+    /// # Safety
+    ///
+    /// As for `read`.
+    unsafe fn drop_in_place(slots: &Self::Slots, i: usize);
+
+    /// Writes `items` to the slots from `i` on, which must not run past the end.
+    ///
+    /// # Safety
+    ///
+    /// As for `write`, for every slot.
+    unsafe fn write_run(slots: &Self::Slots, i: usize, items: &[T])
+    where
+        T: Copy;
+
+    /// Copies the slots from `i` on into `out`, which must not run past the end.
+    ///
+    /// # Safety
+    ///
+    /// As for `read`, for every slot.
+    unsafe fn read_run(slots: &Self::Slots, i: usize, out: &mut [T])
+    where
+        T: Copy;
+}
+
+/// The ring as it runs outside of tests.
+struct Std;
+
+impl<T> Memory<T> for Std {
+    type Index = AtomicUsize;
+    type Slots = RawVec<T>;
+
+    fn index() -> AtomicUsize {
+        AtomicUsize::new(0)
+    }
+
+    fn load(index: &AtomicUsize, order: Ordering) -> usize {
+        index.load(order)
+    }
+
+    fn store(index: &AtomicUsize, value: usize, order: Ordering) {
+        index.store(value, order);
+    }
+
+    fn slots(capacity: usize) -> RawVec<T> {
+        RawVec::with_capacity(capacity)
+    }
+
+    unsafe fn write(slots: &RawVec<T>, i: usize, value: T) {
+        slots.ptr.as_ptr().add(i).write(value);
+    }
+
+    unsafe fn read(slots: &RawVec<T>, i: usize) -> T {
+        slots.ptr.as_ptr().add(i).read()
+    }
+
+    unsafe fn drop_in_place(slots: &RawVec<T>, i: usize) {
+        ptr::drop_in_place(slots.ptr.as_ptr().add(i));
+    }
+
+    unsafe fn write_run(slots: &RawVec<T>, i: usize, items: &[T])
+    where
+        T: Copy,
+    {
+        ptr::copy_nonoverlapping(items.as_ptr(), slots.ptr.as_ptr().add(i), items.len());
+    }
+
+    unsafe fn read_run(slots: &RawVec<T>, i: usize, out: &mut [T])
+    where
+        T: Copy,
+    {
+        ptr::copy_nonoverlapping(slots.ptr.as_ptr().add(i), out.as_mut_ptr(), out.len());
+    }
+}
+
+/// An uninitialised buffer of `cap` elements, like the one behind `unsafe::vec2::Vec`, but
+/// allocated once with a power-of-two capacity.
+struct RawVec<T> {
+    ptr: NonNull<T>,
+    cap: usize,
+}
+
+impl<T> RawVec<T> {
+    fn with_capacity(cap: usize) -> Self {
+        debug_assert!(cap.is_power_of_two());
+        let layout = Layout::array::<T>(cap).expect("capacity overflow");
+        let ptr = if layout.size() == 0 {
+            NonNull::dangling()
+        } else {
+            // Safety: the layout isn't zero-sized.
+            let ptr = unsafe { alloc::alloc(layout) };
+            NonNull::new(ptr.cast()).unwrap_or_else(|| alloc::handle_alloc_error(layout))
+        };
+        Self { ptr, cap }
+    }
+}
+
+impl<T> Drop for RawVec<T> {
+    fn drop(&mut self) {
+        let layout = Layout::array::<T>(self.cap).unwrap();
+        if layout.size() != 0 {
+            // Safety: allocated in `with_capacity` with this layout.
+            unsafe { alloc::dealloc(self.ptr.as_ptr().cast(), layout) };
+        }
+    }
+}
+
+struct Inner<T, M: Memory<T>> {
+    /// The next slot to pop. Only the consumer stores to it.
+    head: CachePadded<M::Index>,
+    /// The next slot to push to. Only the producer stores to it.
+    tail: CachePadded<M::Index>,
+    slots: M::Slots,
+    capacity: usize,
+    _items: PhantomData<T>,
+}
+
+impl<T, M: Memory<T>> Inner<T, M> {
+    /// The slot of `index`.
+    fn slot(&self, index: usize) -> usize {
+        index & (self.capacity - 1)
+    }
+
+    fn len(&self) -> usize {
+        let tail = M::load(&self.tail, Ordering::Relaxed);
+        let head = M::load(&self.head, Ordering::Relaxed);
+        // Two separate loads; clamped in case `head` moved past the `tail` we saw.
+        tail.wrapping_sub(head).min(self.capacity)
+    }
+}
+
+impl<T, M: Memory<T>> Drop for Inner<T, M> {
+    fn drop(&mut self) {
+        // Both ends are gone, so these loads see the final indices.
+        let head = M::load(&self.head, Ordering::Relaxed);
+        let tail = M::load(&self.tail, Ordering::Relaxed);
+        let mut i = head;
+        while i != tail {
+            // Safety: `head..tail` are the items left.
+            unsafe { M::drop_in_place(&self.slots, self.slot(i)) };
+            i = i.wrapping_add(1);
+        }
+    }
+}
+
+/// The sending end of a [`channel`].
+pub struct Producer<T> {
+    raw: RawProducer<T, Std>,
+}
+
+/// The receiving end of a [`channel`].
+pub struct Consumer<T> {
+    raw: RawConsumer<T, Std>,
+}
+
+/// Creates a ring with room for `capacity` items, rounded up to a power of two.
+///
+/// # Panics
+///
+/// Panics if `capacity` is zero.
+pub fn channel<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let (producer, consumer) = raw_channel(capacity);
+    (Producer { raw: producer }, Consumer { raw: consumer })
+}
+
+impl<T> Producer<T> {
+    /// Pushes `value`, or hands it back if the ring is full.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        self.raw.push(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.raw.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.raw.inner.capacity
+    }
+}
+
+impl<T: Copy> Producer<T> {
+    /// Pushes as many items from the front of `items` as fit, with one `tail` store. Returns
+    /// how many that was.
+    pub fn push_slice(&mut self, items: &[T]) -> usize {
+        self.raw.push_slice(items)
+    }
+}
+
+impl<T> Consumer<T> {
+    pub fn pop(&mut self) -> Option<T> {
+        self.raw.pop()
+    }
+
+    pub fn len(&self) -> usize {
+        self.raw.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.raw.inner.capacity
+    }
+}
+
+impl<T: Copy> Consumer<T> {
+    /// Pops as many items as there are, up to `out.len()`, into the front of `out`, with one
+    /// `head` store. Returns how many that was.
+    pub fn pop_slice(&mut self, out: &mut [T]) -> usize {
+        self.raw.pop_slice(out)
+    }
+}
+
+impl<T> fmt::Debug for Producer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Producer")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Debug for Consumer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Consumer")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The producer's side of the protocol, on any [`Memory`].
+struct RawProducer<T, M: Memory<T>> {
+    inner: Arc<Inner<T, M>>,
+    /// Our own `tail`, which only we change.
+    tail: usize,
+    /// The consumer's `head`, as of the last time we ran short of space.
+    head: usize,
+}
+
+/// The consumer's side of the protocol, on any [`Memory`].
+struct RawConsumer<T, M: Memory<T>> {
+    inner: Arc<Inner<T, M>>,
+    /// Our own `head`, which only we change.
+    head: usize,
+    /// The producer's `tail`, as of the last time we ran short of items.
+    tail: usize,
+}
+
+// Safety: items are moved from the producer's thread to the consumer's, never shared, and the
+// indices are atomics.
+unsafe impl<T: Send, M: Memory<T>> Send for RawProducer<T, M> {}
+unsafe impl<T: Send, M: Memory<T>> Send for RawConsumer<T, M> {}
+
+fn raw_channel<T, M: Memory<T>>(capacity: usize) -> (RawProducer<T, M>, RawConsumer<T, M>) {
+    assert!(capacity > 0, "capacity must be non-zero");
+    let capacity = capacity.next_power_of_two();
+    let inner = Arc::new(Inner {
+        head: CachePadded::new(M::index()),
+        tail: CachePadded::new(M::index()),
+        slots: M::slots(capacity),
+        capacity,
+        _items: PhantomData,
+    });
+    let producer = RawProducer {
+        inner: inner.clone(),
+        tail: 0,
+        head: 0,
+    };
+    let consumer = RawConsumer {
+        inner,
+        head: 0,
+        tail: 0,
+    };
+    (producer, consumer)
+}
+
+impl<T, M: Memory<T>> RawProducer<T, M> {
+    fn push(&mut self, value: T) -> Result<(), T> {
+        if self.free(1) == 0 {
+            return Err(value);
+        }
+        // Safety: the slot is outside `head..tail`, so the consumer is done with it.
+        unsafe { M::write(&self.inner.slots, self.inner.slot(self.tail), value) };
+        self.publish(1);
+        Ok(())
+    }
+
+    fn push_slice(&mut self, items: &[T]) -> usize
+    where
+        T: Copy,
+    {
+        let count = items.len().min(self.free(items.len()));
+        let start = self.inner.slot(self.tail);
+        let first = count.min(self.inner.capacity - start);
+        // Safety: the `count` slots from `tail` are free, in at most two runs around the end of
+        // the buffer. `T: Copy`, so there is nothing to drop.
+        unsafe {
+            M::write_run(&self.inner.slots, start, &items[..first]);
+            M::write_run(&self.inner.slots, 0, &items[first..count]);
+        }
+        self.publish(count);
+        count
+    }
+
+    /// Free slots, refreshing our copy of `head` if it shows fewer than `wanted`.
+    fn free(&mut self, wanted: usize) -> usize {
+        let capacity = self.inner.capacity;
+        if capacity - self.tail.wrapping_sub(self.head) < wanted {
+            // Acquire: the consumer's reads of the slots it freed happen before we overwrite
+            // them.
+            self.head = M::load(&self.inner.head, Ordering::Acquire);
+        }
+        capacity - self.tail.wrapping_sub(self.head)
+    }
+
+    fn publish(&mut self, count: usize) {
+        self.tail = self.tail.wrapping_add(count);
+        // Release: the writes to the new slots happen before the consumer reads them.
+        M::store(&self.inner.tail, self.tail, Ordering::Release);
+    }
+}
+
+impl<T, M: Memory<T>> RawConsumer<T, M> {
+    fn pop(&mut self) -> Option<T> {
+        if self.available(1) == 0 {
+            return None;
+        }
+        // Safety: the slot is in `head..tail`, so the producer wrote it and won't again until we
+        // move `head` past it.
+        let value = unsafe { M::read(&self.inner.slots, self.inner.slot(self.head)) };
+        self.release(1);
+        Some(value)
+    }
+
+    fn pop_slice(&mut self, out: &mut [T]) -> usize
+    where
+        T: Copy,
+    {
+        let count = out.len().min(self.available(out.len()));
+        let start = self.inner.slot(self.head);
+        let first = count.min(self.inner.capacity - start);
+        let (front, back) = out[..count].split_at_mut(first);
+        // Safety: the `count` slots from `head` hold items, in at most two runs.
+        unsafe {
+            M::read_run(&self.inner.slots, start, front);
+            M::read_run(&self.inner.slots, 0, back);
+        }
+        self.release(count);
+        count
+    }
+
+    /// Items ready to pop, refreshing our copy of `tail` if it shows fewer than `wanted`.
+    fn available(&mut self, wanted: usize) -> usize {
+        if self.tail.wrapping_sub(self.head) < wanted {
+            // Acquire: the producer's writes to the slots happen before we read them.
+            self.tail = M::load(&self.inner.tail, Ordering::Acquire);
+        }
+        self.tail.wrapping_sub(self.head)
+    }
+
+    fn release(&mut self, count: usize) {
+        self.head = self.head.wrapping_add(count);
+        // Release: our reads of the slots happen before the producer overwrites them.
+        M::store(&self.inner.head, self.head, Ordering::Release);
+    }
+}
+
+#[test]
+fn spsc_is_fifo_and_bounded() {
+    let (mut tx, mut rx) = channel(3);
+    assert_eq!(tx.capacity(), 4);
+    for i in 0..4 {
+        tx.push(i.to_string()).unwrap();
+    }
+    assert!(tx.is_full());
+    assert_eq!(tx.push("4".to_string()), Err("4".to_string()));
+    assert_eq!(rx.pop().as_deref(), Some("0"));
+    tx.push("4".to_string()).unwrap();
+    assert_eq!(rx.pop().as_deref(), Some("1"));
+    // The rest are dropped with the ring.
+    drop((tx, rx));
+
+    // Slices wrap around the end of the buffer.
+    let (mut tx, mut rx) = channel::<u32>(8);
+    assert_eq!(tx.push_slice(&[0, 1, 2, 3, 4, 5]), 6);
+    let mut out = [0; 4];
+    assert_eq!(rx.pop_slice(&mut out), 4);
+    assert_eq!(out, [0, 1, 2, 3]);
+    assert_eq!(tx.push_slice(&[6, 7, 8, 9, 10, 11, 12]), 6);
+    let mut out = [0; 16];
+    assert_eq!(rx.pop_slice(&mut out), 8);
+    assert_eq!(out[..8], [4, 5, 6, 7, 8, 9, 10, 11]);
+    assert_eq!(rx.pop(), None);
+}
+
+#[test]
+fn spsc_threads_see_items_in_order() {
+    const ITEMS: u64 = 100_000;
+    let (mut tx, mut rx) = channel(64);
+    std::thread::scope(|s| {
+        s.spawn(move || {
+            let mut next = 0;
+            while next < ITEMS {
+                // Alternate single pushes and batches.
+                if next % 2 == 0 {
+                    if tx.push(next).is_ok() {
+                        next += 1;
+                    }
+                } else {
+                    let batch: Vec<u64> = (next..(next + 10).min(ITEMS)).collect();
+                    next += tx.push_slice(&batch) as u64;
+                }
+                std::thread::yield_now();
+            }
+        });
+        let mut expected = 0;
+        let mut out = [0; 16];
+        while expected < ITEMS {
+            let popped = rx.pop_slice(&mut out);
+            for &v in &out[..popped] {
+                assert_eq!(v, expected);
+                expected += 1;
+            }
+            if let Some(v) = rx.pop() {
+                assert_eq!(v, expected);
+                expected += 1;
+            }
+            std::thread::yield_now();
+        }
+    });
+}
+
+/// The ring on the model checker's atomics. The slots are atomics for the checker's sake, but are
+/// only accessed `Relaxed`, like plain memory would be. With `WEAK`, the indices are `Relaxed`
+/// too, which the checker has to notice.
+#[cfg(test)]
+struct Model<const WEAK: bool>;
+
+#[cfg(test)]
+impl<const WEAK: bool> Model<WEAK> {
+    fn order(order: Ordering) -> Ordering {
+        if WEAK {
+            Ordering::Relaxed
+        } else {
+            order
+        }
+    }
+}
+
+#[cfg(test)]
+impl<const WEAK: bool> Memory<u64> for Model<WEAK> {
+    type Index = crate::model::AtomicUsize;
+    type Slots = Box<[crate::model::AtomicU64]>;
+
+    fn index() -> Self::Index {
+        crate::model::AtomicUsize::new(0)
+    }
+
+    fn load(index: &Self::Index, order: Ordering) -> usize {
+        index.load(Self::order(order))
+    }
+
+    fn store(index: &Self::Index, value: usize, order: Ordering) {
+        index.store(value, Self::order(order));
+    }
+
+    fn slots(capacity: usize) -> Self::Slots {
+        (0..capacity)
+            .map(|_| crate::model::AtomicU64::new(0))
+            .collect()
+    }
+
+    unsafe fn write(slots: &Self::Slots, i: usize, value: u64) {
+        slots[i].store(value, Ordering::Relaxed);
+    }
+
+    unsafe fn read(slots: &Self::Slots, i: usize) -> u64 {
+        slots[i].load(Ordering::Relaxed)
+    }
+
+    unsafe fn drop_in_place(_: &Self::Slots, _: usize) {}
+
+    unsafe fn write_run(slots: &Self::Slots, i: usize, items: &[u64]) {
+        for (k, &item) in items.iter().enumerate() {
+            slots[i + k].store(item, Ordering::Relaxed);
+        }
+    }
+
+    unsafe fn read_run(slots: &Self::Slots, i: usize, out: &mut [u64]) {
+        for (k, item) in out.iter_mut().enumerate() {
+            *item = slots[i + k].load(Ordering::Relaxed);
+        }
+    }
+}
+
+/// Three items through a ring of two slots, so that the producer wraps around, one at a time or
+/// in batches.
+#[cfg(test)]
+fn model_ring<M: Memory<u64> + 'static>(batches: bool)
+where
+    M::Index: Send + Sync,
+    M::Slots: Send + Sync,
+{
+    use crate::model::thread;
+
+    const ITEMS: [u64; 3] = [1, 2, 3];
+    let (mut tx, mut rx) = raw_channel::<u64, M>(2);
+    let producer = thread::spawn(move || {
+        let mut sent = 0;
+        while sent < ITEMS.len() {
+            let pushed = if batches {
+                tx.push_slice(&ITEMS[sent..])
+            } else {
+                usize::from(tx.push(ITEMS[sent]).is_ok())
+            };
+            if pushed == 0 {
+                thread::yield_now();
+            }
+            sent += pushed;
+        }
+    });
+    let mut out = [0; 3];
+    let mut received = 0;
+    while received < ITEMS.len() {
+        let popped = if batches {
+            rx.pop_slice(&mut out[received..])
+        } else {
+            match rx.pop() {
+                Some(v) => {
+                    out[received] = v;
+                    1
+                }
+                None => 0,
+            }
+        };
+        if popped == 0 {
+            thread::yield_now();
+        }
+        received += popped;
+    }
+    assert_eq!(out, ITEMS, "popped a stale slot");
+    producer.join().unwrap();
+}
+
+#[test]
+fn model_spsc_release_acquire() {
+    use std::panic::{self, AssertUnwindSafe};
+
+    for batches in [false, true] {
+        crate::model::check(move || model_ring::<Model<false>>(batches));
+        let failure = panic::catch_unwind(AssertUnwindSafe(|| {
+            crate::model::check(move || model_ring::<Model<true>>(batches))
+        }))
+        .expect_err("Relaxed indices let the consumer read a slot before it was written");
+        let message = failure
+            .downcast_ref::<String>()
+            .cloned()
+            .unwrap_or_default();
+        assert!(message.contains("popped a stale slot"), "{message}");
+    }
+}
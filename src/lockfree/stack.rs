@@ -0,0 +1,200 @@
+//! A Treiber stack: a singly linked list whose head is swapped in and out with
+//! `compare_exchange`.
+//!
+//! Popped nodes are reclaimed through [`Quiescence`], which also rules out ABA: a node can't be
+//! freed, and its address reused, while a pop that loaded it is still running.
+
+use std::mem::ManuallyDrop;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use super::quiescence::{Quiescence, Retire};
+
+pub struct Stack<T> {
+    head: AtomicPtr<Node<T>>,
+    /// Pops run in sections; pushes never dereference a node they didn't allocate.
+    quiescence: Quiescence<Node<T>>,
+}
+
+struct Node<T> {
+    /// Taken by the thread that pops the node.
+    value: ManuallyDrop<T>,
+    /// Written before the node is pushed and never again: other pops may be reading it.
+    next: *mut Node<T>,
+    garbage_next: *mut Node<T>,
+}
+
+impl<T> Retire for Node<T> {
+    unsafe fn garbage_next(node: *mut Self) -> *mut *mut Self {
+        ptr::addr_of_mut!((*node).garbage_next)
+    }
+}
+
+// Safety: values are moved in by one thread and out by another, but never shared.
+unsafe impl<T: Send> Send for Stack<T> {}
+unsafe impl<T: Send> Sync for Stack<T> {}
+
+impl<T> Stack<T> {
+    pub const fn new() -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+            quiescence: Quiescence::new(),
+        }
+    }
+
+    pub fn push(&self, value: T) {
+        let node = Box::into_raw(Box::new(Node {
+            value: ManuallyDrop::new(value),
+            next: ptr::null_mut(),
+            garbage_next: ptr::null_mut(),
+        }));
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            // Safety: the node isn't published yet, so it's still ours.
+            unsafe { (*node).next = head };
+            // Release: a pop that takes the node sees its value and `next`.
+            match self
+                .head
+                .compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let section = self.quiescence.enter();
+        // SeqCst, as `Quiescence` requires.
+        let mut head = self.head.load(Ordering::SeqCst);
+        let node = loop {
+            if head.is_null() {
+                return None;
+            }
+            // Safety: we're in a section, so `head` hasn't been freed, even if another pop has
+            // taken it since we loaded it.
+            let next = unsafe { (*head).next };
+            match self
+                .head
+                .compare_exchange_weak(head, next, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => break head,
+                Err(current) => head = current,
+            }
+        };
+        // Safety: we unlinked the node, so its value is ours; other pops only read `next`.
+        let value = unsafe { ManuallyDrop::take(&mut (*node).value) };
+        // Safety: we unlinked it, and its value is gone.
+        unsafe { section.retire(node) };
+        Some(value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Relaxed).is_null()
+    }
+}
+
+impl<T> Default for Stack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> std::fmt::Debug for Stack<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Stack")
+            .field("is_empty", &self.is_empty())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> Drop for Stack<T> {
+    fn drop(&mut self) {
+        let mut node = *self.head.get_mut();
+        while !node.is_null() {
+            // Safety: `&mut self`, so the stack and its nodes are ours alone.
+            let mut boxed = unsafe { Box::from_raw(node) };
+            unsafe { ManuallyDrop::drop(&mut boxed.value) };
+            node = boxed.next;
+        }
+        self.quiescence.free_garbage();
+    }
+}
+
+#[test]
+fn stack_is_lifo() {
+    let stack = Stack::new();
+    assert!(stack.is_empty());
+    assert_eq!(stack.pop(), None);
+    for i in 0..3 {
+        stack.push(i.to_string());
+    }
+    assert_eq!(stack.pop().as_deref(), Some("2"));
+    stack.push("3".into());
+    assert_eq!(stack.pop().as_deref(), Some("3"));
+    assert_eq!(stack.pop().as_deref(), Some("1"));
+    // The remaining value is dropped with the stack.
+    stack.push("4".into());
+}
+
+#[test]
+fn stack_loses_and_duplicates_nothing_under_contention() {
+    const THREADS: usize = 4;
+    const PER_THREAD: usize = 20_000;
+    let stack = Stack::new();
+    let popped: Vec<Vec<usize>> = std::thread::scope(|s| {
+        for t in 0..THREADS {
+            let stack = &stack;
+            s.spawn(move || {
+                for i in 0..PER_THREAD {
+                    stack.push(t * PER_THREAD + i);
+                }
+            });
+        }
+        let poppers: Vec<_> = (0..THREADS)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut popped = Vec::new();
+                    for _ in 0..PER_THREAD {
+                        // Popping interleaved with pushes, and with each other, keeps the garbage
+                        // list and the ABA window busy.
+                        popped.extend(stack.pop());
+                        popped.extend(stack.pop());
+                        std::thread::yield_now();
+                    }
+                    popped
+                })
+            })
+            .collect();
+        poppers.into_iter().map(|p| p.join().unwrap()).collect()
+    });
+
+    let mut all: Vec<usize> = popped.into_iter().flatten().collect();
+    while let Some(v) = stack.pop() {
+        all.push(v);
+    }
+    all.sort_unstable();
+    assert_eq!(all, (0..THREADS * PER_THREAD).collect::<Vec<_>>());
+}
+
+#[test]
+fn stack_keeps_each_pushers_order() {
+    // A single popper sees every pusher's values newest first: pushes are linearizable.
+    let stack = Stack::new();
+    std::thread::scope(|s| {
+        for t in 0..2 {
+            let stack = &stack;
+            s.spawn(move || {
+                for i in 0..10_000 {
+                    stack.push((t, i));
+                }
+            });
+        }
+    });
+    let mut last = [usize::MAX; 2];
+    while let Some((t, i)) = stack.pop() {
+        assert!(i < last[t]);
+        last[t] = i;
+    }
+    assert_eq!(last, [0, 0]);
+}
@@ -0,0 +1,165 @@
+//! Shadow atomics. They have the same methods as their `std::sync::atomic` namesakes, but every
+//! operation goes through the checker, which decides when it runs and which value a load sees.
+
+use std::sync::atomic::Ordering;
+
+use super::rt::{self, Location};
+
+#[derive(Debug)]
+pub struct AtomicBool {
+    location: Location,
+}
+
+impl AtomicBool {
+    pub fn new(v: bool) -> Self {
+        Self {
+            location: Location::new(v as u64),
+        }
+    }
+
+    pub fn load(&self, order: Ordering) -> bool {
+        rt::load(&self.location, order) != 0
+    }
+
+    pub fn store(&self, v: bool, order: Ordering) {
+        rt::store(&self.location, v as u64, order);
+    }
+
+    pub fn swap(&self, v: bool, order: Ordering) -> bool {
+        self.update(order, order, |_| Some(v)).unwrap()
+    }
+
+    pub fn compare_exchange(
+        &self,
+        current: bool,
+        new: bool,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<bool, bool> {
+        self.update(success, failure, |v| (v == current).then_some(new))
+    }
+
+    /// Never fails spuriously; the checker doesn't model that.
+    pub fn compare_exchange_weak(
+        &self,
+        current: bool,
+        new: bool,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<bool, bool> {
+        self.compare_exchange(current, new, success, failure)
+    }
+
+    pub fn fetch_or(&self, v: bool, order: Ordering) -> bool {
+        self.update(order, order, |old| Some(old | v)).unwrap()
+    }
+
+    pub fn fetch_and(&self, v: bool, order: Ordering) -> bool {
+        self.update(order, order, |old| Some(old & v)).unwrap()
+    }
+
+    fn update(
+        &self,
+        success: Ordering,
+        failure: Ordering,
+        f: impl FnOnce(bool) -> Option<bool>,
+    ) -> Result<bool, bool> {
+        rt::rmw(&self.location, success, failure, |v| {
+            f(v != 0).map(|v| v as u64)
+        })
+        .map(|v| v != 0)
+        .map_err(|v| v != 0)
+    }
+}
+
+macro_rules! model_atomic_int {
+    ($($atomic:ident: $int:ty),* $(,)?) => {$(
+        #[derive(Debug)]
+        pub struct $atomic {
+            location: Location,
+        }
+
+        impl $atomic {
+            pub fn new(v: $int) -> Self {
+                Self {
+                    location: Location::new(v as u64),
+                }
+            }
+
+            pub fn load(&self, order: Ordering) -> $int {
+                rt::load(&self.location, order) as $int
+            }
+
+            pub fn store(&self, v: $int, order: Ordering) {
+                rt::store(&self.location, v as u64, order);
+            }
+
+            pub fn swap(&self, v: $int, order: Ordering) -> $int {
+                self.update(order, order, |_| Some(v)).unwrap()
+            }
+
+            pub fn compare_exchange(
+                &self,
+                current: $int,
+                new: $int,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<$int, $int> {
+                self.update(success, failure, |v| (v == current).then_some(new))
+            }
+
+            /// Never fails spuriously; the checker doesn't model that.
+            pub fn compare_exchange_weak(
+                &self,
+                current: $int,
+                new: $int,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<$int, $int> {
+                self.compare_exchange(current, new, success, failure)
+            }
+
+            pub fn fetch_add(&self, v: $int, order: Ordering) -> $int {
+                self.update(order, order, |old| Some(old.wrapping_add(v))).unwrap()
+            }
+
+            pub fn fetch_sub(&self, v: $int, order: Ordering) -> $int {
+                self.update(order, order, |old| Some(old.wrapping_sub(v))).unwrap()
+            }
+
+            pub fn fetch_max(&self, v: $int, order: Ordering) -> $int {
+                self.update(order, order, |old| Some(old.max(v))).unwrap()
+            }
+
+            pub fn fetch_min(&self, v: $int, order: Ordering) -> $int {
+                self.update(order, order, |old| Some(old.min(v))).unwrap()
+            }
+
+            fn update(
+                &self,
+                success: Ordering,
+                failure: Ordering,
+                f: impl FnOnce($int) -> Option<$int>,
+            ) -> Result<$int, $int> {
+                rt::rmw(&self.location, success, failure, |v| {
+                    f(v as $int).map(|v| v as u64)
+                })
+                .map(|v| v as $int)
+                .map_err(|v| v as $int)
+            }
+        }
+    )*};
+}
+
+model_atomic_int! {
+    AtomicU8: u8,
+    AtomicU32: u32,
+    AtomicU64: u64,
+    AtomicUsize: usize,
+    AtomicI32: i32,
+}
+
+/// A memory fence. `SeqCst` fences are ordered with each other in the order they run.
+pub fn fence(order: Ordering) {
+    rt::fence(order);
+}
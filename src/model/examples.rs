@@ -0,0 +1,216 @@
+//! The `atomics_locks` experiments, as model tests. Where an experiment relies on an ordering,
+//! the test also checks that weakening it gets caught.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::Ordering::{self, Acquire, Relaxed, Release, SeqCst};
+use std::sync::Arc;
+
+use super::{check, check_with, fence, replay, thread, AtomicBool, AtomicU32, AtomicU64, Config};
+
+/// Runs `check(f)`, which must fail, and returns the failure message.
+fn check_fails(f: impl Fn() + Send + Sync + 'static) -> String {
+    let payload = panic::catch_unwind(AssertUnwindSafe(|| check(f)))
+        .expect_err("the checker missed a failing execution");
+    payload
+        .downcast_ref::<String>()
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// `sequentially_consistent_ordering.rs`: each thread sets its flag and checks the other's. At
+/// most one thread may see the other's flag still unset.
+fn store_buffering(store: Ordering, load: Ordering) {
+    let a = Arc::new(AtomicBool::new(false));
+    let b = Arc::new(AtomicBool::new(false));
+    let ta = thread::spawn({
+        let (a, b) = (a.clone(), b.clone());
+        move || {
+            a.store(true, store);
+            !b.load(load)
+        }
+    });
+    let tb = thread::spawn({
+        let (a, b) = (a.clone(), b.clone());
+        move || {
+            b.store(true, store);
+            !a.load(load)
+        }
+    });
+    let pushed = [ta.join().unwrap(), tb.join().unwrap()];
+    assert!(pushed != [true, true], "both threads pushed");
+}
+
+#[test]
+fn model_seq_cst_store_buffering() {
+    check(|| store_buffering(SeqCst, SeqCst));
+    let message = check_fails(|| store_buffering(Release, Acquire));
+    assert!(message.contains("both threads pushed"), "{message}");
+    // `SeqCst` loads alone don't help: neither store is in the total order, so both loads may
+    // still read `false`.
+    let message = check_fails(|| store_buffering(Relaxed, SeqCst));
+    assert!(message.contains("both threads pushed"), "{message}");
+}
+
+/// `release_acquire_ordering.rs`, `atomic_data()`: the data is written before the flag is
+/// released, so whoever acquires the flag sees the data.
+fn message_passing(store: Ordering, load: Ordering) {
+    let data = Arc::new(AtomicU64::new(0));
+    let ready = Arc::new(AtomicBool::new(false));
+    thread::spawn({
+        let (data, ready) = (data.clone(), ready.clone());
+        move || {
+            data.store(42, Relaxed);
+            ready.store(true, store);
+        }
+    });
+    while !ready.load(load) {
+        thread::yield_now();
+    }
+    assert_eq!(data.load(Relaxed), 42);
+}
+
+#[test]
+fn model_release_acquire_message_passing() {
+    check(|| message_passing(Release, Acquire));
+    check_fails(|| message_passing(Relaxed, Relaxed));
+}
+
+#[test]
+fn model_failures_replay() {
+    let message = check_fails(|| message_passing(Relaxed, Relaxed));
+    let trace = message
+        .split("model::replay(\"")
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .expect("no trace in the failure message")
+        .to_string();
+    let replayed = panic::catch_unwind(|| replay(&trace, || message_passing(Relaxed, Relaxed)))
+        .expect_err("the replay didn't fail");
+    let replayed = replayed.downcast_ref::<String>().unwrap();
+    assert_eq!(replayed.lines().last(), message.lines().last());
+    // The acquiring version takes the same thread choices and still passes.
+    replay(&trace, || message_passing(Release, Acquire));
+}
+
+/// `fences.rs`: the flag is read relaxed, and an acquire fence after it pairs with the release
+/// store of the flag.
+#[test]
+fn model_acquire_fence() {
+    check(|| {
+        let data = Arc::new(AtomicU64::new(0));
+        let ready = Arc::new(AtomicBool::new(false));
+        thread::spawn({
+            let (data, ready) = (data.clone(), ready.clone());
+            move || {
+                data.store(7, Relaxed);
+                ready.store(true, Release);
+            }
+        });
+        if ready.load(Relaxed) {
+            fence(Acquire);
+            assert_eq!(data.load(Relaxed), 7);
+        }
+    });
+}
+
+/// `increment()` from `atomics_compare_and_exchange_id_alloc.rs`: a compare-and-exchange loop
+/// never loses an increment, while a separate load and store does.
+#[test]
+fn model_compare_exchange_increment() {
+    fn increment(a: &AtomicU32) {
+        let mut old = a.load(Relaxed);
+        loop {
+            match a.compare_exchange(old, old + 1, Relaxed, Relaxed) {
+                Ok(_) => return,
+                Err(x) => old = x,
+            }
+        }
+    }
+    fn racy_increment(a: &AtomicU32) {
+        a.store(a.load(Relaxed) + 1, Relaxed);
+    }
+    fn two_increments(increment: fn(&AtomicU32)) {
+        let a = Arc::new(AtomicU32::new(0));
+        let t = thread::spawn({
+            let a = a.clone();
+            move || increment(&a)
+        });
+        increment(&a);
+        t.join().unwrap();
+        assert_eq!(a.load(Relaxed), 2, "lost an increment");
+    }
+
+    check(|| two_increments(increment));
+    let message = check_fails(|| two_increments(racy_increment));
+    assert!(message.contains("lost an increment"), "{message}");
+}
+
+/// `example_load_store_stop_flag()` from `atomics.rs` spins on its flag; without a yield the
+/// checker can't tell that loop from an infinite one, and a flag nobody sets is a livelock.
+#[test]
+fn model_spinning_forever_is_a_livelock() {
+    let message = check_fails(|| {
+        let stop = AtomicBool::new(false);
+        while !stop.load(Relaxed) {
+            thread::yield_now();
+        }
+    });
+    assert!(message.contains("livelock"), "{message}");
+}
+
+#[test]
+fn model_dpor_skips_independent_interleavings() {
+    let independent = || {
+        let (x, y) = (Arc::new(AtomicU32::new(0)), Arc::new(AtomicU32::new(0)));
+        let t = thread::spawn({
+            let x = x.clone();
+            move || {
+                x.store(1, Relaxed);
+                x.store(2, Relaxed);
+            }
+        });
+        y.store(1, Relaxed);
+        y.store(2, Relaxed);
+        t.join().unwrap();
+    };
+    let all = check_with(
+        &Config {
+            dpor: false,
+            ..Config::default()
+        },
+        independent,
+    );
+    assert!(all > 1);
+    assert_eq!(check(independent), 1);
+}
+
+/// A spinning thread that read a stale value and yielded isn't stuck: its next load reads the
+/// newest store. Here `t1` reads `x` before `main`'s store reaches it and parks, then `t2` spins
+/// on `y`, which only `t1` sets. Nobody stores again, yet `t1` still gets to run.
+#[test]
+fn model_stale_reader_is_not_a_livelock() {
+    check(|| {
+        let x = Arc::new(AtomicBool::new(false));
+        let y = Arc::new(AtomicBool::new(false));
+        let t1 = thread::spawn({
+            let (x, y) = (x.clone(), y.clone());
+            move || {
+                while !x.load(Relaxed) {
+                    thread::yield_now();
+                }
+                y.store(true, Relaxed);
+            }
+        });
+        let t2 = thread::spawn({
+            let y = y.clone();
+            move || {
+                while !y.load(Relaxed) {
+                    thread::yield_now();
+                }
+            }
+        });
+        x.store(true, Relaxed);
+        t1.join().unwrap();
+        t2.join().unwrap();
+    });
+}
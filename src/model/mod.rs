@@ -0,0 +1,138 @@
+//! An exhaustive model checker for small programs built on atomics.
+//!
+//! Running `sequentially_consistent_ordering.rs` on x86 will never show weak-memory behaviour,
+//! and running it on ARM only shows it now and then. [`check`] instead runs a closure over and
+//! over, once for every way its threads can interleave, and for every older store a load is still
+//! allowed to read. If any of those
+//! executions panics, `check` panics with the events that led there and a trace that
+//! [`replay`] turns back into exactly that execution.
+//!
+//! Inside the closure, use the shadow types from this module instead of `std`'s:
+//! [`AtomicBool`] and friends, [`fence`], [`thread::spawn`] and [`thread::yield_now`] (which every
+//! spin loop needs, or the checker would spin with it). Plain `std` types that don't synchronise,
+//! such as `Arc`, are fine.
+//!
+//! Two simplifications keep the search finite and small: stores join a location's modification
+//! order in the order they run, and read-modify-write operations (including a failing
+//! `compare_exchange`) always read the newest value. A load also only ever reads stores that have
+//! already run, so the checker never produces load buffering (both threads reading the other's
+//! later store), which C++ allows for `Relaxed` operations; code that is only wrong because of
+//! it passes. Thread choices are pruned with dynamic
+//! partial-order reduction (DPOR): the order of two operations is only varied if they touch the
+//! same location, at least one writes, and neither happens before the other.
+
+mod atomic;
+#[cfg(test)]
+mod examples;
+mod path;
+mod rt;
+pub mod thread;
+
+use std::fmt;
+use std::sync::Arc;
+
+pub use atomic::{fence, AtomicBool, AtomicI32, AtomicU32, AtomicU64, AtomicU8, AtomicUsize};
+use path::Path;
+
+pub mod hint {
+    /// The model version of `std::hint::spin_loop`, which is [`yield_now`](super::thread::yield_now).
+    pub fn spin_loop() {
+        super::thread::yield_now();
+    }
+}
+
+/// Limits for [`check_with`].
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Stop after this many executions, even if there are more to explore.
+    pub max_executions: Option<usize>,
+    /// Only explore executions that switch away from a runnable thread at most this many times.
+    pub preemption_bound: Option<usize>,
+    /// Fail an execution that takes more scheduling steps than this.
+    pub max_steps: usize,
+    /// Prune equivalent interleavings. Turning this off explores every interleaving.
+    pub dpor: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_executions: None,
+            preemption_bound: None,
+            max_steps: 10_000,
+            dpor: true,
+        }
+    }
+}
+
+/// Runs `f` in every execution the default [`Config`] allows. Returns how many there were.
+///
+/// # Panics
+///
+/// Panics if any execution panics, deadlocks or livelocks.
+pub fn check(f: impl Fn() + Send + Sync + 'static) -> usize {
+    check_with(&Config::default(), f)
+}
+
+pub fn check_with(config: &Config, f: impl Fn() + Send + Sync + 'static) -> usize {
+    let f: Arc<dyn Fn() + Send + Sync> = Arc::new(f);
+    let mut path = Path::new();
+    let mut executions = 0;
+    loop {
+        executions += 1;
+        let (explored, failure) = rt::execute(config, path, f.clone());
+        if let Some(failure) = failure {
+            panic!("{}", Report::new(executions, failure));
+        }
+        path = explored;
+        if !path.step() || config.max_executions.is_some_and(|max| executions >= max) {
+            return executions;
+        }
+    }
+}
+
+/// Runs `f` once, making the choices recorded in `trace` (from the message of a failed
+/// [`check`]). Handy for stepping through a failure in a debugger.
+///
+/// # Panics
+///
+/// Panics like [`check`] if the execution fails, and if `trace` doesn't fit `f`.
+pub fn replay(trace: &str, f: impl Fn() + Send + Sync + 'static) {
+    let path = Path::from_trace(trace).unwrap_or_else(|e| panic!("model::replay: {e}"));
+    let (_, failure) = rt::execute(&Config::default(), path, Arc::new(f));
+    if let Some(failure) = failure {
+        panic!("{}", Report::new(1, failure));
+    }
+}
+
+struct Report {
+    executions: usize,
+    failure: rt::Failure,
+}
+
+impl Report {
+    fn new(executions: usize, failure: rt::Failure) -> Self {
+        Self {
+            executions,
+            failure,
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "model: execution {} failed: {}",
+            self.executions, self.failure.message
+        )?;
+        for event in &self.failure.events {
+            writeln!(f, "  {event}")?;
+        }
+        write!(
+            f,
+            "replay with model::replay(\"{}\", ..)",
+            self.failure.trace
+        )
+    }
+}
@@ -0,0 +1,202 @@
+//! The tree of choices explored by [`check`](super::check), walked depth first.
+//!
+//! Every execution makes a sequence of choices: which thread runs next, and which store a load
+//! reads from. The path remembers the choices of the current execution. The next execution
+//! replays a prefix of them and then takes a different alternative at the deepest choice that
+//! still has one left.
+
+use std::collections::BTreeSet;
+
+#[derive(Debug, Default)]
+pub(super) struct Path {
+    branches: Vec<Branch>,
+    /// Index of the next choice in the current execution.
+    pos: usize,
+    /// Choices to take once the recorded branches run out, from a trace given to `replay`.
+    forced: Vec<usize>,
+}
+
+#[derive(Debug)]
+enum Branch {
+    Thread {
+        enabled: Vec<usize>,
+        /// The thread that was running, if it could have kept running.
+        current: Option<usize>,
+        /// Whether switching away from `current` here stays within the preemption bound.
+        can_preempt: bool,
+        chosen: usize,
+        /// Threads that still need to be tried here. Without DPOR, all of `enabled`.
+        backtrack: BTreeSet<usize>,
+        done: BTreeSet<usize>,
+    },
+    /// Stores are numbered from the oldest one the load may read; the newest is tried first.
+    Read { options: usize, chosen: usize },
+}
+
+const NONDETERMINISTIC: &str = "the model closure is not deterministic: replaying an earlier \
+     execution led to different choices (is it reading real time, randomness, or non-model \
+     atomics?)";
+
+impl Path {
+    pub(super) fn new() -> Self {
+        Self::default()
+    }
+
+    /// A path that makes the choices listed in `trace`, as printed by a failed
+    /// [`check`](super::check).
+    pub(super) fn from_trace(trace: &str) -> Result<Self, String> {
+        let forced = trace
+            .split(',')
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| {
+                s.trim()
+                    .parse()
+                    .map_err(|_| format!("malformed trace step {s:?}"))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            forced,
+            ..Self::default()
+        })
+    }
+
+    /// Index of the branch the most recent choice was recorded in.
+    pub(super) fn last(&self) -> usize {
+        self.pos - 1
+    }
+
+    pub(super) fn choose_thread(
+        &mut self,
+        enabled: &[usize],
+        current: Option<usize>,
+        can_preempt: bool,
+        dpor: bool,
+    ) -> Result<usize, String> {
+        if let Some(branch) = self.branches.get(self.pos) {
+            let Branch::Thread {
+                enabled: recorded,
+                chosen,
+                ..
+            } = branch
+            else {
+                return Err(NONDETERMINISTIC.into());
+            };
+            if recorded != enabled {
+                return Err(NONDETERMINISTIC.into());
+            }
+            self.pos += 1;
+            return Ok(*chosen);
+        }
+        let allowed = |t: usize| can_preempt || current.is_none_or(|c| c == t);
+        let chosen = match self.forced.get(self.pos) {
+            Some(&t) if enabled.contains(&t) => t,
+            Some(&t) => return Err(format!("trace step {}: t{t} can't run here", self.pos)),
+            None => current.unwrap_or(enabled[0]),
+        };
+        let backtrack = if dpor {
+            BTreeSet::from([chosen])
+        } else {
+            enabled.iter().copied().filter(|&t| allowed(t)).collect()
+        };
+        self.branches.push(Branch::Thread {
+            enabled: enabled.to_vec(),
+            current,
+            can_preempt,
+            chosen,
+            backtrack,
+            done: BTreeSet::from([chosen]),
+        });
+        self.pos += 1;
+        Ok(chosen)
+    }
+
+    /// Picks one of `options` stores, numbered oldest first.
+    pub(super) fn choose_read(&mut self, options: usize) -> Result<usize, String> {
+        if let Some(branch) = self.branches.get(self.pos) {
+            let Branch::Read {
+                options: recorded,
+                chosen,
+            } = *branch
+            else {
+                return Err(NONDETERMINISTIC.into());
+            };
+            if recorded != options {
+                return Err(NONDETERMINISTIC.into());
+            }
+            self.pos += 1;
+            return Ok(chosen);
+        }
+        let chosen = match self.forced.get(self.pos) {
+            Some(&i) if i < options => i,
+            Some(&i) => return Err(format!("trace step {}: no store {i} to read", self.pos)),
+            None => options - 1,
+        };
+        self.branches.push(Branch::Read { options, chosen });
+        self.pos += 1;
+        Ok(chosen)
+    }
+
+    /// Asks for `thread` to also be tried at thread choice `branch`. If it couldn't run there,
+    /// every thread that could is tried instead.
+    pub(super) fn add_backtrack(&mut self, branch: usize, thread: usize) {
+        let Some(Branch::Thread {
+            enabled,
+            current,
+            can_preempt,
+            backtrack,
+            ..
+        }) = self.branches.get_mut(branch)
+        else {
+            return;
+        };
+        let allowed = |t: usize| *can_preempt || current.is_none_or(|c| c == t);
+        if enabled.contains(&thread) {
+            if allowed(thread) {
+                backtrack.insert(thread);
+            }
+        } else {
+            backtrack.extend(enabled.iter().copied().filter(|&t| allowed(t)));
+        }
+    }
+
+    /// Moves on to the next execution. Returns `false` once every alternative has been tried.
+    pub(super) fn step(&mut self) -> bool {
+        self.pos = 0;
+        self.forced.clear();
+        while let Some(branch) = self.branches.last_mut() {
+            match branch {
+                Branch::Read { chosen, .. } if *chosen > 0 => {
+                    *chosen -= 1;
+                    return true;
+                }
+                Branch::Thread {
+                    chosen,
+                    backtrack,
+                    done,
+                    ..
+                } => {
+                    if let Some(&t) = backtrack.difference(done).next() {
+                        *chosen = t;
+                        done.insert(t);
+                        return true;
+                    }
+                }
+                Branch::Read { .. } => {}
+            }
+            self.branches.pop();
+        }
+        false
+    }
+
+    /// The choices made so far in the current execution, in the format [`Path::from_trace`]
+    /// reads.
+    pub(super) fn trace(&self) -> String {
+        let choices: Vec<String> = self.branches[..self.pos]
+            .iter()
+            .map(|b| match b {
+                Branch::Thread { chosen, .. } | Branch::Read { chosen, .. } => chosen.to_string(),
+            })
+            .collect();
+        choices.join(",")
+    }
+}
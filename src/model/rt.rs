@@ -0,0 +1,700 @@
+//! The runtime behind the shadow types: one [`Execution`] at a time, with the model threads
+//! running on real OS threads that take turns.
+//!
+//! Only the thread whose id is `active` may run; every other model thread is blocked on the
+//! `turn` condition variable. Before each atomic operation (and when a thread blocks or finishes)
+//! the active thread asks the [`Path`] which thread runs next and hands over the turn. That makes
+//! every operation a potential preemption point, and nothing in between is observable by other
+//! threads.
+//!
+//! Each location keeps all of its stores in modification order, together with the vector clock
+//! of the storing thread. A load may read any store that isn't hidden from it by coherence or by
+//! a newer store that happens before it; which one is another choice on the path. A `SeqCst` load
+//! is also hidden from everything before the location's newest `SeqCst` store, since that store
+//! precedes it in the single total order of `SeqCst` operations.
+
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{self, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::JoinHandle;
+
+use super::path::Path;
+use super::Config;
+use crate::clock::VectorClock;
+
+/// Unwinding payload that tears down the remaining threads of a failed execution.
+pub(super) struct Abort;
+
+/// Why an execution failed, and how it got there.
+#[derive(Debug)]
+pub(super) struct Failure {
+    pub(super) message: String,
+    pub(super) trace: String,
+    pub(super) events: Vec<String>,
+}
+
+/// A handle to an atomic location of the execution that created it.
+#[derive(Debug)]
+pub(super) struct Location {
+    execution: u64,
+    index: usize,
+}
+
+pub(super) struct Shared {
+    exec: Mutex<Execution>,
+    turn: Condvar,
+}
+
+struct Execution {
+    id: u64,
+    config: Config,
+    path: Path,
+    threads: Vec<Thread>,
+    locations: Vec<Vec<Store>>,
+    active: usize,
+    steps: usize,
+    preemptions: usize,
+    /// Index of the path branch that scheduled the running operation.
+    branch: usize,
+    /// The clock of the last `SeqCst` fence; `SeqCst` fences synchronise with each other in the
+    /// order they run, which is a little stronger than C++ requires.
+    sc_fences: VectorClock,
+    accesses: Vec<Access>,
+    /// How many stores the execution has made so far.
+    stores: usize,
+    events: Vec<String>,
+    failure: Option<Failure>,
+    aborted: bool,
+    finished: bool,
+    os_threads: Vec<JoinHandle<()>>,
+}
+
+struct Thread {
+    status: Status,
+    clock: VectorClock,
+    /// Release clocks of the stores read by relaxed loads, for a later `Acquire` fence.
+    acquire_fence: VectorClock,
+    /// The clock at the last `Release` fence, released by later relaxed stores.
+    release_fence: Option<VectorClock>,
+    /// Per location, the newest store this thread has read or written.
+    coherence: Vec<usize>,
+    /// Set by `yield_now`: the next load reads the newest store, so spin loops make progress.
+    fresh_read: bool,
+    /// The execution's store count when the thread last yielded.
+    yielded_at: Option<usize>,
+    /// The execution's store count at the thread's last fresh read.
+    fresh_at: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Runnable,
+    /// Spinning; runnable again once another thread stores something or finishes.
+    Yielded,
+    Joining(usize),
+    Finished,
+}
+
+struct Store {
+    value: u64,
+    thread: usize,
+    time: u32,
+    /// What an acquire load of this store synchronises with, if anything.
+    sync: Option<VectorClock>,
+    seq_cst: bool,
+}
+
+/// A memory access, remembered for DPOR.
+struct Access {
+    thread: usize,
+    location: usize,
+    write: bool,
+    time: u32,
+    branch: usize,
+}
+
+/// A pseudo-location for `SeqCst` fences, which conflict with each other.
+const SC_FENCE: usize = usize::MAX;
+
+thread_local! {
+    static CURRENT: RefCell<Option<(Arc<Shared>, usize)>> = const { RefCell::new(None) };
+}
+
+fn current() -> (Arc<Shared>, usize) {
+    CURRENT
+        .with_borrow(|c| c.clone())
+        .expect("model types can only be used inside model::check (and not across executions)")
+}
+
+fn abort() -> ! {
+    panic::resume_unwind(Box::new(Abort))
+}
+
+/// Runs one execution of `f`, following `path` as far as it goes. Returns the path with this
+/// execution's choices appended, and the failure, if any.
+pub(super) fn execute(
+    config: &Config,
+    path: Path,
+    f: Arc<dyn Fn() + Send + Sync>,
+) -> (Path, Option<Failure>) {
+    static EXECUTIONS: atomic::AtomicU64 = atomic::AtomicU64::new(0);
+    let shared = Arc::new(Shared {
+        exec: Mutex::new(Execution {
+            id: EXECUTIONS.fetch_add(1, Ordering::Relaxed),
+            config: config.clone(),
+            path,
+            threads: vec![Thread::new(VectorClock::default())],
+            locations: Vec::new(),
+            active: 0,
+            steps: 0,
+            preemptions: 0,
+            branch: 0,
+            sc_fences: VectorClock::default(),
+            accesses: Vec::new(),
+            stores: 0,
+            events: Vec::new(),
+            failure: None,
+            aborted: false,
+            finished: false,
+            os_threads: Vec::new(),
+        }),
+        turn: Condvar::new(),
+    });
+    let main = std::thread::Builder::new()
+        .name("model-t0".into())
+        .spawn({
+            let shared = shared.clone();
+            move || run_thread(shared, 0, Box::new(move || f()))
+        })
+        .expect("failed to spawn model thread");
+
+    let mut exec = shared.lock();
+    while !exec.finished && !exec.aborted {
+        exec = shared.wait(exec);
+    }
+    drop(exec);
+    // An aborted execution unwinds its remaining threads; wait for them to let go of everything.
+    main.join().expect("model thread runner panicked");
+    while let Some(handle) = shared.lock().os_threads.pop() {
+        handle.join().expect("model thread runner panicked");
+    }
+    let mut exec = shared.lock();
+    (std::mem::take(&mut exec.path), exec.failure.take())
+}
+
+fn run_thread(shared: Arc<Shared>, tid: usize, f: Box<dyn FnOnce() + Send>) {
+    CURRENT.set(Some((shared.clone(), tid)));
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        drop(shared.wait_turn(shared.lock(), tid));
+        f();
+    }));
+    match result {
+        Ok(()) => shared.finish(tid),
+        Err(payload) if payload.is::<Abort>() => {}
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "Box<dyn Any>".into());
+            let mut exec = shared.lock();
+            exec.fail(format!("t{tid} panicked: {message}"));
+            drop(exec);
+            shared.turn.notify_all();
+        }
+    }
+    CURRENT.set(None);
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Execution> {
+        // Nothing panics while holding the lock, except in bugs of the checker itself.
+        self.exec.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wait<'a>(&self, exec: MutexGuard<'a, Execution>) -> MutexGuard<'a, Execution> {
+        self.turn.wait(exec).unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wait_turn<'a>(
+        &self,
+        mut exec: MutexGuard<'a, Execution>,
+        tid: usize,
+    ) -> MutexGuard<'a, Execution> {
+        while exec.active != tid && !exec.aborted {
+            exec = self.wait(exec);
+        }
+        if exec.aborted {
+            drop(exec);
+            abort();
+        }
+        exec
+    }
+
+    /// Fails the execution and unwinds the calling thread.
+    fn fail(&self, mut exec: MutexGuard<'_, Execution>, message: String) -> ! {
+        exec.fail(message);
+        drop(exec);
+        self.turn.notify_all();
+        abort();
+    }
+
+    /// Lets the path pick the next thread and waits until it is `tid`'s turn again.
+    fn switch<'a>(
+        &self,
+        mut exec: MutexGuard<'a, Execution>,
+        tid: usize,
+    ) -> MutexGuard<'a, Execution> {
+        if let Err(message) = exec.schedule() {
+            self.fail(exec, message);
+        }
+        self.turn.notify_all();
+        self.wait_turn(exec, tid)
+    }
+
+    /// Starts an operation of `tid`: a scheduling point, then the thread's clock ticks.
+    ///
+    /// Once an execution has failed, the threads unwind; operations in their destructors run
+    /// right away without scheduling (and `aborted` tells blocking operations not to block).
+    fn enter(&self, tid: usize) -> MutexGuard<'_, Execution> {
+        let mut exec = self.lock();
+        if exec.aborted {
+            if std::thread::panicking() {
+                return exec;
+            }
+            drop(exec);
+            abort();
+        }
+        exec = self.switch(exec, tid);
+        exec.threads[tid].clock.tick(tid);
+        exec
+    }
+
+    fn finish(&self, tid: usize) {
+        let mut exec = self.lock();
+        if exec.aborted {
+            return;
+        }
+        exec.threads[tid].status = Status::Finished;
+        exec.event(tid, "finished".into());
+        exec.wake(|s| s == Status::Yielded || s == Status::Joining(tid));
+        if let Err(message) = exec.schedule() {
+            exec.fail(message);
+        }
+        drop(exec);
+        self.turn.notify_all();
+    }
+}
+
+/// Runs a memory operation of the current thread.
+fn op<R>(
+    location: &Location,
+    f: impl FnOnce(&mut Execution, usize, usize) -> Result<R, String>,
+) -> R {
+    let (shared, tid) = current();
+    let mut exec = shared.enter(tid);
+    if location.execution != exec.id {
+        shared.fail(
+            exec,
+            "model atomic used outside the execution that created it".into(),
+        );
+    }
+    match f(&mut exec, tid, location.index) {
+        Ok(r) => r,
+        Err(message) => shared.fail(exec, message),
+    }
+}
+
+impl Location {
+    pub(super) fn new(value: u64) -> Self {
+        let (shared, tid) = current();
+        let mut exec = shared.lock();
+        let time = exec.threads[tid].clock.get(tid);
+        exec.locations.push(vec![Store {
+            value,
+            thread: tid,
+            time,
+            sync: None,
+            seq_cst: false,
+        }]);
+        Location {
+            execution: exec.id,
+            index: exec.locations.len() - 1,
+        }
+    }
+}
+
+pub(super) fn load(location: &Location, order: Ordering) -> u64 {
+    assert!(
+        !matches!(order, Ordering::Release | Ordering::AcqRel),
+        "there is no such thing as a release load"
+    );
+    op(location, |exec, tid, loc| {
+        exec.access(tid, loc, false);
+        let first = exec.oldest_readable(tid, loc, order);
+        let stores = exec.locations[loc].len();
+        let fresh = std::mem::take(&mut exec.threads[tid].fresh_read);
+        if fresh {
+            exec.threads[tid].fresh_at = Some(exec.stores);
+        }
+        let index = if fresh || stores - first == 1 {
+            stores - 1
+        } else {
+            first + exec.path.choose_read(stores - first)?
+        };
+        let value = exec.read(tid, loc, index, order);
+        let seen = if stores - first > 1 {
+            format!(" (store {} of {})", index - first + 1, stores - first)
+        } else {
+            String::new()
+        };
+        exec.event(tid, format!("a{loc}.load({order:?}) -> {value}{seen}"));
+        Ok(value)
+    })
+}
+
+pub(super) fn store(location: &Location, value: u64, order: Ordering) {
+    assert!(
+        !matches!(order, Ordering::Acquire | Ordering::AcqRel),
+        "there is no such thing as an acquire store"
+    );
+    op(location, |exec, tid, loc| {
+        exec.access(tid, loc, true);
+        exec.write(tid, loc, value, order, None);
+        exec.event(tid, format!("a{loc}.store({value}, {order:?})"));
+        Ok(())
+    })
+}
+
+/// Reads the newest value and, if `f` returns a new one, writes it in the same step. Returns the
+/// old value, as `Ok` if it was replaced.
+pub(super) fn rmw(
+    location: &Location,
+    success: Ordering,
+    failure: Ordering,
+    f: impl FnOnce(u64) -> Option<u64>,
+) -> Result<u64, u64> {
+    assert!(
+        !matches!(failure, Ordering::Release | Ordering::AcqRel),
+        "there is no such thing as a release failure ordering"
+    );
+    op(location, |exec, tid, loc| {
+        exec.access(tid, loc, true);
+        let index = exec.locations[loc].len() - 1;
+        let old = exec.locations[loc][index].value;
+        let result = match f(old) {
+            Some(new) => {
+                exec.read(tid, loc, index, success);
+                let sequence = exec.locations[loc][index].sync.clone();
+                exec.write(tid, loc, new, success, sequence);
+                exec.event(tid, format!("a{loc}.rmw({success:?}): {old} -> {new}"));
+                Ok(old)
+            }
+            None => {
+                exec.read(tid, loc, index, failure);
+                exec.event(tid, format!("a{loc}.rmw({failure:?}): {old}, unchanged"));
+                Err(old)
+            }
+        };
+        Ok(result)
+    })
+}
+
+pub(super) fn fence(order: Ordering) {
+    assert!(
+        order != Ordering::Relaxed,
+        "there is no such thing as a relaxed fence"
+    );
+    let (shared, tid) = current();
+    let mut exec = shared.enter(tid);
+    if exec.aborted {
+        return;
+    }
+    if order == Ordering::SeqCst {
+        exec.access(tid, SC_FENCE, true);
+        let sc = exec.sc_fences.clone();
+        exec.threads[tid].clock.join(&sc);
+    }
+    let thread = &mut exec.threads[tid];
+    if matches!(
+        order,
+        Ordering::Acquire | Ordering::AcqRel | Ordering::SeqCst
+    ) {
+        let pending = thread.acquire_fence.clone();
+        thread.clock.join(&pending);
+    }
+    if matches!(
+        order,
+        Ordering::Release | Ordering::AcqRel | Ordering::SeqCst
+    ) {
+        thread.release_fence = Some(thread.clock.clone());
+    }
+    if order == Ordering::SeqCst {
+        exec.sc_fences = exec.threads[tid].clock.clone();
+    }
+    exec.event(tid, format!("fence({order:?})"));
+}
+
+/// Creates a model thread running `f` and returns its id.
+pub(super) fn spawn(f: Box<dyn FnOnce() + Send>) -> usize {
+    let (shared, tid) = current();
+    let mut exec = shared.enter(tid);
+    if exec.aborted {
+        drop(exec);
+        abort();
+    }
+    let child = exec.threads.len();
+    // Everything the parent did so far happens before the child starts.
+    let mut clock = exec.threads[tid].clock.clone();
+    clock.tick(child);
+    exec.threads.push(Thread::new(clock));
+    exec.event(tid, format!("spawn t{child}"));
+    let handle = std::thread::Builder::new()
+        .name(format!("model-t{child}"))
+        .spawn({
+            let shared = shared.clone();
+            move || run_thread(shared, child, f)
+        })
+        .expect("failed to spawn model thread");
+    exec.os_threads.push(handle);
+    child
+}
+
+/// Blocks until model thread `target` has finished.
+pub(super) fn join(target: usize) {
+    let (shared, tid) = current();
+    let mut exec = shared.enter(tid);
+    if exec.aborted {
+        return;
+    }
+    if exec.threads[target].status != Status::Finished {
+        exec.threads[tid].status = Status::Joining(target);
+        exec = shared.switch(exec, tid);
+    }
+    // Everything the target did happens before the join returns.
+    let clock = exec.threads[target].clock.clone();
+    exec.threads[tid].clock.join(&clock);
+    exec.event(tid, format!("join t{target}"));
+}
+
+/// Lets other threads run before the current one continues, e.g. in a spin loop.
+pub(super) fn yield_now() {
+    let (shared, tid) = current();
+    let mut exec = shared.enter(tid);
+    if exec.aborted {
+        return;
+    }
+    exec.event(tid, "yield".into());
+    let stores = exec.stores;
+    // Others can run if they're runnable, or parked on stale values (see `schedule`).
+    let others = exec.threads.iter().enumerate().any(|(t, thread)| {
+        t != tid
+            && (thread.status == Status::Runnable
+                || thread.status == Status::Yielded && thread.fresh_at != Some(stores))
+    });
+    let thread = &mut exec.threads[tid];
+    if others {
+        thread.status = Status::Yielded;
+    } else if thread.yielded_at == Some(stores) {
+        // Nobody else can run, and nothing changed since the last time around the loop.
+        shared.fail(exec, "livelock: every unfinished thread is spinning".into());
+    }
+    thread.yielded_at = Some(stores);
+    thread.fresh_read = true;
+    drop(shared.switch(exec, tid));
+}
+
+impl Thread {
+    fn new(clock: VectorClock) -> Self {
+        Self {
+            status: Status::Runnable,
+            clock,
+            acquire_fence: VectorClock::default(),
+            release_fence: None,
+            coherence: Vec::new(),
+            fresh_read: false,
+            yielded_at: None,
+            fresh_at: None,
+        }
+    }
+}
+
+impl Execution {
+    fn schedule(&mut self) -> Result<(), String> {
+        self.steps += 1;
+        if self.steps > self.config.max_steps {
+            return Err(format!(
+                "execution took more than {} steps; spin loops need model::thread::yield_now() \
+                 or model::hint::spin_loop()",
+                self.config.max_steps
+            ));
+        }
+        let mut enabled = self.enabled();
+        if enabled.is_empty() {
+            if self.threads.iter().all(|t| t.status == Status::Finished) {
+                self.finished = true;
+                return Ok(());
+            }
+            // A thread that parked after reading stale values hasn't seen the newest stores yet,
+            // and its next load will: no store is coming to wake it, but it isn't stuck.
+            let stores = self.stores;
+            for t in &mut self.threads {
+                if t.status == Status::Yielded && t.fresh_at != Some(stores) {
+                    t.status = Status::Runnable;
+                }
+            }
+            enabled = self.enabled();
+        }
+        if enabled.is_empty() {
+            if self.threads.iter().any(|t| t.status == Status::Yielded) {
+                return Err("livelock: every unfinished thread is spinning".into());
+            }
+            return Err("deadlock: every unfinished thread is blocked in join".into());
+        }
+        let current = (self.threads[self.active].status == Status::Runnable).then_some(self.active);
+        let can_preempt = self
+            .config
+            .preemption_bound
+            .is_none_or(|bound| self.preemptions < bound);
+        let chosen = self
+            .path
+            .choose_thread(&enabled, current, can_preempt, self.config.dpor)?;
+        if current.is_some_and(|c| c != chosen) {
+            self.preemptions += 1;
+        }
+        self.active = chosen;
+        self.branch = self.path.last();
+        Ok(())
+    }
+
+    fn fail(&mut self, message: String) {
+        if self.failure.is_none() {
+            self.failure = Some(Failure {
+                message,
+                trace: self.path.trace(),
+                events: std::mem::take(&mut self.events),
+            });
+        }
+        self.aborted = true;
+    }
+
+    fn event(&mut self, tid: usize, what: String) {
+        self.events.push(format!("t{tid}: {what}"));
+    }
+
+    fn enabled(&self) -> Vec<usize> {
+        (0..self.threads.len())
+            .filter(|&t| self.threads[t].status == Status::Runnable)
+            .collect()
+    }
+
+    fn wake(&mut self, which: impl Fn(Status) -> bool) {
+        for t in &mut self.threads {
+            if which(t.status) {
+                t.status = Status::Runnable;
+            }
+        }
+    }
+
+    /// Records an access for DPOR. Any earlier conflicting access by another thread that doesn't
+    /// happen before this one could have gone the other way, so the thread choice that let it run
+    /// has to try running this thread first as well.
+    fn access(&mut self, tid: usize, location: usize, write: bool) {
+        let clock = &self.threads[tid].clock;
+        for a in &self.accesses {
+            if a.location == location
+                && a.thread != tid
+                && (write || a.write)
+                && clock.get(a.thread) < a.time
+            {
+                self.path.add_backtrack(a.branch, tid);
+            }
+        }
+        self.accesses.push(Access {
+            thread: tid,
+            location,
+            write,
+            time: clock.get(tid),
+            branch: self.branch,
+        });
+    }
+
+    /// The oldest store of `loc` that `tid` may still read: not older than what it has seen
+    /// already, nor than the newest store that happens before it, nor, for a `SeqCst` load, than
+    /// the newest `SeqCst` store. Newer stores that aren't `SeqCst` stay readable either way.
+    fn oldest_readable(&self, tid: usize, loc: usize, order: Ordering) -> usize {
+        let thread = &self.threads[tid];
+        let stores = &self.locations[loc];
+        let seen = thread.coherence.get(loc).copied().unwrap_or(0);
+        let visible = stores
+            .iter()
+            .rposition(|s| thread.clock.get(s.thread) >= s.time)
+            .unwrap_or(0);
+        let sequenced = match order {
+            Ordering::SeqCst => stores.iter().rposition(|s| s.seq_cst).unwrap_or(0),
+            _ => 0,
+        };
+        seen.max(visible).max(sequenced)
+    }
+
+    fn read(&mut self, tid: usize, loc: usize, index: usize, order: Ordering) -> u64 {
+        let store = &self.locations[loc][index];
+        let thread = &mut self.threads[tid];
+        if thread.coherence.len() <= loc {
+            thread.coherence.resize(loc + 1, 0);
+        }
+        thread.coherence[loc] = index;
+        if let Some(sync) = &store.sync {
+            if matches!(
+                order,
+                Ordering::Acquire | Ordering::AcqRel | Ordering::SeqCst
+            ) {
+                thread.clock.join(sync);
+            } else {
+                thread.acquire_fence.join(sync);
+            }
+        }
+        store.value
+    }
+
+    /// Appends a store. `sequence` is the release clock of the store an RMW replaces, which the
+    /// RMW carries on (a release sequence).
+    fn write(
+        &mut self,
+        tid: usize,
+        loc: usize,
+        value: u64,
+        order: Ordering,
+        mut sequence: Option<VectorClock>,
+    ) {
+        let thread = &mut self.threads[tid];
+        let release = if matches!(
+            order,
+            Ordering::Release | Ordering::AcqRel | Ordering::SeqCst
+        ) {
+            Some(&thread.clock)
+        } else {
+            thread.release_fence.as_ref()
+        };
+        if let Some(release) = release {
+            sequence
+                .get_or_insert_with(VectorClock::default)
+                .join(release);
+        }
+        let stores = &mut self.locations[loc];
+        stores.push(Store {
+            value,
+            thread: tid,
+            time: thread.clock.get(tid),
+            sync: sequence,
+            seq_cst: order == Ordering::SeqCst,
+        });
+        if thread.coherence.len() <= loc {
+            thread.coherence.resize(loc + 1, 0);
+        }
+        thread.coherence[loc] = stores.len() - 1;
+        self.stores += 1;
+        self.wake(|s| s == Status::Yielded);
+    }
+}
@@ -0,0 +1,48 @@
+//! Shadow threads. [`spawn`] runs the closure on a real OS thread, but it only runs when the
+//! checker gives it the turn.
+
+use std::sync::{Arc, Mutex, PoisonError};
+
+use super::rt;
+
+/// An owned permission to join a model thread.
+#[derive(Debug)]
+pub struct JoinHandle<T> {
+    thread: usize,
+    result: Arc<Mutex<Option<T>>>,
+}
+
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let result = Arc::new(Mutex::new(None));
+    let slot = result.clone();
+    let thread = rt::spawn(Box::new(move || {
+        let value = f();
+        *slot.lock().unwrap_or_else(PoisonError::into_inner) = Some(value);
+    }));
+    JoinHandle { thread, result }
+}
+
+impl<T> JoinHandle<T> {
+    /// Waits for the thread to finish. A panicking model thread fails the whole execution, so
+    /// this only returns `Err` while a failed execution is being torn down.
+    pub fn join(self) -> std::thread::Result<T> {
+        rt::join(self.thread);
+        let value = self
+            .result
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        value.ok_or_else(|| Box::new(rt::Abort) as _)
+    }
+}
+
+/// Lets the other threads run first. Call this in every spin loop: a thread that yields isn't
+/// scheduled again until another thread has stored something (or finished), and its next load
+/// sees the newest value.
+pub fn yield_now() {
+    rt::yield_now();
+}
@@ -0,0 +1,703 @@
+//! Data-parallel iterators over slices and vectors.
+//!
+//! A parallel iterator is a splittable source of items (a slice, or the items moved out of a
+//! vector) and a chain of adapters. A terminal operation (`for_each`, `reduce`, `sum`, `collect`)
+//! turns into a [`Consumer`] that folds a sequential iterator over a piece of the source and
+//! combines the results of neighbouring pieces. The source is split in halves with [`join`],
+//! adaptively: a piece is split about as many times as there are threads, and split further
+//! only when it turns out to have been stolen, which means some thread ran out of work.
+//!
+//! Mutable slices are split with the `split_at_mut` pattern from the `borrow` test in `main.rs`:
+//! two `from_raw_parts_mut` over disjoint halves of one pointer.
+//!
+//! Run on a pool thread, everything stays on that pool. Run anywhere else, it moves to the
+//! [`global`](super::global) pool. Results are combined in order, so `collect` keeps the order of
+//! the source and `reduce` only needs an associative operation.
+
+use std::collections::LinkedList;
+use std::iter::Sum;
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr;
+use std::slice;
+use std::thread;
+
+use super::{current_num_threads, in_worker, join};
+
+/// Folds pieces of a parallel iterator and combines the results. The plumbing between adapters
+/// and terminal operations; there is rarely a reason to implement it outside this module.
+pub trait Consumer<T>: Sync {
+    type Result: Send;
+
+    fn consume_iter(&self, iter: impl Iterator<Item = T>) -> Self::Result;
+
+    /// Combines the results of two neighbouring pieces, `left` coming first.
+    fn combine(&self, left: Self::Result, right: Self::Result) -> Self::Result;
+}
+
+pub trait ParallelIterator: Sized + Send {
+    type Item: Send;
+
+    /// Feeds every item to `consumer`, piece by piece.
+    fn drive<C: Consumer<Self::Item>>(self, consumer: &C) -> C::Result;
+
+    fn map<R, F>(self, f: F) -> Map<Self, F>
+    where
+        R: Send,
+        F: Fn(Self::Item) -> R + Sync + Send,
+    {
+        Map { base: self, f }
+    }
+
+    fn filter<P>(self, predicate: P) -> Filter<Self, P>
+    where
+        P: Fn(&Self::Item) -> bool + Sync + Send,
+    {
+        Filter {
+            base: self,
+            predicate,
+        }
+    }
+
+    fn for_each<F>(self, f: F)
+    where
+        F: Fn(Self::Item) + Sync + Send,
+    {
+        struct ForEach<F>(F);
+        impl<T, F: Fn(T) + Sync> Consumer<T> for ForEach<F> {
+            type Result = ();
+            fn consume_iter(&self, iter: impl Iterator<Item = T>) {
+                iter.for_each(&self.0);
+            }
+            fn combine(&self, (): (), (): ()) {}
+        }
+        in_worker(|| self.drive(&ForEach(f)));
+    }
+
+    /// Combines all items with `op`, which must be associative. `identity()` starts every piece,
+    /// so it must be an identity of `op` (like 0 for `+`).
+    fn reduce<ID, OP>(self, identity: ID, op: OP) -> Self::Item
+    where
+        ID: Fn() -> Self::Item + Sync + Send,
+        OP: Fn(Self::Item, Self::Item) -> Self::Item + Sync + Send,
+    {
+        struct Reduce<ID, OP>(ID, OP);
+        impl<T: Send, ID, OP> Consumer<T> for Reduce<ID, OP>
+        where
+            ID: Fn() -> T + Sync,
+            OP: Fn(T, T) -> T + Sync,
+        {
+            type Result = T;
+            fn consume_iter(&self, iter: impl Iterator<Item = T>) -> T {
+                iter.fold((self.0)(), &self.1)
+            }
+            fn combine(&self, left: T, right: T) -> T {
+                (self.1)(left, right)
+            }
+        }
+        in_worker(|| self.drive(&Reduce(identity, op)))
+    }
+
+    fn sum<S>(self) -> S
+    where
+        S: Sum<Self::Item> + Sum<S> + Send,
+    {
+        struct Summer<S>(PhantomData<fn() -> S>);
+        impl<T, S: Sum<T> + Sum<S> + Send> Consumer<T> for Summer<S> {
+            type Result = S;
+            fn consume_iter(&self, iter: impl Iterator<Item = T>) -> S {
+                iter.sum()
+            }
+            fn combine(&self, left: S, right: S) -> S {
+                [left, right].into_iter().sum()
+            }
+        }
+        in_worker(|| self.drive(&Summer(PhantomData)))
+    }
+
+    fn collect<C: FromParallelIterator<Self::Item>>(self) -> C {
+        C::from_par_iter(self)
+    }
+
+    /// The items in order, as one vector per piece. What `collect` implementations start from.
+    fn collect_vec_list(self) -> LinkedList<Vec<Self::Item>> {
+        struct Chunks;
+        impl<T: Send> Consumer<T> for Chunks {
+            type Result = LinkedList<Vec<T>>;
+            fn consume_iter(&self, iter: impl Iterator<Item = T>) -> Self::Result {
+                let chunk: Vec<T> = iter.collect();
+                let mut list = LinkedList::new();
+                if !chunk.is_empty() {
+                    list.push_back(chunk);
+                }
+                list
+            }
+            fn combine(&self, mut left: Self::Result, mut right: Self::Result) -> Self::Result {
+                left.append(&mut right);
+                left
+            }
+        }
+        in_worker(|| self.drive(&Chunks))
+    }
+}
+
+/// A collection that can be built from a parallel iterator, keeping its order.
+pub trait FromParallelIterator<T: Send> {
+    fn from_par_iter<I: ParallelIterator<Item = T>>(iter: I) -> Self;
+}
+
+impl<T: Send> FromParallelIterator<T> for Vec<T> {
+    fn from_par_iter<I: ParallelIterator<Item = T>>(iter: I) -> Self {
+        let chunks = iter.collect_vec_list();
+        let mut vec = Vec::with_capacity(chunks.iter().map(Vec::len).sum());
+        for mut chunk in chunks {
+            vec.append(&mut chunk);
+        }
+        vec
+    }
+}
+
+pub trait IntoParallelIterator {
+    type Item: Send;
+    type Iter: ParallelIterator<Item = Self::Item>;
+
+    fn into_par_iter(self) -> Self::Iter;
+}
+
+/// `par_iter` and `par_sort` for slices, and through `Deref` for anything that derefs to one.
+pub trait ParallelSlice<T> {
+    fn par_iter(&self) -> Iter<'_, T>
+    where
+        T: Sync;
+
+    fn par_iter_mut(&mut self) -> IterMut<'_, T>
+    where
+        T: Send;
+
+    /// A stable merge sort: halves are sorted in parallel, then merged.
+    fn par_sort(&mut self)
+    where
+        T: Ord + Send;
+}
+
+impl<T> ParallelSlice<T> for [T] {
+    fn par_iter(&self) -> Iter<'_, T>
+    where
+        T: Sync,
+    {
+        Iter { slice: self }
+    }
+
+    fn par_iter_mut(&mut self) -> IterMut<'_, T>
+    where
+        T: Send,
+    {
+        IterMut { slice: self }
+    }
+
+    fn par_sort(&mut self)
+    where
+        T: Ord + Send,
+    {
+        in_worker(|| merge_sort(self));
+    }
+}
+
+impl<'a, T: Sync> IntoParallelIterator for &'a [T] {
+    type Item = &'a T;
+    type Iter = Iter<'a, T>;
+
+    fn into_par_iter(self) -> Iter<'a, T> {
+        Iter { slice: self }
+    }
+}
+
+impl<'a, T: Send> IntoParallelIterator for &'a mut [T] {
+    type Item = &'a mut T;
+    type Iter = IterMut<'a, T>;
+
+    fn into_par_iter(self) -> IterMut<'a, T> {
+        IterMut { slice: self }
+    }
+}
+
+impl<T: Send> IntoParallelIterator for Vec<T> {
+    type Item = T;
+    type Iter = IntoIter<T, Vec<T>>;
+
+    fn into_par_iter(mut self) -> Self::Iter {
+        let (ptr, len) = (self.as_mut_ptr(), self.len());
+        // Safety: with its length at zero, the vector keeps its buffer but no longer drops the
+        // items, and moving it doesn't move the buffer.
+        unsafe {
+            self.set_len(0);
+            IntoIter::from_raw_parts(self, ptr, len)
+        }
+    }
+}
+
+/// A parallel iterator over `&T`.
+#[derive(Debug)]
+pub struct Iter<'a, T> {
+    slice: &'a [T],
+}
+
+/// A parallel iterator over `&mut T`.
+#[derive(Debug)]
+pub struct IterMut<'a, T> {
+    slice: &'a mut [T],
+}
+
+/// A parallel iterator moving items out of a buffer that `owner` keeps allocated.
+#[derive(Debug)]
+pub struct IntoIter<T, O> {
+    owner: O,
+    ptr: *mut T,
+    len: usize,
+}
+
+// Safety: the items are moved to other threads (T: Send), and the owner dropped on any (O: Send).
+unsafe impl<T: Send, O: Send> Send for IntoIter<T, O> {}
+
+impl<T, O> IntoIter<T, O> {
+    /// # Safety
+    ///
+    /// `ptr..ptr + len` must hold initialised items that nobody else uses or drops, in an
+    /// allocation that stays valid for as long as `owner` lives.
+    pub unsafe fn from_raw_parts(owner: O, ptr: *mut T, len: usize) -> Self {
+        Self { owner, ptr, len }
+    }
+}
+
+impl<T, O> Drop for IntoIter<T, O> {
+    fn drop(&mut self) {
+        // Only reached with the items still in place if the iterator was never driven.
+        // Safety: see `from_raw_parts`.
+        unsafe { ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.ptr, self.len)) };
+    }
+}
+
+impl<'a, T: Sync> ParallelIterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn drive<C: Consumer<&'a T>>(self, consumer: &C) -> C::Result {
+        bridge(self.slice, consumer)
+    }
+}
+
+impl<'a, T: Send> ParallelIterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+
+    fn drive<C: Consumer<&'a mut T>>(self, consumer: &C) -> C::Result {
+        bridge(self.slice, consumer)
+    }
+}
+
+impl<T: Send, O: Send> ParallelIterator for IntoIter<T, O> {
+    type Item = T;
+
+    fn drive<C: Consumer<T>>(self, consumer: &C) -> C::Result {
+        let this = mem::ManuallyDrop::new(self);
+        // Safety: `this` is never used again. From here on the drain owns the items, and `owner`
+        // only the allocation; it is dropped after the drain, even if a consumer panics.
+        let owner = unsafe { ptr::read(&this.owner) };
+        let items = unsafe { slice::from_raw_parts_mut(this.ptr, this.len) };
+        let result = bridge(Drain { items }, consumer);
+        drop(owner);
+        result
+    }
+}
+
+/// A parallel iterator applying `f` to every item.
+#[derive(Debug)]
+pub struct Map<I, F> {
+    base: I,
+    f: F,
+}
+
+impl<I, F, R> ParallelIterator for Map<I, F>
+where
+    I: ParallelIterator,
+    F: Fn(I::Item) -> R + Sync + Send,
+    R: Send,
+{
+    type Item = R;
+
+    fn drive<C: Consumer<R>>(self, consumer: &C) -> C::Result {
+        struct MapConsumer<'c, C, F>(&'c C, &'c F);
+        impl<T, R, C: Consumer<R>, F: Fn(T) -> R + Sync> Consumer<T> for MapConsumer<'_, C, F> {
+            type Result = C::Result;
+            fn consume_iter(&self, iter: impl Iterator<Item = T>) -> C::Result {
+                self.0.consume_iter(iter.map(self.1))
+            }
+            fn combine(&self, left: C::Result, right: C::Result) -> C::Result {
+                self.0.combine(left, right)
+            }
+        }
+        self.base.drive(&MapConsumer(consumer, &self.f))
+    }
+}
+
+/// A parallel iterator keeping the items `predicate` accepts.
+#[derive(Debug)]
+pub struct Filter<I, P> {
+    base: I,
+    predicate: P,
+}
+
+impl<I, P> ParallelIterator for Filter<I, P>
+where
+    I: ParallelIterator,
+    P: Fn(&I::Item) -> bool + Sync + Send,
+{
+    type Item = I::Item;
+
+    fn drive<C: Consumer<I::Item>>(self, consumer: &C) -> C::Result {
+        struct FilterConsumer<'c, C, P>(&'c C, &'c P);
+        impl<T, C: Consumer<T>, P: Fn(&T) -> bool + Sync> Consumer<T> for FilterConsumer<'_, C, P> {
+            type Result = C::Result;
+            fn consume_iter(&self, iter: impl Iterator<Item = T>) -> C::Result {
+                self.0.consume_iter(iter.filter(self.1))
+            }
+            fn combine(&self, left: C::Result, right: C::Result) -> C::Result {
+                self.0.combine(left, right)
+            }
+        }
+        self.base.drive(&FilterConsumer(consumer, &self.predicate))
+    }
+}
+
+/// A source of items that can be split at an index.
+trait Producer: Send + Sized {
+    type Item;
+    type IntoIter: Iterator<Item = Self::Item>;
+
+    fn len(&self) -> usize;
+    fn split_at(self, index: usize) -> (Self, Self);
+    fn into_iter(self) -> Self::IntoIter;
+}
+
+impl<'a, T: Sync> Producer for &'a [T] {
+    type Item = &'a T;
+    type IntoIter = slice::Iter<'a, T>;
+
+    fn len(&self) -> usize {
+        <[T]>::len(self)
+    }
+
+    fn split_at(self, index: usize) -> (Self, Self) {
+        <[T]>::split_at(self, index)
+    }
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T: Send> Producer for &'a mut [T] {
+    type Item = &'a mut T;
+    type IntoIter = slice::IterMut<'a, T>;
+
+    fn len(&self) -> usize {
+        <[T]>::len(self)
+    }
+
+    fn split_at(self, index: usize) -> (Self, Self) {
+        split_at_mut(self, index)
+    }
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+/// Items to be moved out of a slice. Whatever isn't moved out is dropped in place.
+struct Drain<'a, T> {
+    items: &'a mut [T],
+}
+
+impl<'a, T: Send> Producer for Drain<'a, T> {
+    type Item = T;
+    type IntoIter = DrainIter<'a, T>;
+
+    fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    fn split_at(mut self, index: usize) -> (Self, Self) {
+        let (left, right) = split_at_mut(mem::take(&mut self.items), index);
+        (Drain { items: left }, Drain { items: right })
+    }
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        DrainIter {
+            items: mem::take(&mut self.items).iter_mut(),
+        }
+    }
+}
+
+impl<T> Drop for Drain<'_, T> {
+    fn drop(&mut self) {
+        // Safety: the drain owns its items.
+        unsafe { ptr::drop_in_place(self.items) };
+    }
+}
+
+struct DrainIter<'a, T> {
+    items: slice::IterMut<'a, T>,
+}
+
+impl<T> Iterator for DrainIter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        // Safety: each item is moved out once, and never dropped in place after that.
+        self.items.next().map(|item| unsafe { ptr::read(item) })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.items.size_hint()
+    }
+}
+
+impl<T> Drop for DrainIter<'_, T> {
+    fn drop(&mut self) {
+        // Safety: the remaining items were never moved out.
+        unsafe { ptr::drop_in_place(mem::take(&mut self.items).into_slice()) };
+    }
+}
+
+/// Splits a mutable slice in two, as in the `borrow` test in `main.rs`.
+fn split_at_mut<T>(slice: &mut [T], mid: usize) -> (&mut [T], &mut [T]) {
+    let len = slice.len();
+    let ptr = slice.as_mut_ptr();
+    assert!(mid <= len);
+    // Safety: the halves don't overlap, and together they cover exactly the borrowed slice.
+    unsafe {
+        (
+            slice::from_raw_parts_mut(ptr, mid),
+            slice::from_raw_parts_mut(ptr.add(mid), len - mid),
+        )
+    }
+}
+
+/// How many more times a piece may be split.
+#[derive(Clone, Copy)]
+struct Splitter {
+    splits: usize,
+}
+
+impl Splitter {
+    fn try_split(&mut self, stolen: bool) -> bool {
+        if stolen {
+            // Another thread was idle enough to steal this piece: give it room to share.
+            self.splits = current_num_threads().max(self.splits / 2);
+            true
+        } else if self.splits > 0 {
+            self.splits /= 2;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn bridge<P: Producer, C: Consumer<P::Item>>(producer: P, consumer: &C) -> C::Result {
+    let splitter = Splitter {
+        splits: current_num_threads(),
+    };
+    bridge_piece(producer, consumer, splitter, false)
+}
+
+fn bridge_piece<P: Producer, C: Consumer<P::Item>>(
+    producer: P,
+    consumer: &C,
+    mut splitter: Splitter,
+    stolen: bool,
+) -> C::Result {
+    if producer.len() < 2 || !splitter.try_split(stolen) {
+        return consumer.consume_iter(producer.into_iter());
+    }
+    let mid = producer.len() / 2;
+    let (left, right) = producer.split_at(mid);
+    let origin = thread::current().id();
+    let (left, right) = join(
+        || bridge_piece(left, consumer, splitter, false),
+        || bridge_piece(right, consumer, splitter, thread::current().id() != origin),
+    );
+    consumer.combine(left, right)
+}
+
+/// Below this, `par_sort` sorts sequentially.
+const SEQUENTIAL_SORT_BELOW: usize = 4096;
+
+fn merge_sort<T: Ord + Send>(v: &mut [T]) {
+    if v.len() <= SEQUENTIAL_SORT_BELOW {
+        v.sort();
+        return;
+    }
+    let mid = v.len() / 2;
+    let (left, right) = split_at_mut(v, mid);
+    join(|| merge_sort(left), || merge_sort(right));
+    merge(v, mid);
+}
+
+/// Merges the sorted runs `v[..mid]` and `v[mid..]`, stably.
+fn merge<T: Ord>(v: &mut [T], mid: usize) {
+    let len = v.len();
+    let mut buf: Vec<T> = Vec::with_capacity(mid);
+    let v = v.as_mut_ptr();
+
+    /// The part of the left run not merged yet, moved out to `buf`. If a comparison panics, it
+    /// goes back to the gap it fits exactly, so every item is in `v` once.
+    struct Hole<T> {
+        start: *mut T,
+        end: *mut T,
+        dest: *mut T,
+    }
+
+    impl<T> Drop for Hole<T> {
+        fn drop(&mut self) {
+            // Safety: `start..end` are the unmerged left items, and `dest` the gap left for them.
+            unsafe {
+                let rest = self.end.offset_from(self.start) as usize;
+                ptr::copy_nonoverlapping(self.start, self.dest, rest);
+            }
+        }
+    }
+
+    // Safety: the left run moves to `buf` and back, each item written to `v` exactly once. The
+    // gap at `dest` is always as long as what's left in `buf`, so the right run is never
+    // overwritten before it is read.
+    unsafe {
+        ptr::copy_nonoverlapping(v, buf.as_mut_ptr(), mid);
+        let mut hole = Hole {
+            start: buf.as_mut_ptr(),
+            end: buf.as_mut_ptr().add(mid),
+            dest: v,
+        };
+        let mut right = v.add(mid);
+        let right_end = v.add(len);
+        while hole.start < hole.end && right < right_end {
+            // Ties go left, which keeps the sort stable.
+            let take_right = *right < *hole.start;
+            let src = if take_right { right } else { hole.start };
+            ptr::copy_nonoverlapping(src, hole.dest, 1);
+            hole.dest = hole.dest.add(1);
+            if take_right {
+                right = right.add(1);
+            } else {
+                hole.start = hole.start.add(1);
+            }
+        }
+        // Dropping the hole moves the rest of the left run into place; the rest of the right run
+        // already is.
+    }
+}
+
+#[test]
+fn par_iter_matches_sequential() {
+    let numbers: Vec<u64> = (0..100_000).collect();
+    let pool = super::ThreadPool::new(4);
+    pool.install(|| {
+        let sum: u64 = numbers
+            .par_iter()
+            .map(|&n| n * 3)
+            .filter(|n| n % 2 == 0)
+            .sum();
+        let expected: u64 = numbers.iter().map(|&n| n * 3).filter(|n| n % 2 == 0).sum();
+        assert_eq!(sum, expected);
+
+        let max = numbers
+            .par_iter()
+            .map(|&n| n ^ 0x5555)
+            .reduce(|| 0, u64::max);
+        assert_eq!(Some(max), numbers.iter().map(|&n| n ^ 0x5555).max());
+
+        let strings: Vec<String> = numbers.par_iter().map(u64::to_string).collect();
+        assert!(strings
+            .iter()
+            .zip(&numbers)
+            .all(|(s, n)| *s == n.to_string()));
+    });
+    // Outside a pool, on the global one.
+    let evens: Vec<u64> = numbers
+        .clone()
+        .into_par_iter()
+        .filter(|n| n % 2 == 0)
+        .collect();
+    assert_eq!(evens, (0..100_000).step_by(2).collect::<Vec<u64>>());
+}
+
+#[test]
+fn par_iter_mut_and_for_each() {
+    let mut numbers: Vec<u64> = (0..10_000).collect();
+    numbers.par_iter_mut().for_each(|n| *n *= 2);
+    assert!(numbers.iter().enumerate().all(|(i, &n)| n == 2 * i as u64));
+
+    // Items moved out are dropped exactly once, whether consumed, filtered out or left behind by
+    // a panic.
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    struct Counted<'a>(usize, &'a AtomicUsize);
+    impl Drop for Counted<'_> {
+        fn drop(&mut self) {
+            self.1.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    let drops = AtomicUsize::new(0);
+    let items = || (0..1_000).map(|i| Counted(i, &drops)).collect::<Vec<_>>();
+
+    let kept: Vec<Counted> = items()
+        .into_par_iter()
+        .filter(|c| c.0.is_multiple_of(3))
+        .collect();
+    assert_eq!(kept.len(), 334);
+    assert_eq!(drops.load(Ordering::Relaxed), 666);
+    drop(kept);
+    assert_eq!(drops.swap(0, Ordering::Relaxed), 1_000);
+
+    let seen = AtomicUsize::new(0);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        items().into_par_iter().for_each(|c| {
+            if seen.fetch_add(1, Ordering::Relaxed) == 500 {
+                panic!("consumer failed");
+            }
+            drop(c);
+        })
+    }));
+    assert!(result.is_err());
+    assert_eq!(drops.swap(0, Ordering::Relaxed), 1_000);
+
+    drop(items().into_par_iter());
+    assert_eq!(drops.into_inner(), 1_000);
+}
+
+#[test]
+fn par_sort_is_a_stable_sort() {
+    // Sort by key only, so stability shows in the second field.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct Item(u32, usize);
+    impl PartialOrd for Item {
+        fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+    impl Ord for Item {
+        fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+            self.0.cmp(&other.0)
+        }
+    }
+    let mut x = 12345u32;
+    let mut items: Vec<Item> = (0..50_000)
+        .map(|i| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            Item(x % 1000, i)
+        })
+        .collect();
+    let mut expected = items.clone();
+    expected.sort();
+    items.par_sort();
+    assert_eq!(items, expected);
+}
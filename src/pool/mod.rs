@@ -0,0 +1,422 @@
+//! A work-stealing thread pool.
+//!
+//! Every worker owns a [`deque`](crate::lockfree::deque): jobs spawned from a worker go to the
+//! bottom of its own deque, where it picks them up again LIFO while they're still hot in its
+//! cache. Jobs spawned from outside go to a shared injector queue. A worker that runs out of work
+//! takes from the injector, then steals from the top of other workers' deques, starting from a
+//! random victim so that thieves spread out. A worker that finds nothing at all sleeps on a
+//! condition variable until a job is pushed.
+//!
+//! [`ThreadPool::join`] is fork-join: it pushes the second closure as a job, runs the first
+//! itself, and then either pops the second back (nobody stole it) or runs other jobs until the
+//! thief is done. [`ThreadPool::scope`] is [`std::thread::scope`] on pool threads: spawned jobs
+//! may borrow from outside the scope, which waits for all of them. [`iter`] builds parallel
+//! iterators on `join`.
+
+pub mod iter;
+mod scope;
+
+use std::cell::Cell;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock, PoisonError};
+use std::thread::{self, JoinHandle, Thread};
+
+use crate::lockfree::deque::{Steal, Stealer, Worker};
+use crate::lockfree::Queue;
+pub use scope::Scope;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+pub struct ThreadPool {
+    registry: Arc<Registry>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+/// What the workers share.
+struct Registry {
+    injector: Queue<Job>,
+    stealers: Vec<Stealer<Job>>,
+    /// Jobs pushed and not yet taken. Counted before the push, so it never underflows.
+    pending: AtomicUsize,
+    /// Workers asleep, or about to be.
+    sleepers: AtomicUsize,
+    sleep: Mutex<()>,
+    wake: Condvar,
+    shutdown: AtomicBool,
+}
+
+/// A worker thread's own state, on its stack for as long as it runs.
+struct WorkerThread {
+    registry: Arc<Registry>,
+    index: usize,
+    deque: Worker<Job>,
+    /// Xorshift state for picking victims.
+    rng: Cell<u64>,
+}
+
+thread_local! {
+    static WORKER: Cell<*const WorkerThread> = const { Cell::new(ptr::null()) };
+}
+
+impl ThreadPool {
+    /// Starts a pool of `threads` workers.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `threads` is zero or a thread can't be spawned.
+    pub fn new(threads: usize) -> Self {
+        assert!(threads > 0, "a thread pool needs at least one thread");
+        let deques: Vec<Worker<Job>> = (0..threads).map(|_| Worker::new()).collect();
+        let registry = Arc::new(Registry {
+            injector: Queue::new(),
+            stealers: deques.iter().map(Worker::stealer).collect(),
+            pending: AtomicUsize::new(0),
+            sleepers: AtomicUsize::new(0),
+            sleep: Mutex::new(()),
+            wake: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+        });
+        let threads = deques
+            .into_iter()
+            .enumerate()
+            .map(|(index, deque)| {
+                let worker = WorkerThread {
+                    registry: registry.clone(),
+                    index,
+                    deque,
+                    rng: Cell::new(0x9E37_79B9_7F4A_7C15 ^ (index as u64 + 1)),
+                };
+                thread::Builder::new()
+                    .name(format!("pool-{index}"))
+                    .spawn(move || worker.run())
+                    .expect("failed to spawn a pool thread")
+            })
+            .collect();
+        Self { registry, threads }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// Runs `f` on the pool, in the background. A panic in `f` is reported by the panic hook and
+    /// otherwise ignored.
+    pub fn spawn(&self, f: impl FnOnce() + Send + 'static) {
+        self.registry.push(Box::new(move || {
+            let _ = panic::catch_unwind(AssertUnwindSafe(f));
+        }));
+    }
+
+    /// Runs `f` on a pool thread and returns its result, so that [`join`] inside it is
+    /// parallel. Called from a pool thread, just runs `f`.
+    pub fn install<R: Send>(&self, f: impl FnOnce() -> R + Send) -> R {
+        if WorkerThread::current().is_some_and(|w| ptr::eq(&*w.registry, &*self.registry)) {
+            return f();
+        }
+        let latch = Latch::new();
+        let mut result = None;
+        let job = || {
+            result = Some(panic::catch_unwind(AssertUnwindSafe(f)));
+            latch.set();
+        };
+        // Safety: we wait for the latch, which the job sets last, before `result` and `latch`
+        // go out of scope.
+        self.registry.push(unsafe { erase(Box::new(job)) });
+        latch.wait();
+        match result.expect("the job sets the result before the latch") {
+            Ok(value) => value,
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+
+    /// Runs `a` and `b`, potentially in parallel, and returns both results. If either panics,
+    /// the panic is resumed once both are done.
+    pub fn join<A, B, RA, RB>(&self, a: A, b: B) -> (RA, RB)
+    where
+        A: FnOnce() -> RA + Send,
+        B: FnOnce() -> RB + Send,
+        RA: Send,
+        RB: Send,
+    {
+        self.install(|| join(a, b))
+    }
+
+    /// Like [`std::thread::scope`], but the spawned closures run as jobs on the pool.
+    pub fn scope<'env, F, T>(&self, f: F) -> T
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+    {
+        scope::scope(&self.registry, f)
+    }
+}
+
+/// Runs `a` and `b`, in parallel if called from a pool thread (see [`ThreadPool::join`]), one
+/// after the other if not.
+pub fn join<A, B, RA, RB>(a: A, b: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA + Send,
+    B: FnOnce() -> RB + Send,
+    RA: Send,
+    RB: Send,
+{
+    let Some(worker) = WorkerThread::current() else {
+        return (a(), b());
+    };
+    let latch = Latch::new();
+    let mut result_b = None;
+    let job_b = || {
+        result_b = Some(panic::catch_unwind(AssertUnwindSafe(b)));
+        latch.set();
+    };
+    // Safety: we wait for the latch, which the job sets last, before returning or unwinding.
+    worker.registry.push(unsafe { erase(Box::new(job_b)) });
+    let result_a = panic::catch_unwind(AssertUnwindSafe(a));
+    // Most of the time `b` is still at the bottom of our deque, and this pops and runs it.
+    latch.wait();
+    match (
+        result_a,
+        result_b.expect("the job sets the result before the latch"),
+    ) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(payload), _) | (_, Err(payload)) => panic::resume_unwind(payload),
+    }
+}
+
+/// The pool that parallel iterators run on when they're not already on one, with a thread per
+/// CPU. Started on first use.
+pub fn global() -> &'static ThreadPool {
+    static GLOBAL: OnceLock<ThreadPool> = OnceLock::new();
+    GLOBAL.get_or_init(|| ThreadPool::new(thread::available_parallelism().map_or(1, |n| n.get())))
+}
+
+/// The number of threads in the pool running the current thread, or 1 off the pools.
+pub fn current_num_threads() -> usize {
+    WorkerThread::current().map_or(1, |w| w.registry.stealers.len())
+}
+
+/// Runs `f` right here on a pool thread, or on the [`global`] pool if not on one.
+fn in_worker<R: Send>(f: impl FnOnce() -> R + Send) -> R {
+    match WorkerThread::current() {
+        Some(_) => f(),
+        None => global().install(f),
+    }
+}
+
+/// Turns a job that borrows into one the pool accepts.
+///
+/// # Safety
+///
+/// Whatever the job borrows must outlive its run, which the caller has to wait for.
+unsafe fn erase<'a>(job: Box<dyn FnOnce() + Send + 'a>) -> Job {
+    mem::transmute::<Box<dyn FnOnce() + Send + 'a>, Job>(job)
+}
+
+impl Registry {
+    /// Pushes onto the current worker's deque if it belongs to this pool, onto the injector if
+    /// not, and wakes a sleeping worker.
+    fn push(&self, job: Job) {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        match WorkerThread::current() {
+            Some(worker) if ptr::eq(&*worker.registry, self) => worker.deque.push(job),
+            _ => self.injector.push(job),
+        }
+        // SeqCst, paired with `sleep`: either we see the sleeper, or it sees our job.
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            let _lock = self.sleep.lock().unwrap_or_else(PoisonError::into_inner);
+            self.wake.notify_one();
+        }
+    }
+
+    /// Blocks until there may be work, or the pool shuts down.
+    fn sleep(&self) {
+        let mut lock = self.sleep.lock().unwrap_or_else(PoisonError::into_inner);
+        self.sleepers.fetch_add(1, Ordering::SeqCst);
+        while self.pending.load(Ordering::SeqCst) == 0 && !self.shutdown.load(Ordering::SeqCst) {
+            lock = self.wake.wait(lock).unwrap_or_else(PoisonError::into_inner);
+        }
+        self.sleepers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl WorkerThread {
+    /// The pool worker running on this thread, if any.
+    fn current<'a>() -> Option<&'a WorkerThread> {
+        // Safety: the pointer is set for exactly as long as `run` borrows the worker, and jobs
+        // (the only callers on a worker thread) run inside `run`.
+        unsafe { WORKER.get().as_ref() }
+    }
+
+    fn run(&self) {
+        WORKER.set(self);
+        loop {
+            if let Some(job) = self.find_work() {
+                job();
+            } else if self.registry.shutdown.load(Ordering::SeqCst) {
+                break;
+            } else {
+                self.registry.sleep();
+            }
+        }
+        WORKER.set(ptr::null());
+    }
+
+    /// Our own newest job, else the oldest injected one, else one stolen from another worker.
+    fn find_work(&self) -> Option<Job> {
+        let job = self
+            .deque
+            .pop()
+            .or_else(|| self.registry.injector.pop())
+            .or_else(|| self.steal());
+        if job.is_some() {
+            self.registry.pending.fetch_sub(1, Ordering::SeqCst);
+        }
+        job
+    }
+
+    fn steal(&self) -> Option<Job> {
+        let stealers = &self.registry.stealers;
+        loop {
+            let mut retry = false;
+            let start = self.next_random() as usize % stealers.len();
+            for i in (start..stealers.len()).chain(0..start) {
+                if i == self.index {
+                    continue;
+                }
+                match stealers[i].steal() {
+                    Steal::Success(job) => return Some(job),
+                    Steal::Retry => retry = true,
+                    Steal::Empty => {}
+                }
+            }
+            if !retry {
+                return None;
+            }
+        }
+    }
+
+    fn next_random(&self) -> u64 {
+        let mut x = self.rng.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng.set(x);
+        x
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.registry.shutdown.store(true, Ordering::SeqCst);
+        {
+            let _lock = self
+                .registry
+                .sleep
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            self.registry.wake.notify_all();
+        }
+        for thread in self.threads.drain(..) {
+            // Jobs catch their own panics, so workers don't panic.
+            let _ = thread.join();
+        }
+    }
+}
+
+impl std::fmt::Debug for ThreadPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ThreadPool")
+            .field("threads", &self.threads())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A one-shot completion flag for a job. The waiting thread runs other jobs meanwhile if it is a
+/// pool worker, and parks if it isn't.
+struct Latch {
+    set: AtomicBool,
+    owner: Thread,
+}
+
+impl Latch {
+    fn new() -> Self {
+        Self {
+            set: AtomicBool::new(false),
+            owner: thread::current(),
+        }
+    }
+
+    fn set(&self) {
+        // The owner may free the latch as soon as it sees it set.
+        let owner = self.owner.clone();
+        // Release: the job's results happen before `wait` returns.
+        self.set.store(true, Ordering::Release);
+        owner.unpark();
+    }
+
+    fn is_set(&self) -> bool {
+        self.set.load(Ordering::Acquire)
+    }
+
+    fn wait(&self) {
+        while !self.is_set() {
+            match WorkerThread::current() {
+                Some(worker) => match worker.find_work() {
+                    Some(job) => job(),
+                    None => thread::yield_now(),
+                },
+                None => thread::park(),
+            }
+        }
+    }
+}
+
+#[test]
+fn pool_spawn_runs_every_job() {
+    let pool = ThreadPool::new(3);
+    let (tx, rx) = std::sync::mpsc::channel();
+    for i in 0..100 {
+        let tx = tx.clone();
+        pool.spawn(move || tx.send(i).unwrap());
+    }
+    drop(tx);
+    let mut got: Vec<i32> = rx.iter().collect();
+    got.sort_unstable();
+    assert_eq!(got, (0..100).collect::<Vec<_>>());
+}
+
+#[test]
+fn pool_join_recursion() {
+    fn fib(n: u64) -> u64 {
+        if n < 2 {
+            return n;
+        }
+        let (a, b) = join(|| fib(n - 1), || fib(n - 2));
+        a + b
+    }
+    let pool = ThreadPool::new(4);
+    assert_eq!(pool.install(|| fib(20)), 6765);
+    // Outside the pool, `join` is sequential.
+    assert_eq!(fib(10), 55);
+}
+
+#[test]
+fn pool_join_resumes_panics_after_both_finish() {
+    let pool = ThreadPool::new(2);
+    let finished = AtomicBool::new(false);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        pool.join(
+            || panic!("boom"),
+            || {
+                thread::sleep(std::time::Duration::from_millis(20));
+                finished.store(true, Ordering::Relaxed);
+            },
+        )
+    }));
+    assert!(result.is_err());
+    assert!(finished.load(Ordering::Relaxed));
+    // The pool still works.
+    assert_eq!(pool.join(|| 1, || 2), (1, 2));
+}
@@ -0,0 +1,134 @@
+//! Scoped jobs: [`ThreadPool::scope`](super::ThreadPool::scope).
+
+use std::any::Any;
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use super::{erase, Latch, Registry};
+
+/// A scope to spawn borrowing jobs in, like [`std::thread::Scope`].
+pub struct Scope<'scope, 'env: 'scope> {
+    registry: Arc<Registry>,
+    data: Arc<ScopeData>,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+/// Shared with the jobs, which may still touch it after the scope has seen the count reach zero.
+struct ScopeData {
+    /// Unfinished jobs, plus one for the scope's own closure.
+    running: AtomicUsize,
+    /// The first panic of a job, resumed when the scope ends.
+    panic: Mutex<Option<Box<dyn Any + Send>>>,
+    done: Latch,
+}
+
+impl ScopeData {
+    fn finish_one(&self) {
+        // AcqRel: everything the jobs did happens before the scope returns.
+        if self.running.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.done.set();
+        }
+    }
+}
+
+pub(super) fn scope<'env, F, T>(registry: &Arc<Registry>, f: F) -> T
+where
+    F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+{
+    let scope = Scope {
+        registry: registry.clone(),
+        data: Arc::new(ScopeData {
+            running: AtomicUsize::new(1),
+            panic: Mutex::new(None),
+            done: Latch::new(),
+        }),
+        scope: PhantomData,
+        env: PhantomData,
+    };
+    let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+    scope.data.finish_one();
+    scope.data.done.wait();
+    let job_panic = scope
+        .data
+        .panic
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take();
+    match (result, job_panic) {
+        (Err(payload), _) | (Ok(_), Some(payload)) => panic::resume_unwind(payload),
+        (Ok(value), None) => value,
+    }
+}
+
+impl<'scope> Scope<'scope, '_> {
+    /// Runs `f` on the pool; the scope doesn't end before it has finished.
+    pub fn spawn<F>(&'scope self, f: F)
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        self.data.running.fetch_add(1, Ordering::Relaxed);
+        let data = self.data.clone();
+        let job = move || {
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(f)) {
+                data.panic
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .get_or_insert(payload);
+            }
+            data.finish_one();
+        };
+        // Safety: the scope waits for `running` to reach zero before it returns, so everything
+        // living for 'scope outlives the job.
+        self.registry.push(unsafe { erase(Box::new(job)) });
+    }
+}
+
+impl std::fmt::Debug for Scope<'_, '_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Scope")
+            .field("running", &self.data.running.load(Ordering::Relaxed))
+            .finish_non_exhaustive()
+    }
+}
+
+#[test]
+fn scope_jobs_borrow_and_nest() {
+    let pool = super::ThreadPool::new(3);
+    let mut numbers: Vec<u64> = (0..1_000).collect();
+    let total = AtomicUsize::new(0);
+    pool.scope(|s| {
+        for chunk in numbers.chunks_mut(100) {
+            let total = &total;
+            s.spawn(move || {
+                for n in chunk.iter_mut() {
+                    *n *= 2;
+                }
+                // Jobs can spawn more jobs into the same scope.
+                s.spawn(move || {
+                    total.fetch_add(1, Ordering::Relaxed);
+                });
+            });
+        }
+    });
+    assert_eq!(total.load(Ordering::Relaxed), 10);
+    assert!(numbers.iter().enumerate().all(|(i, &n)| n == 2 * i as u64));
+}
+
+#[test]
+fn scope_resumes_job_panics() {
+    let pool = super::ThreadPool::new(2);
+    let ran = AtomicUsize::new(0);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        pool.scope(|s| {
+            s.spawn(|| panic!("boom"));
+            s.spawn(|| {
+                ran.fetch_add(1, Ordering::Relaxed);
+            });
+        })
+    }));
+    assert!(result.is_err());
+    assert_eq!(ran.load(Ordering::Relaxed), 1);
+}
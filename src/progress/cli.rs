@@ -0,0 +1,50 @@
+//! What the `atomics_progress_reporting_*` binaries share: their command line, and typing `stop`
+//! to cancel the remaining work.
+
+#[cfg(target_os = "linux")]
+use crate::sync::CancellationToken;
+
+/// Parses `N` optional numbers followed by an optional `--json`, in any order, falling back to
+/// `defaults` for the numbers that are missing. Returns the numbers and whether `--json` was given.
+///
+/// # Panics
+///
+/// Panics with `usage` on anything else.
+pub fn parse_args<const N: usize>(usage: &str, defaults: [u64; N]) -> ([u64; N], bool) {
+    let mut json = false;
+    let mut numbers = defaults;
+    let mut given = 0;
+    for arg in std::env::args().skip(1) {
+        if arg == "--json" {
+            json = true;
+        } else {
+            let slot = numbers.get_mut(given);
+            *slot.unwrap_or_else(|| panic!("usage: {usage}")) =
+                arg.parse().unwrap_or_else(|_| panic!("usage: {usage}"));
+            given += 1;
+        }
+    }
+    (numbers, json)
+}
+
+/// A token that is cancelled when a line reading `stop` arrives on stdin.
+///
+/// The listener thread is detached: it may still be blocked reading stdin when the work
+/// finishes, and exiting the process ends it.
+#[cfg(target_os = "linux")]
+pub fn stop_on_stdin() -> CancellationToken {
+    let stop = CancellationToken::new();
+    std::thread::spawn({
+        let stop = stop.clone();
+        move || {
+            if std::io::stdin()
+                .lines()
+                .map_while(Result::ok)
+                .any(|l| l == "stop")
+            {
+                stop.cancel();
+            }
+        }
+    });
+    stop
+}
@@ -0,0 +1,111 @@
+//! A lock-free log-linear histogram.
+//!
+//! Each power of two is split into four equal sub-buckets, so any recorded value lands in a
+//! bucket at most 25% wider than itself. 252 buckets cover the whole `u64` range, which is enough
+//! to hold durations in nanoseconds without any configuration.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const SUB_BUCKET_BITS: u32 = 2;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+/// Values below `SUB_BUCKETS` get a bucket each; every power of two above that gets `SUB_BUCKETS`.
+pub const BUCKETS: usize = ((64 - SUB_BUCKET_BITS + 1) << SUB_BUCKET_BITS) as usize;
+
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS],
+}
+
+impl Histogram {
+    pub const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS],
+        }
+    }
+
+    pub fn record(&self, value: u64) {
+        self.buckets[bucket_index(value)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Copies the current bucket counts, for computing several percentiles from one snapshot.
+    pub fn counts(&self) -> [u64; BUCKETS] {
+        std::array::from_fn(|i| self.buckets[i].load(Ordering::Relaxed))
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The value below which a fraction `q` of the samples in `counts` fall, rounded up to the upper
+/// edge of its bucket. Returns `None` for an empty histogram.
+pub fn percentile(counts: &[u64; BUCKETS], q: f64) -> Option<u64> {
+    let total: u64 = counts.iter().sum();
+    if total == 0 {
+        return None;
+    }
+    let rank = ((q.clamp(0.0, 1.0) * total as f64).ceil() as u64).max(1);
+    let mut seen = 0;
+    for (i, &n) in counts.iter().enumerate() {
+        seen += n;
+        if seen >= rank {
+            return Some(bucket_upper(i));
+        }
+    }
+    unreachable!("rank never exceeds the total count")
+}
+
+pub fn bucket_index(value: u64) -> usize {
+    if value < SUB_BUCKETS {
+        return value as usize;
+    }
+    let exp = 63 - value.leading_zeros();
+    let sub = (value >> (exp - SUB_BUCKET_BITS)) & (SUB_BUCKETS - 1);
+    (((exp - SUB_BUCKET_BITS + 1) << SUB_BUCKET_BITS) as u64 | sub) as usize
+}
+
+pub fn bucket_lower(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+        return index;
+    }
+    let exp = (index >> SUB_BUCKET_BITS) as u32 + SUB_BUCKET_BITS - 1;
+    let sub = index & (SUB_BUCKETS - 1);
+    (SUB_BUCKETS | sub) << (exp - SUB_BUCKET_BITS)
+}
+
+pub fn bucket_upper(index: usize) -> u64 {
+    if index + 1 == BUCKETS {
+        u64::MAX
+    } else {
+        bucket_lower(index + 1) - 1
+    }
+}
+
+#[test]
+fn histogram_buckets_cover_values() {
+    for v in (0..10_000).chain([u64::MAX / 3, u64::MAX - 1, u64::MAX]) {
+        let i = bucket_index(v);
+        assert!(
+            bucket_lower(i) <= v && v <= bucket_upper(i),
+            "{v} not in bucket {i}"
+        );
+    }
+    assert_eq!(bucket_index(u64::MAX), BUCKETS - 1);
+}
+
+#[test]
+fn histogram_percentiles() {
+    let h = Histogram::new();
+    for v in 1..=1000 {
+        h.record(v);
+    }
+    let counts = h.counts();
+    let p50 = percentile(&counts, 0.5).unwrap();
+    let p99 = percentile(&counts, 0.99).unwrap();
+    // Within the 25% bucket resolution of the exact answers.
+    assert!((500..=625).contains(&p50), "p50 = {p50}");
+    assert!((990..=1250).contains(&p99), "p99 = {p99}");
+    assert_eq!(percentile(&[0; BUCKETS], 0.5), None);
+}
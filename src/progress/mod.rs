@@ -0,0 +1,333 @@
+//! Progress reporting for a fixed amount of work shared between threads.
+//!
+//! This is the `atomics_progress_reporting_*` experiments turned into a library. Workers call
+//! [`Tracker::record`] with how long each item took; that updates the count and the totals of the
+//! worker's own [shard](crate::stats) under that shard's [`SeqLock`], so workers on different
+//! shards never wait for each other. A [`Snapshot`] reads every shard consistently and adds them
+//! up: each item is in exactly one shard, so the count, sum, min and max always describe the same
+//! items, even if that's not quite the items of any one moment. The histogram behind the
+//! percentiles is sharded the same way.
+//!
+//! The one write all workers share is a relaxed counter of finished items, so that the worker
+//! recording the last one knows it and unparks the observer. An observer thread calls
+//! [`Tracker::observe`] to get a snapshot every interval, and so doesn't sleep out the rest of its
+//! interval once the work is done.
+
+pub mod cli;
+pub mod histogram;
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+use crate::stats::{ShardedHistogram, Shards};
+#[cfg(target_os = "linux")]
+use crate::sync::CancellationToken;
+use crate::sync::{SeqLock, SpinLock};
+
+pub struct Tracker {
+    total: u64,
+    start: Instant,
+    stats: Shards<SeqLock<Stats>>,
+    /// How many items were recorded, across all shards.
+    recorded: AtomicU64,
+    histogram: ShardedHistogram,
+    observer: SpinLock<Option<Thread>>,
+}
+
+impl Tracker {
+    /// Tracks `total` items of work, starting the clock now.
+    pub fn new(total: u64) -> Self {
+        Self {
+            total,
+            start: Instant::now(),
+            stats: Shards::new(|| SeqLock::new(Stats::EMPTY)),
+            recorded: AtomicU64::new(0),
+            histogram: ShardedHistogram::new(),
+            observer: SpinLock::new(None),
+        }
+    }
+
+    /// Records one finished item that took `time`.
+    pub fn record(&self, time: Duration) {
+        let nanos = u64::try_from(time.as_nanos()).unwrap_or(u64::MAX);
+        self.histogram.record(nanos);
+        // Only threads that share this shard can contend here.
+        self.stats.local().write(|stats| {
+            *stats = stats.combine(Stats {
+                count: 1,
+                sum_nanos: nanos,
+                min_nanos: nanos,
+                max_nanos: nanos,
+            })
+        });
+        // Release: whoever sees the final count also sees every shard's last write, through the
+        // release sequence of these increments.
+        if self.recorded.fetch_add(1, Ordering::Release) + 1 == self.total {
+            if let Some(observer) = &*self.observer.lock() {
+                observer.unpark();
+            }
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.recorded.load(Ordering::Acquire) >= self.total
+    }
+
+    /// The current statistics. Count, mean, min and max always belong together; the percentiles
+    /// come from the histogram, which is read separately and may include a few more items.
+    pub fn snapshot(&self) -> Snapshot {
+        let Stats {
+            count,
+            sum_nanos,
+            min_nanos,
+            max_nanos: max,
+        } = self
+            .stats
+            .iter()
+            .map(SeqLock::read)
+            .fold(Stats::EMPTY, Stats::combine);
+        let counts = self.histogram.counts();
+        let elapsed = self.start.elapsed();
+        let stat = |nanos: u64| (count > 0).then(|| Duration::from_nanos(nanos));
+        // Percentiles are bucket edges; don't let rounding report more than was ever observed.
+        let percentile =
+            |q| histogram::percentile(&counts, q).map(|p| Duration::from_nanos(p.min(max)));
+        Snapshot {
+            count,
+            total: self.total,
+            elapsed,
+            mean: stat(sum_nanos.checked_div(count).unwrap_or(0)),
+            min: stat(min_nanos),
+            max: stat(max),
+            p50: percentile(0.50),
+            p99: percentile(0.99),
+            eta: (count > 0)
+                .then(|| elapsed.mul_f64(self.total.saturating_sub(count) as f64 / count as f64)),
+        }
+    }
+
+    /// Calls `report` with a fresh snapshot every `interval` until all work is recorded, then
+    /// once more with the final snapshot.
+    ///
+    /// Only one thread should observe a tracker at a time.
+    pub fn observe(&self, interval: Duration, report: impl FnMut(&Snapshot)) {
+        self.observe_while(interval, || true, report);
+    }
+
+    /// Like [`Tracker::observe`], but also stops (after one last report) as soon as `token` is
+    /// cancelled, for when the workers give up before all work is recorded.
+    #[cfg(target_os = "linux")]
+    pub fn observe_until(
+        &self,
+        token: &CancellationToken,
+        interval: Duration,
+        report: impl FnMut(&Snapshot),
+    ) {
+        let observer = thread::current();
+        // Unregistered when we return, so observing a long-lived token repeatedly doesn't pile
+        // up callbacks on it.
+        let _registration = token.on_cancel(move || observer.unpark());
+        self.observe_while(interval, || !token.is_cancelled(), report);
+    }
+
+    fn observe_while(
+        &self,
+        interval: Duration,
+        keep_going: impl Fn() -> bool,
+        mut report: impl FnMut(&Snapshot),
+    ) {
+        *self.observer.lock() = Some(thread::current());
+        while !self.is_done() && keep_going() {
+            report(&self.snapshot());
+            // `record` unparks us when the last item finishes; checking `is_done` again after
+            // waking covers both that and a spurious wake-up.
+            thread::park_timeout(interval);
+        }
+        *self.observer.lock() = None;
+        report(&self.snapshot());
+    }
+}
+
+/// What a [`Tracker`] updates atomically with each recorded item, per shard.
+#[derive(Clone, Copy)]
+struct Stats {
+    count: u64,
+    sum_nanos: u64,
+    min_nanos: u64,
+    max_nanos: u64,
+}
+
+impl Stats {
+    const EMPTY: Self = Self {
+        count: 0,
+        sum_nanos: 0,
+        min_nanos: u64::MAX,
+        max_nanos: 0,
+    };
+
+    /// The statistics of both sets of items together.
+    fn combine(self, other: Self) -> Self {
+        Self {
+            count: self.count + other.count,
+            sum_nanos: self.sum_nanos.saturating_add(other.sum_nanos),
+            min_nanos: self.min_nanos.min(other.min_nanos),
+            max_nanos: self.max_nanos.max(other.max_nanos),
+        }
+    }
+}
+
+/// A point-in-time view of a [`Tracker`]. Statistics are `None` until an item is recorded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub count: u64,
+    pub total: u64,
+    pub elapsed: Duration,
+    pub mean: Option<Duration>,
+    pub min: Option<Duration>,
+    pub max: Option<Duration>,
+    pub p50: Option<Duration>,
+    pub p99: Option<Duration>,
+    pub eta: Option<Duration>,
+}
+
+impl Snapshot {
+    pub fn is_done(&self) -> bool {
+        self.count >= self.total
+    }
+
+    /// One JSON object per snapshot, with durations in milliseconds.
+    pub fn to_json(&self) -> String {
+        fn ms(d: Option<Duration>) -> String {
+            d.map_or_else(
+                || "null".into(),
+                |d| format!("{:.3}", d.as_secs_f64() * 1e3),
+            )
+        }
+        format!(
+            concat!(
+                "{{\"count\":{},\"total\":{},\"elapsed_ms\":{},\"mean_ms\":{},\"min_ms\":{},",
+                "\"max_ms\":{},\"p50_ms\":{},\"p99_ms\":{},\"eta_ms\":{}}}"
+            ),
+            self.count,
+            self.total,
+            ms(Some(self.elapsed)),
+            ms(self.mean),
+            ms(self.min),
+            ms(self.max),
+            ms(self.p50),
+            ms(self.p99),
+            ms(self.eta),
+        )
+    }
+}
+
+/// A single terminal line, e.g.
+/// `Progress: 40/100 done, 120ms mean, 10ms min, 250ms max, p50 125ms, p99 250ms, ETA 3.2s`.
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Progress: {}/{} done", self.count, self.total)?;
+        let (Some(mean), Some(min), Some(max), Some(p50), Some(p99)) =
+            (self.mean, self.min, self.max, self.p50, self.p99)
+        else {
+            return f.write_str(", nothing done yet");
+        };
+        write!(
+            f,
+            ", {mean:.0?} mean, {min:.0?} min, {max:.0?} max, p50 {p50:.0?}, p99 {p99:.0?}"
+        )?;
+        match self.eta {
+            Some(eta) if !self.is_done() => write!(f, ", ETA {eta:.1?}"),
+            _ => write!(f, ", took {:.1?}", self.elapsed),
+        }
+    }
+}
+
+#[test]
+fn tracker_statistics() {
+    let tracker = Tracker::new(4);
+    assert_eq!(tracker.snapshot().mean, None);
+    assert_eq!(
+        tracker.snapshot().to_string(),
+        "Progress: 0/4 done, nothing done yet"
+    );
+
+    for ms in [10, 20, 30] {
+        tracker.record(Duration::from_millis(ms));
+    }
+    let s = tracker.snapshot();
+    assert_eq!(s.count, 3);
+    assert_eq!(s.mean, Some(Duration::from_millis(20)));
+    assert_eq!(s.min, Some(Duration::from_millis(10)));
+    assert_eq!(s.max, Some(Duration::from_millis(30)));
+    assert!(s.p50.unwrap() >= Duration::from_millis(20));
+    assert!(s.eta.is_some());
+    assert!(s.to_json().starts_with("{\"count\":3,\"total\":4,"));
+}
+
+#[test]
+fn tracker_snapshots_are_consistent_across_shards() {
+    // Each worker records its own duration, so a snapshot whose sum doesn't match its count (or
+    // whose min and max don't match the workers) mixed up two moments.
+    let tracker = Tracker::new(4 * 2_000);
+    thread::scope(|s| {
+        for ms in 1..=4 {
+            let tracker = &tracker;
+            s.spawn(move || {
+                for _ in 0..2_000 {
+                    tracker.record(Duration::from_millis(ms));
+                }
+            });
+        }
+        while !tracker.is_done() {
+            let snapshot = tracker.snapshot();
+            if let (Some(mean), Some(min), Some(max)) = (snapshot.mean, snapshot.min, snapshot.max)
+            {
+                assert!(min <= mean && mean <= max);
+                assert!(min >= Duration::from_millis(1) && max <= Duration::from_millis(4));
+            }
+        }
+    });
+    let snapshot = tracker.snapshot();
+    assert_eq!(snapshot.count, 8_000);
+    assert_eq!(snapshot.mean, Some(Duration::from_micros(2_500)));
+}
+
+#[test]
+fn tracker_observer_is_woken_when_done() {
+    let tracker = Tracker::new(8);
+    let start = Instant::now();
+    let mut reports = 0;
+    thread::scope(|s| {
+        s.spawn(|| {
+            for _ in 0..8 {
+                thread::sleep(Duration::from_millis(5));
+                tracker.record(Duration::from_millis(5));
+            }
+        });
+        // Far longer than the work takes: only the unpark can end the wait early.
+        tracker.observe(Duration::from_secs(30), |_| reports += 1);
+    });
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert!(reports >= 2);
+    assert!(tracker.snapshot().is_done());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn tracker_observer_stops_on_cancel() {
+    let tracker = Tracker::new(8);
+    let token = CancellationToken::new();
+    let start = Instant::now();
+    thread::scope(|s| {
+        s.spawn(|| {
+            tracker.record(Duration::from_millis(5));
+            thread::sleep(Duration::from_millis(20));
+            token.cancel();
+        });
+        tracker.observe_until(&token, Duration::from_secs(30), |_| {});
+    });
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert!(!tracker.is_done());
+}
@@ -0,0 +1,183 @@
+//! Atomics that tell the race detector about the synchronisation they do.
+//!
+//! Each one pairs a real atomic with the clock published by the store it currently holds. Both
+//! are updated under a small lock, so a load always takes in the clock of the store it read.
+
+use std::sync::atomic::{self, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use super::with_thread;
+use crate::clock::VectorClock;
+
+/// The clock released by the latest store, if it released anything.
+struct Published(Mutex<Option<VectorClock>>);
+
+impl Published {
+    const fn new() -> Self {
+        Self(Mutex::new(None))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<VectorClock>> {
+        // Only clock bookkeeping happens under the lock; it can't be left half-updated.
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn load<T>(&self, order: Ordering, load: impl FnOnce() -> T) -> T {
+        let published = self.lock();
+        let value = load();
+        with_thread(|t| t.acquire(published.as_ref(), order));
+        value
+    }
+
+    fn store(&self, order: Ordering, store: impl FnOnce()) {
+        let mut published = self.lock();
+        store();
+        *published = with_thread(|t| t.release(order));
+    }
+
+    /// A read-modify-write: acquires what the replaced store published, and keeps publishing it
+    /// along with its own release (a release sequence). A failed one is only a load, ordered by
+    /// `failure`.
+    fn rmw<T>(
+        &self,
+        success: Ordering,
+        failure: Ordering,
+        rmw: impl FnOnce() -> Result<T, T>,
+    ) -> Result<T, T> {
+        let mut published = self.lock();
+        let result = rmw();
+        with_thread(|t| {
+            if result.is_ok() {
+                t.acquire(published.as_ref(), success);
+                if let Some(released) = t.release(success) {
+                    published
+                        .get_or_insert_with(VectorClock::new)
+                        .join(&released);
+                }
+            } else {
+                t.acquire(published.as_ref(), failure);
+            }
+        });
+        result
+    }
+}
+
+pub struct AtomicBool {
+    value: atomic::AtomicBool,
+    published: Published,
+}
+
+impl AtomicBool {
+    pub const fn new(v: bool) -> Self {
+        Self {
+            value: atomic::AtomicBool::new(v),
+            published: Published::new(),
+        }
+    }
+
+    pub fn load(&self, order: Ordering) -> bool {
+        self.published.load(order, || self.value.load(order))
+    }
+
+    pub fn store(&self, v: bool, order: Ordering) {
+        self.published.store(order, || self.value.store(v, order));
+    }
+
+    pub fn swap(&self, v: bool, order: Ordering) -> bool {
+        let rmw = || Ok(self.value.swap(v, order));
+        self.published.rmw(order, order, rmw).unwrap_or_else(|v| v)
+    }
+
+    pub fn compare_exchange(
+        &self,
+        current: bool,
+        new: bool,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<bool, bool> {
+        let rmw = || self.value.compare_exchange(current, new, success, failure);
+        self.published.rmw(success, failure, rmw)
+    }
+}
+
+macro_rules! race_atomic_int {
+    ($($atomic:ident: $int:ty),* $(,)?) => {$(
+        pub struct $atomic {
+            value: atomic::$atomic,
+            published: Published,
+        }
+
+        impl $atomic {
+            pub const fn new(v: $int) -> Self {
+                Self {
+                    value: atomic::$atomic::new(v),
+                    published: Published::new(),
+                }
+            }
+
+            pub fn load(&self, order: Ordering) -> $int {
+                self.published.load(order, || self.value.load(order))
+            }
+
+            pub fn store(&self, v: $int, order: Ordering) {
+                self.published.store(order, || self.value.store(v, order));
+            }
+
+            pub fn swap(&self, v: $int, order: Ordering) -> $int {
+                let rmw = || Ok(self.value.swap(v, order));
+                self.published.rmw(order, order, rmw).unwrap_or_else(|v| v)
+            }
+
+            pub fn compare_exchange(
+                &self,
+                current: $int,
+                new: $int,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<$int, $int> {
+                let rmw = || self.value.compare_exchange(current, new, success, failure);
+                self.published.rmw(success, failure, rmw)
+            }
+
+            pub fn fetch_add(&self, v: $int, order: Ordering) -> $int {
+                let rmw = || Ok(self.value.fetch_add(v, order));
+                self.published.rmw(order, order, rmw).unwrap_or_else(|v| v)
+            }
+        }
+    )*};
+}
+
+race_atomic_int! {
+    AtomicU32: u32,
+    AtomicU64: u64,
+    AtomicUsize: usize,
+}
+
+static SC_FENCES: Mutex<VectorClock> = Mutex::new(VectorClock::new());
+
+/// A fence. An `Acquire` fence takes in what the relaxed loads before it read; a `Release` fence
+/// is published by the relaxed stores after it. `SeqCst` fences also synchronise with each other.
+pub fn fence(order: Ordering) {
+    atomic::fence(order);
+    with_thread(|t| {
+        if order == Ordering::SeqCst {
+            let mut sc = SC_FENCES.lock().unwrap_or_else(PoisonError::into_inner);
+            t.clock.join(&sc);
+            sc.join(&t.clock);
+        }
+        if matches!(
+            order,
+            Ordering::Acquire | Ordering::AcqRel | Ordering::SeqCst
+        ) {
+            let pending = std::mem::take(&mut t.acquire_fence);
+            t.clock.join(&pending);
+        }
+        if matches!(
+            order,
+            Ordering::Release | Ordering::AcqRel | Ordering::SeqCst
+        ) {
+            t.release_fence = Some(t.clock.clone());
+            t.clock.tick(t.id);
+        }
+    });
+}
@@ -0,0 +1,317 @@
+//! A happens-before data race detector for non-atomic data that atomics are supposed to guard.
+//!
+//! `fences.rs`, `release_acquire_ordering.rs` and `sequentially_consistent_ordering.rs` keep data
+//! in a `static mut` and rely on atomics to order the accesses to it. Get an ordering wrong and
+//! the program has undefined behaviour that usually still prints the right answer. [`Shared<T>`]
+//! is a replacement for such a `static mut` whose every access is checked against a vector clock:
+//! each thread counts its operations, and release/acquire pairs on the atomics of this module
+//! ([`AtomicBool`] and friends, [`fence`], [`thread::spawn`] and `join`) carry those counts from
+//! one thread to another. An access to a `Shared` that doesn't happen after the previous
+//! conflicting one panics with the source locations of both.
+//!
+//! The detector checks the execution that actually happens, not every possible one (that's what
+//! [`model`](crate::model) is for), but it catches a missing synchronisation even when the timing
+//! happened to work out.
+
+mod atomic;
+pub mod thread;
+
+use std::cell::{RefCell, UnsafeCell};
+use std::fmt;
+use std::panic::Location;
+use std::sync::atomic::Ordering;
+use std::sync::{Mutex, PoisonError};
+
+use crate::clock::VectorClock;
+pub use atomic::{fence, AtomicBool, AtomicU32, AtomicU64, AtomicUsize};
+
+/// A value that may be accessed from several threads, as long as the accesses are ordered by
+/// happens-before.
+pub struct Shared<T> {
+    value: UnsafeCell<T>,
+    log: Mutex<AccessLog>,
+}
+
+// Safety: every access first checks, under `log`, that it happens after all conflicting accesses
+// recorded so far (and panics instead of touching the value if it doesn't). A write that passes
+// is therefore never concurrent with any other access, which is what `&mut T` needs, and `T: Send`
+// because the value may be written from any thread. Reads don't conflict with each other, so
+// several threads may hold a `&T` at once: that needs `T: Sync`.
+unsafe impl<T: Send + Sync> Sync for Shared<T> {}
+
+struct AccessLog {
+    write: Option<Access>,
+    /// The latest read of each thread since `write`.
+    reads: Vec<Access>,
+}
+
+#[derive(Clone, Copy)]
+struct Access {
+    thread: usize,
+    time: u32,
+    location: &'static Location<'static>,
+    write: bool,
+}
+
+impl<T> Shared<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            value: UnsafeCell::new(value),
+            log: Mutex::new(AccessLog {
+                write: None,
+                reads: Vec::new(),
+            }),
+        }
+    }
+
+    /// Runs `f` on the value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the last write doesn't happen before this read.
+    #[track_caller]
+    pub fn read<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        self.access(false, Location::caller());
+        // Safety: `access` checked there is no conflicting access, see the `Sync` impl.
+        f(unsafe { &*self.value.get() })
+    }
+
+    /// Runs `f` on the value, mutably.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the last write or any read since doesn't happen before this write.
+    #[track_caller]
+    pub fn write<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        self.access(true, Location::caller());
+        // Safety: `access` checked there is no conflicting access, see the `Sync` impl.
+        f(unsafe { &mut *self.value.get() })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    fn access(&self, write: bool, location: &'static Location<'static>) {
+        // A panicking access leaves the log as it was, so a poisoned log is still consistent.
+        let mut log = self.log.lock().unwrap_or_else(PoisonError::into_inner);
+        with_thread(|thread| {
+            let access = Access {
+                thread: thread.id,
+                time: thread.clock.get(thread.id),
+                location,
+                write,
+            };
+            let conflicts = log
+                .write
+                .iter()
+                .chain(if write { &log.reads[..] } else { &[] });
+            for earlier in conflicts {
+                if earlier.thread != thread.id && thread.clock.get(earlier.thread) < earlier.time {
+                    panic!("data race: {access} is not ordered after {earlier}");
+                }
+            }
+            if write {
+                log.write = Some(access);
+                log.reads.clear();
+            } else if let Some(read) = log.reads.iter_mut().find(|r| r.thread == thread.id) {
+                *read = access;
+            } else {
+                log.reads.push(access);
+            }
+        });
+    }
+}
+
+impl<T: Default> Default for Shared<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> fmt::Debug for Shared<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Printing the value would be an unchecked read.
+        f.pad("Shared { .. }")
+    }
+}
+
+/// E.g. `write at src/atomics_locks/fences.rs:18:9 on thread 3`.
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = if self.write { "write" } else { "read" };
+        write!(f, "{kind} at {} on thread {}", self.location, self.thread)
+    }
+}
+
+/// What the detector knows about the current thread.
+struct ThreadClock {
+    id: usize,
+    clock: VectorClock,
+    /// Release clocks of the stores read by relaxed loads, for a later `Acquire` fence.
+    acquire_fence: VectorClock,
+    /// The clock at the last `Release` fence, released by later relaxed stores.
+    release_fence: Option<VectorClock>,
+}
+
+thread_local! {
+    static THREAD: RefCell<Option<ThreadClock>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` on the current thread's clock, registering the thread on first use.
+fn with_thread<R>(f: impl FnOnce(&mut ThreadClock) -> R) -> R {
+    THREAD.with_borrow_mut(|thread| f(thread.get_or_insert_with(|| ThreadClock::new(None))))
+}
+
+impl ThreadClock {
+    /// A new thread, starting after `parent` if it was spawned by an instrumented spawn.
+    fn new(parent: Option<&VectorClock>) -> Self {
+        static NEXT_ID: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let mut clock = parent.cloned().unwrap_or_default();
+        clock.tick(id);
+        Self {
+            id,
+            clock,
+            acquire_fence: VectorClock::new(),
+            release_fence: None,
+        }
+    }
+
+    /// The clock to publish with a store of ordering `order`. Releasing starts a new epoch, so
+    /// that later accesses of this thread aren't covered by it.
+    fn release(&mut self, order: Ordering) -> Option<VectorClock> {
+        if matches!(
+            order,
+            Ordering::Release | Ordering::AcqRel | Ordering::SeqCst
+        ) {
+            let clock = self.clock.clone();
+            self.clock.tick(self.id);
+            Some(clock)
+        } else {
+            self.release_fence.clone()
+        }
+    }
+
+    /// Takes in the clock published by the store that a load of ordering `order` read.
+    fn acquire(&mut self, published: Option<&VectorClock>, order: Ordering) {
+        let Some(published) = published else {
+            return;
+        };
+        if matches!(
+            order,
+            Ordering::Acquire | Ordering::AcqRel | Ordering::SeqCst
+        ) {
+            self.clock.join(published);
+        } else {
+            self.acquire_fence.join(published);
+        }
+    }
+}
+
+#[cfg(test)]
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    payload
+        .downcast_ref::<String>()
+        .cloned()
+        .unwrap_or_default()
+}
+
+#[test]
+fn race_release_acquire_orders_accesses() {
+    let data = std::sync::Arc::new(Shared::new(0));
+    let ready = std::sync::Arc::new(AtomicBool::new(false));
+    let t = thread::spawn({
+        let (data, ready) = (data.clone(), ready.clone());
+        move || {
+            data.write(|d| *d = 42);
+            ready.store(true, Ordering::Release);
+        }
+    });
+    while !ready.load(Ordering::Acquire) {
+        std::hint::spin_loop();
+    }
+    assert_eq!(data.read(|d| *d), 42);
+    t.join().unwrap();
+    // After the join, even a write is fine.
+    data.write(|d| *d += 1);
+}
+
+#[test]
+fn race_relaxed_flag_is_reported_with_both_locations() {
+    let data = std::sync::Arc::new(Shared::new(0));
+    let ready = std::sync::Arc::new(AtomicBool::new(false));
+    let t = thread::spawn({
+        let (data, ready) = (data.clone(), ready.clone());
+        move || {
+            data.write(|d| *d = 42);
+            ready.store(true, Ordering::Relaxed);
+        }
+    });
+    while !ready.load(Ordering::Relaxed) {
+        std::hint::spin_loop();
+    }
+    let payload =
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| data.read(|d| *d))).unwrap_err();
+    let message = panic_message(payload);
+    assert!(
+        message.starts_with("data race: read at src/race/mod.rs:"),
+        "{message}"
+    );
+    assert!(
+        message.contains("not ordered after write at src/race/mod.rs:"),
+        "{message}"
+    );
+    t.join().unwrap();
+}
+
+#[test]
+fn race_fence_pairs_with_relaxed_flag() {
+    let data = std::sync::Arc::new(Shared::new(0));
+    let ready = std::sync::Arc::new(AtomicBool::new(false));
+    let t = thread::spawn({
+        let (data, ready) = (data.clone(), ready.clone());
+        move || {
+            data.write(|d| *d = 7);
+            fence(Ordering::Release);
+            ready.store(true, Ordering::Relaxed);
+        }
+    });
+    while !ready.load(Ordering::Relaxed) {
+        std::hint::spin_loop();
+    }
+    fence(Ordering::Acquire);
+    assert_eq!(data.read(|d| *d), 7);
+    t.join().unwrap();
+}
+
+#[test]
+fn race_failed_compare_exchange_orders_by_failure() {
+    let data = std::sync::Arc::new(Shared::new(0));
+    let ready = std::sync::Arc::new(AtomicBool::new(false));
+    let t = thread::spawn({
+        let (data, ready) = (data.clone(), ready.clone());
+        move || {
+            data.write(|d| *d = 42);
+            ready.store(true, Ordering::Release);
+        }
+    });
+    // Fails once the flag is set, and a `Relaxed` failure acquires nothing.
+    while ready
+        .compare_exchange(false, false, Ordering::AcqRel, Ordering::Relaxed)
+        .is_ok()
+    {
+        std::hint::spin_loop();
+    }
+    let payload =
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| data.read(|d| *d))).unwrap_err();
+    assert!(
+        panic_message(payload).starts_with("data race: read at src/race/mod.rs:"),
+        "failed CAS synchronised"
+    );
+    t.join().unwrap();
+}
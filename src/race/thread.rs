@@ -0,0 +1,50 @@
+//! Thread spawning and joining that the race detector knows about: everything before `spawn`
+//! happens before the new thread starts, and everything the thread does happens before `join`
+//! returns.
+
+use std::sync::{Arc, Mutex, PoisonError};
+
+use super::{with_thread, ThreadClock, THREAD};
+use crate::clock::VectorClock;
+
+#[derive(Debug)]
+pub struct JoinHandle<T> {
+    inner: std::thread::JoinHandle<T>,
+    /// The thread's clock when it finished.
+    clock: Arc<Mutex<Option<VectorClock>>>,
+}
+
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let parent = with_thread(|t| t.release(std::sync::atomic::Ordering::Release));
+    let clock = Arc::new(Mutex::new(None));
+    let finished = clock.clone();
+    let inner = std::thread::spawn(move || {
+        THREAD.set(Some(ThreadClock::new(parent.as_ref())));
+        let value = f();
+        *finished.lock().unwrap_or_else(PoisonError::into_inner) =
+            with_thread(|t| t.release(std::sync::atomic::Ordering::Release));
+        value
+    });
+    JoinHandle { inner, clock }
+}
+
+impl<T> JoinHandle<T> {
+    pub fn join(self) -> std::thread::Result<T> {
+        let value = self.inner.join()?;
+        let clock = self
+            .clock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        with_thread(|t| {
+            if let Some(clock) = clock {
+                t.clock.join(&clock);
+            }
+        });
+        Ok(value)
+    }
+}
@@ -0,0 +1,123 @@
+//! A `Box<T>` that can be replaced while other threads read it.
+//!
+//! [`RaceOnce`](crate::sync::RaceOnce) publishes a pointer once and keeps the box alive as long as
+//! itself, because nothing tells it when the last reader of an old box is done. Here readers
+//! [`pin`](super::pin) first, so a replaced box can be retired and dropped once they all have
+//! unpinned.
+
+use std::fmt;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use super::{pin, Guard};
+
+pub struct AtomicBox<T> {
+    /// Never null.
+    ptr: AtomicPtr<T>,
+}
+
+// Safety: an `AtomicBox<T>` owns a `Box<T>` that can be built on one thread and dropped on
+// another (T: Send), and it hands out `&T` to every thread (T: Sync).
+unsafe impl<T: Send + Sync> Sync for AtomicBox<T> {}
+unsafe impl<T: Send> Send for AtomicBox<T> {}
+
+impl<T> AtomicBox<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            ptr: AtomicPtr::new(Box::into_raw(Box::new(value))),
+        }
+    }
+
+    /// The current value, valid for as long as the thread stays pinned (and the `AtomicBox`
+    /// lives, since dropping it drops the current value straight away).
+    pub fn load<'g>(&'g self, _guard: &'g Guard) -> &'g T {
+        // Acquire: pairs with the Release of the `swap` that published it.
+        let p = self.ptr.load(Ordering::Acquire);
+        // Safety: the box is only retired once replaced, and dropped once every thread that was
+        // pinned at the time, including us, has unpinned.
+        unsafe { &*p }
+    }
+
+    /// Publishes `value`, dropping the old value once no reader can still be using it.
+    pub fn store(&self, value: T)
+    where
+        T: Send + 'static,
+    {
+        let guard = pin();
+        self.swap(value, &guard);
+    }
+
+    /// Publishes `value` and returns the old one, which stays valid for as long as the thread
+    /// stays pinned and is dropped some time after.
+    pub fn swap<'g>(&self, value: T, guard: &'g Guard) -> &'g T
+    where
+        T: Send + 'static,
+    {
+        let new = Box::into_raw(Box::new(value));
+        // AcqRel: Release publishes the new box, Acquire lets us read the old one.
+        let old = self.ptr.swap(new, Ordering::AcqRel);
+        // Safety: the old box came from `Box::into_raw`, is no longer reachable through `ptr`,
+        // and only this swap took it out, so it's retired once. T: Send.
+        unsafe {
+            guard.defer_destroy(old);
+            &*old
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        // Safety: `&mut self`, so no reader is using the box.
+        unsafe { &mut **self.ptr.get_mut() }
+    }
+
+    pub fn into_inner(self) -> T {
+        let p = *std::mem::ManuallyDrop::new(self).ptr.get_mut();
+        // Safety: `self` is gone without running `Drop`, so the box is ours.
+        *unsafe { Box::from_raw(p) }
+    }
+}
+
+impl<T: Default> Default for AtomicBox<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for AtomicBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AtomicBox").field(self.load(&pin())).finish()
+    }
+}
+
+impl<T> Drop for AtomicBox<T> {
+    fn drop(&mut self) {
+        // Safety: `&mut self`; replaced boxes were handed to the collector, this one wasn't.
+        drop(unsafe { Box::from_raw(*self.ptr.get_mut()) });
+    }
+}
+
+#[test]
+fn atomic_box_readers_see_whole_values_while_it_is_replaced() {
+    // Every value keeps both halves equal; a box freed under a reader would show up as garbage
+    // (and under Miri as a use after free).
+    let data = AtomicBox::new((0u64, vec![0u64; 16]));
+    let done = std::sync::atomic::AtomicBool::new(false);
+    std::thread::scope(|s| {
+        for _ in 0..3 {
+            s.spawn(|| {
+                while !done.load(Ordering::Relaxed) {
+                    let guard = pin();
+                    let (n, v) = data.load(&guard);
+                    assert!(v.iter().all(|x| x == n));
+                }
+            });
+        }
+        for n in 1..=2_000 {
+            data.store((n, vec![n; 16]));
+        }
+        done.store(true, Ordering::Relaxed);
+    });
+    let guard = pin();
+    let old = data.swap((0, Vec::new()), &guard);
+    assert_eq!(old.0, 2_000);
+    drop(guard);
+    assert_eq!(data.into_inner(), (0, Vec::new()));
+}
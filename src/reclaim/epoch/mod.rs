@@ -0,0 +1,336 @@
+//! Epoch-based reclamation.
+//!
+//! A thread [`pin`]s itself before it loads shared pointers and stays pinned for as long as it
+//! uses them; the [`Guard`] unpins it when dropped. A pinned thread records the global epoch it
+//! saw. The global epoch only moves from `e` to `e + 1` once every pinned thread has seen `e`.
+//!
+//! A retired object goes into its thread's garbage bag, tagged with the global epoch at the time.
+//! Any thread that may still hold a pointer to it was pinned at that epoch or the one before, so
+//! once the global epoch is two further along, all of them have unpinned and the object can be
+//! destroyed. Each thread collects its own bag every [`COLLECT_EVERY`] retirements; the bag of a
+//! thread that exits is handed to whichever thread collects next.
+//!
+//! Pinning and unpinning are cheap (a store and a fence), but a thread that stays pinned holds up
+//! every other thread's garbage. [`hazard`](super::hazard) doesn't have that problem.
+
+mod atomic_box;
+
+use std::cell::{Cell, RefCell};
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+pub use atomic_box::AtomicBox;
+
+/// How many objects a thread retires between attempts to advance the epoch and collect.
+pub const COLLECT_EVERY: usize = 64;
+
+/// The global epoch.
+static EPOCH: AtomicUsize = AtomicUsize::new(0);
+
+/// Every thread that ever pinned, as a linked list. Records are reused by later threads rather
+/// than freed, so a thread walking the list never needs protecting.
+static PARTICIPANTS: AtomicPtr<Participant> = AtomicPtr::new(ptr::null_mut());
+
+/// Garbage left behind by exited threads.
+static ORPHANS: Mutex<Vec<Deferred>> = Mutex::new(Vec::new());
+
+struct Participant {
+    /// `epoch << 1 | 1` while the thread is pinned, 0 otherwise.
+    state: AtomicUsize,
+    /// Whether a live thread owns this record.
+    in_use: AtomicBool,
+    /// Written before the record is published and never again.
+    next: *mut Participant,
+}
+
+// Safety: `next` is only written before the record is shared.
+unsafe impl Sync for Participant {}
+
+impl Participant {
+    /// Claims an unused record, or adds a new one.
+    fn register() -> &'static Participant {
+        let mut p = PARTICIPANTS.load(Ordering::Acquire);
+        // Safety: records are never freed.
+        while let Some(participant) = unsafe { p.as_ref() } {
+            if participant
+                .in_use
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                return participant;
+            }
+            p = participant.next;
+        }
+        let participant = Box::leak(Box::new(Participant {
+            state: AtomicUsize::new(0),
+            in_use: AtomicBool::new(true),
+            next: ptr::null_mut(),
+        }));
+        let mut head = PARTICIPANTS.load(Ordering::Relaxed);
+        loop {
+            participant.next = head;
+            match PARTICIPANTS.compare_exchange_weak(
+                head,
+                participant,
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return participant,
+                Err(current) => head = current,
+            }
+        }
+    }
+}
+
+/// Something to run once the epoch has moved on: a destructor for a retired object, or a
+/// closure.
+struct Deferred {
+    /// The global epoch when it was retired.
+    epoch: usize,
+    call: unsafe fn(*mut ()),
+    data: *mut (),
+}
+
+// Safety: `Guard::defer` only takes `Send` closures, and `Guard::defer_destroy` requires the
+// object to be safe to drop on another thread.
+unsafe impl Send for Deferred {}
+
+impl Deferred {
+    fn is_ready(&self, epoch: usize) -> bool {
+        epoch.wrapping_sub(self.epoch) >= 2
+    }
+
+    fn run(self) {
+        // Safety: `call` and `data` were paired up by `Guard::defer` or `Guard::defer_destroy`,
+        // and a `Deferred` runs at most once.
+        unsafe { (self.call)(self.data) }
+    }
+}
+
+/// The current thread's record and garbage.
+struct Local {
+    participant: &'static Participant,
+    /// How many guards this thread holds.
+    guards: Cell<usize>,
+    bag: RefCell<Vec<Deferred>>,
+}
+
+impl Drop for Local {
+    fn drop(&mut self) {
+        let bag = mem::take(self.bag.get_mut());
+        if !bag.is_empty() {
+            ORPHANS
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .extend(bag);
+        }
+        self.participant.state.store(0, Ordering::Release);
+        self.participant.in_use.store(false, Ordering::Release);
+    }
+}
+
+thread_local! {
+    static LOCAL: Local = Local {
+        participant: Participant::register(),
+        guards: Cell::new(0),
+        bag: RefCell::new(Vec::new()),
+    };
+}
+
+/// Pins the current thread until the guard is dropped. Pins nest.
+pub fn pin() -> Guard {
+    LOCAL.with(|local| {
+        let guards = local.guards.get();
+        local.guards.set(guards + 1);
+        if guards == 0 {
+            let epoch = EPOCH.load(Ordering::Relaxed);
+            local
+                .participant
+                .state
+                .store(epoch << 1 | 1, Ordering::Relaxed);
+            // Makes the pin visible to `try_advance` before any pointer we load from here on:
+            // either it sees us pinned, or we see everything it saw unlinked.
+            fence(Ordering::SeqCst);
+        }
+    });
+    Guard {
+        _not_send: PhantomData,
+    }
+}
+
+/// Proof that the current thread is pinned: pointers loaded from an epoch-protected structure
+/// stay valid for as long as it lives.
+#[derive(Debug)]
+pub struct Guard {
+    _not_send: PhantomData<*mut ()>,
+}
+
+impl Guard {
+    /// Runs `f` once no thread that is pinned now can still be pinned.
+    pub fn defer<F: FnOnce() + Send + 'static>(&self, f: F) {
+        unsafe fn call<F: FnOnce()>(data: *mut ()) {
+            Box::from_raw(data.cast::<F>())();
+        }
+        let data = Box::into_raw(Box::new(f)).cast();
+        self.retire(call::<F>, data);
+    }
+
+    /// Drops the box behind `ptr` once no thread that is pinned now can still be reading it.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `Box::into_raw`, be unreachable for threads that pin from now on,
+    /// and not be retired twice. `T` must be safe to drop on another thread.
+    pub unsafe fn defer_destroy<T>(&self, ptr: *mut T) {
+        unsafe fn drop_box<T>(data: *mut ()) {
+            drop(Box::from_raw(data.cast::<T>()));
+        }
+        self.retire(drop_box::<T>, ptr.cast());
+    }
+
+    /// Tries to advance the epoch and runs whatever garbage of this thread is ready.
+    pub fn flush(&self) {
+        try_advance();
+        let epoch = EPOCH.load(Ordering::Acquire);
+        let ready: Vec<_> = LOCAL.with(|local| {
+            let mut bag = local.bag.borrow_mut();
+            let (ready, waiting) = mem::take(&mut *bag)
+                .into_iter()
+                .partition(|d| d.is_ready(epoch));
+            *bag = waiting;
+            ready
+        });
+        let orphans: Vec<_> = match ORPHANS.try_lock() {
+            Ok(mut orphans) if !orphans.is_empty() => {
+                let (ready, waiting) = mem::take(&mut *orphans)
+                    .into_iter()
+                    .partition(|d| d.is_ready(epoch));
+                *orphans = waiting;
+                ready
+            }
+            _ => Vec::new(),
+        };
+        // Outside the borrow: a destructor may retire more garbage.
+        for deferred in ready.into_iter().chain(orphans) {
+            deferred.run();
+        }
+    }
+
+    fn retire(&self, call: unsafe fn(*mut ()), data: *mut ()) {
+        // Everything before the fence (the unlinking, in particular) is ordered before the epoch
+        // load, so a thread pinned at a later epoch can't have seen the object.
+        fence(Ordering::SeqCst);
+        let epoch = EPOCH.load(Ordering::Relaxed);
+        let full = LOCAL.with(|local| {
+            let mut bag = local.bag.borrow_mut();
+            bag.push(Deferred { epoch, call, data });
+            bag.len() % COLLECT_EVERY == 0
+        });
+        if full {
+            self.flush();
+        }
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        LOCAL.with(|local| {
+            let guards = local.guards.get() - 1;
+            local.guards.set(guards);
+            if guards == 0 {
+                // Release: our reads through protected pointers happen before the epoch that
+                // frees them.
+                local.participant.state.store(0, Ordering::Release);
+            }
+        });
+    }
+}
+
+/// Moves the global epoch on if every pinned thread has seen the current one.
+fn try_advance() {
+    let epoch = EPOCH.load(Ordering::Relaxed);
+    // Pairs with the fence in `pin`.
+    fence(Ordering::SeqCst);
+    let mut p = PARTICIPANTS.load(Ordering::Acquire);
+    // Safety: records are never freed.
+    while let Some(participant) = unsafe { p.as_ref() } {
+        let state = participant.state.load(Ordering::Relaxed);
+        if state & 1 == 1 && state >> 1 != epoch {
+            return;
+        }
+        p = participant.next;
+    }
+    // Takes in the unpinning (Release) stores we just read.
+    fence(Ordering::Acquire);
+    let _ = EPOCH.compare_exchange(
+        epoch,
+        epoch.wrapping_add(1),
+        Ordering::Release,
+        Ordering::Relaxed,
+    );
+}
+
+#[cfg(test)]
+struct DropCounter<'a>(&'a AtomicUsize);
+
+#[cfg(test)]
+impl Drop for DropCounter<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+fn flush_until(done: impl Fn() -> bool) {
+    let start = std::time::Instant::now();
+    while !done() {
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+        pin().flush();
+        std::thread::yield_now();
+    }
+}
+
+#[test]
+fn epoch_defers_until_pinned_threads_unpin() {
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+    let (pinned_tx, pinned_rx) = std::sync::mpsc::channel();
+    let (unpin_tx, unpin_rx) = std::sync::mpsc::channel::<()>();
+    std::thread::scope(|s| {
+        s.spawn(move || {
+            let _guard = pin();
+            pinned_tx.send(()).unwrap();
+            unpin_rx.recv().unwrap();
+        });
+        pinned_rx.recv().unwrap();
+
+        let guard = pin();
+        let ptr = Box::into_raw(Box::new(DropCounter(&DROPPED)));
+        // Safety: `ptr` was never shared.
+        unsafe { guard.defer_destroy(ptr) };
+        drop(guard);
+        for _ in 0..10 {
+            pin().flush();
+        }
+        assert_eq!(DROPPED.load(Ordering::Relaxed), 0);
+
+        unpin_tx.send(()).unwrap();
+    });
+    // Other tests may be pinned too, so give them a moment.
+    flush_until(|| DROPPED.load(Ordering::Relaxed) == 1);
+}
+
+#[test]
+fn epoch_collects_garbage_of_exited_threads() {
+    static RAN: AtomicUsize = AtomicUsize::new(0);
+    std::thread::spawn(|| {
+        pin().defer(|| {
+            RAN.fetch_add(1, Ordering::Relaxed);
+        });
+    })
+    .join()
+    .unwrap();
+    flush_until(|| RAN.load(Ordering::Relaxed) == 1);
+}
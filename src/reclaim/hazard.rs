@@ -0,0 +1,358 @@
+//! Hazard-pointer reclamation.
+//!
+//! Before dereferencing a shared pointer, a thread publishes it in one of its hazard slots with
+//! [`HazardPointer::protect`], then checks that the pointer is still current. A retired object
+//! is only destroyed once no slot holds it. Retiring threads [`retire`] into a thread-local list
+//! and scan all the slots once the list reaches [`SCAN_THRESHOLD`] (or twice the number of slots,
+//! if that is more).
+//!
+//! Unlike [`epoch`](super::epoch), a reader that stalls holds up only the objects it protects,
+//! not everyone's garbage: a scan frees everything except at most one object per slot, so the
+//! garbage waiting on any thread stays bounded by the threshold plus the number of slots. The
+//! price is a `SeqCst` fence per protected pointer, where a pinned thread loads pointers for
+//! free.
+
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+/// The minimum number of objects a thread retires before it scans the hazard slots.
+pub const SCAN_THRESHOLD: usize = 64;
+
+/// Every hazard slot ever created, as a linked list. Slots are reused by later threads rather
+/// than freed, so a thread walking the list never needs protecting.
+static SLOTS: AtomicPtr<Slot> = AtomicPtr::new(ptr::null_mut());
+static SLOT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Objects retired by exited threads, destroyed by the next thread to scan.
+static ORPHANS: Mutex<Vec<Retired>> = Mutex::new(Vec::new());
+
+#[derive(Debug)]
+struct Slot {
+    /// The protected pointer, or null.
+    hazard: AtomicPtr<()>,
+    /// Whether a live thread owns this slot.
+    in_use: AtomicBool,
+    /// Written before the slot is published and never again.
+    next: *mut Slot,
+}
+
+// Safety: `next` is only written before the slot is shared.
+unsafe impl Sync for Slot {}
+
+impl Slot {
+    /// Claims an unused slot, or adds a new one.
+    fn acquire() -> &'static Slot {
+        let mut p = SLOTS.load(Ordering::Acquire);
+        // Safety: slots are never freed.
+        while let Some(slot) = unsafe { p.as_ref() } {
+            if slot
+                .in_use
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                return slot;
+            }
+            p = slot.next;
+        }
+        let slot = Box::leak(Box::new(Slot {
+            hazard: AtomicPtr::new(ptr::null_mut()),
+            in_use: AtomicBool::new(true),
+            next: ptr::null_mut(),
+        }));
+        SLOT_COUNT.fetch_add(1, Ordering::Relaxed);
+        let mut head = SLOTS.load(Ordering::Relaxed);
+        loop {
+            slot.next = head;
+            match SLOTS.compare_exchange_weak(head, slot, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => return slot,
+                Err(current) => head = current,
+            }
+        }
+    }
+}
+
+/// A retired object and its destructor.
+struct Retired {
+    ptr: *mut (),
+    drop: unsafe fn(*mut ()),
+}
+
+// Safety: `retire` requires the object to be safe to drop on another thread.
+unsafe impl Send for Retired {}
+
+/// The current thread's slots and garbage.
+struct Local {
+    /// Slots this thread owns that no `HazardPointer` is using.
+    free: RefCell<Vec<&'static Slot>>,
+    retired: RefCell<Vec<Retired>>,
+}
+
+impl Drop for Local {
+    fn drop(&mut self) {
+        for slot in self.free.get_mut().drain(..) {
+            slot.in_use.store(false, Ordering::Release);
+        }
+        // Not scanned here: a destructor that retires something else would find `LOCAL` already
+        // gone. Another thread's scan destroys them instead, as in `epoch`.
+        let retired = mem::take(self.retired.get_mut());
+        if !retired.is_empty() {
+            ORPHANS
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .extend(retired);
+        }
+    }
+}
+
+thread_local! {
+    static LOCAL: Local = const {
+        Local {
+            free: RefCell::new(Vec::new()),
+            retired: RefCell::new(Vec::new()),
+        }
+    };
+}
+
+/// One of the current thread's hazard slots. It protects at most one pointer at a time, and
+/// goes back to the thread when dropped.
+#[derive(Debug)]
+pub struct HazardPointer {
+    slot: &'static Slot,
+    _not_send: PhantomData<*mut ()>,
+}
+
+impl HazardPointer {
+    pub fn new() -> Self {
+        let slot = LOCAL
+            .with(|local| local.free.borrow_mut().pop())
+            .unwrap_or_else(Slot::acquire);
+        Self {
+            slot,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Loads `src` and protects what it points to, until the next `protect` or `reset`.
+    ///
+    /// # Safety
+    ///
+    /// Every non-null pointer stored in `src` must point to a valid `T` until it is passed to
+    /// [`retire`], after being replaced in `src`.
+    pub unsafe fn protect<'hp, T>(&'hp mut self, src: &AtomicPtr<T>) -> Option<&'hp T> {
+        let mut p = src.load(Ordering::Relaxed);
+        loop {
+            self.slot.hazard.store(p.cast(), Ordering::Relaxed);
+            // Pairs with the fence in `scan`: either the scan sees our hazard, or we see that
+            // `src` no longer holds `p` (it was replaced before being retired).
+            fence(Ordering::SeqCst);
+            // Acquire: pairs with the Release that published the pointee.
+            let current = src.load(Ordering::Acquire);
+            if current == p {
+                // Safety: `p` was still current after we protected it, so it hadn't been
+                // retired, and no scan will free it while our slot holds it.
+                return unsafe { p.as_ref() };
+            }
+            p = current;
+        }
+    }
+
+    /// Stops protecting the current pointer.
+    pub fn reset(&mut self) {
+        // Release: our reads through the pointer happen before the scan that frees it.
+        self.slot.hazard.store(ptr::null_mut(), Ordering::Release);
+    }
+}
+
+impl Default for HazardPointer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for HazardPointer {
+    fn drop(&mut self) {
+        self.reset();
+        let slot = self.slot;
+        // During thread exit, after `LOCAL` is gone, give the slot back to everyone instead.
+        if LOCAL
+            .try_with(|local| local.free.borrow_mut().push(slot))
+            .is_err()
+        {
+            slot.in_use.store(false, Ordering::Release);
+        }
+    }
+}
+
+/// Drops the box behind `ptr` once no hazard slot protects it.
+///
+/// # Safety
+///
+/// `ptr` must come from `Box::into_raw`, no longer be loadable by threads that protect from now
+/// on, and not be retired twice. `T` must be safe to drop on another thread.
+pub unsafe fn retire<T>(ptr: *mut T) {
+    unsafe fn drop_box<T>(ptr: *mut ()) {
+        drop(Box::from_raw(ptr.cast::<T>()));
+    }
+    let retired = Retired {
+        ptr: ptr.cast(),
+        drop: drop_box::<T>,
+    };
+    let threshold = SCAN_THRESHOLD.max(2 * SLOT_COUNT.load(Ordering::Relaxed));
+    let full = LOCAL.with(|local| {
+        let mut list = local.retired.borrow_mut();
+        list.push(retired);
+        list.len() >= threshold
+    });
+    if full {
+        reclaim();
+    }
+}
+
+/// Scans the hazard slots now, destroying whatever the current thread retired that nobody
+/// protects.
+pub fn reclaim() {
+    let retired = LOCAL.with(|local| mem::take(&mut *local.retired.borrow_mut()));
+    let mut retired = scan(retired);
+    if let Ok(mut orphans) = ORPHANS.try_lock() {
+        let kept = scan(mem::take(&mut *orphans));
+        *orphans = kept;
+    }
+    // Destructors run by `scan` may have retired more in the meantime.
+    LOCAL.with(|local| {
+        let mut list = local.retired.borrow_mut();
+        retired.append(&mut list);
+        *list = retired;
+    });
+}
+
+/// Destroys the objects in `retired` that no slot protects, and returns the rest.
+fn scan(retired: Vec<Retired>) -> Vec<Retired> {
+    if retired.is_empty() {
+        return retired;
+    }
+    // Pairs with the fence in `protect`.
+    fence(Ordering::SeqCst);
+    let mut hazards = Vec::new();
+    let mut p = SLOTS.load(Ordering::Acquire);
+    // Safety: slots are never freed.
+    while let Some(slot) = unsafe { p.as_ref() } {
+        let hazard = slot.hazard.load(Ordering::Relaxed);
+        if !hazard.is_null() {
+            hazards.push(hazard);
+        }
+        p = slot.next;
+    }
+    // Takes in the `reset`s (Release) we just read.
+    fence(Ordering::Acquire);
+    hazards.sort_unstable();
+    let (kept, free): (Vec<_>, Vec<_>) = retired
+        .into_iter()
+        .partition(|r| hazards.binary_search(&r.ptr).is_ok());
+    for r in free {
+        // Safety: `r` is unreachable (see `retire`) and unprotected, and retired only once.
+        unsafe { (r.drop)(r.ptr) };
+    }
+    kept
+}
+
+#[cfg(test)]
+struct DropCounter<'a>(&'a AtomicUsize);
+
+#[cfg(test)]
+impl Drop for DropCounter<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn hazard_protected_objects_survive_scans() {
+    // Retired objects may outlive a failing test, so the counter must too.
+    let dropped: &'static AtomicUsize = Box::leak(Box::new(AtomicUsize::new(0)));
+    let src = AtomicPtr::new(Box::into_raw(Box::new(DropCounter(dropped))));
+    let mut hp = HazardPointer::new();
+    // Safety: `src` only ever holds boxes, retired after being replaced.
+    assert!(unsafe { hp.protect(&src) }.is_some());
+
+    let old = src.swap(ptr::null_mut(), Ordering::AcqRel);
+    // Safety: `old` was replaced in `src`, so it's only reachable through our hazard.
+    unsafe { retire(old) };
+    reclaim();
+    assert_eq!(dropped.load(Ordering::Relaxed), 0);
+
+    hp.reset();
+    reclaim();
+    assert_eq!(dropped.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn hazard_stalled_reader_holds_up_only_what_it_protects() {
+    // Retired objects may outlive a failing test, so the counter must too.
+    let dropped: &'static AtomicUsize = Box::leak(Box::new(AtomicUsize::new(0)));
+    let src = AtomicPtr::new(Box::into_raw(Box::new(DropCounter(dropped))));
+    let (protected_tx, protected_rx) = std::sync::mpsc::channel();
+    let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+    std::thread::scope(|s| {
+        let src = &src;
+        s.spawn(move || {
+            let mut hp = HazardPointer::new();
+            // Safety: as above.
+            unsafe { hp.protect(src) };
+            protected_tx.send(()).unwrap();
+            // Stall while holding the hazard.
+            release_rx.recv().unwrap();
+        });
+        protected_rx.recv().unwrap();
+
+        const RETIRED: usize = 10_000;
+        for _ in 0..RETIRED {
+            let new = Box::into_raw(Box::new(DropCounter(dropped)));
+            // Safety: `old` was just replaced in `src`.
+            unsafe { retire(src.swap(new, Ordering::AcqRel)) };
+        }
+        // Everything but the protected object and one threshold's worth of recent garbage
+        // was freed along the way.
+        let threshold = SCAN_THRESHOLD.max(2 * SLOT_COUNT.load(Ordering::Relaxed));
+        assert!(dropped.load(Ordering::Relaxed) >= RETIRED - threshold);
+        release_tx.send(()).unwrap();
+    });
+    reclaim();
+    assert_eq!(dropped.load(Ordering::Relaxed), 10_000);
+    // Safety: the last box was never retired.
+    drop(unsafe { Box::from_raw(src.into_inner()) });
+}
+
+#[test]
+fn hazard_exiting_thread_leaves_its_garbage_to_others() {
+    /// Retires its child when dropped, like a node of a linked structure.
+    struct Parent(*mut DropCounter<'static>);
+    impl Drop for Parent {
+        fn drop(&mut self) {
+            // Safety: the child was only ever reachable through its parent.
+            unsafe { retire(self.0) };
+        }
+    }
+
+    let dropped: &'static AtomicUsize = Box::leak(Box::new(AtomicUsize::new(0)));
+    std::thread::spawn(move || {
+        let child = Box::into_raw(Box::new(DropCounter(dropped)));
+        // Safety: nobody else ever saw the parent.
+        unsafe { retire(Box::into_raw(Box::new(Parent(child)))) };
+    })
+    .join()
+    .unwrap();
+    // The first scan to get at the orphans destroys the parent, which retires the child here;
+    // other tests' threads may hold the orphan list for a moment.
+    for _ in 0..1_000 {
+        reclaim();
+        if dropped.load(Ordering::Relaxed) == 1 {
+            return;
+        }
+        std::thread::yield_now();
+    }
+    panic!("the orphaned child was never destroyed");
+}
@@ -0,0 +1,9 @@
+//! Safe memory reclamation for lock-free data structures.
+//!
+//! A thread that unlinks a node from a lock-free structure can't free it straight away: another
+//! thread may have loaded the pointer just before and be about to read through it. The schemes
+//! here let the unlinking thread *retire* the node instead, and free it once no thread can still
+//! be holding it.
+
+pub mod epoch;
+pub mod hazard;
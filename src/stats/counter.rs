@@ -0,0 +1,102 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use super::Shards;
+
+/// A counter that threads add to without contending, summed on read.
+pub struct ShardedCounter {
+    shards: Shards<AtomicU64>,
+}
+
+impl ShardedCounter {
+    pub fn new() -> Self {
+        Self {
+            shards: Shards::new(|| AtomicU64::new(0)),
+        }
+    }
+
+    pub fn add(&self, n: u64) {
+        // Relaxed: a counter orders nothing else, and `sum` doesn't promise a snapshot.
+        self.shards.local().fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn increment(&self) {
+        self.add(1);
+    }
+
+    /// The total. Exact once the writers are done; while they're running, it includes some
+    /// recent additions and not others.
+    pub fn sum(&self) -> u64 {
+        self.shards
+            .iter()
+            .map(|shard| shard.load(Ordering::Relaxed))
+            .fold(0, u64::wrapping_add)
+    }
+}
+
+impl Default for ShardedCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for ShardedCounter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ShardedCounter").field(&self.sum()).finish()
+    }
+}
+
+/// The largest value recorded by any thread.
+pub struct ShardedMax {
+    shards: Shards<MaxShard>,
+}
+
+struct MaxShard {
+    max: AtomicU64,
+    /// Whether `max` holds a recorded value rather than the initial 0.
+    recorded: AtomicBool,
+}
+
+impl ShardedMax {
+    pub fn new() -> Self {
+        Self {
+            shards: Shards::new(|| MaxShard {
+                max: AtomicU64::new(0),
+                recorded: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    pub fn record(&self, value: u64) {
+        let shard = self.shards.local();
+        // Once the maximum settles, most records are smaller and this skips the write.
+        if shard.max.load(Ordering::Relaxed) < value {
+            shard.max.fetch_max(value, Ordering::Relaxed);
+        }
+        if !shard.recorded.load(Ordering::Relaxed) {
+            // Release: `max` sees this value once it sees the flag.
+            shard.recorded.store(true, Ordering::Release);
+        }
+    }
+
+    /// The maximum, or `None` if nothing was recorded.
+    pub fn max(&self) -> Option<u64> {
+        self.shards
+            .iter()
+            .filter(|shard| shard.recorded.load(Ordering::Acquire))
+            .map(|shard| shard.max.load(Ordering::Relaxed))
+            .max()
+    }
+}
+
+impl Default for ShardedMax {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for ShardedMax {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ShardedMax").field(&self.max()).finish()
+    }
+}
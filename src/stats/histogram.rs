@@ -0,0 +1,76 @@
+use std::fmt;
+
+use super::Shards;
+use crate::progress::histogram::{self, Histogram, BUCKETS};
+
+/// A [`Histogram`] per shard, merged on read.
+pub struct ShardedHistogram {
+    shards: Shards<Histogram>,
+}
+
+impl ShardedHistogram {
+    pub fn new() -> Self {
+        Self {
+            shards: Shards::new(Histogram::new),
+        }
+    }
+
+    pub fn record(&self, value: u64) {
+        self.shards.local().record(value);
+    }
+
+    /// The bucket counts of all shards added together, for [`histogram::percentile`].
+    pub fn counts(&self) -> [u64; BUCKETS] {
+        let mut counts = [0; BUCKETS];
+        for shard in self.shards.iter() {
+            for (total, n) in counts.iter_mut().zip(shard.counts()) {
+                *total += n;
+            }
+        }
+        counts
+    }
+
+    pub fn percentile(&self, q: f64) -> Option<u64> {
+        histogram::percentile(&self.counts(), q)
+    }
+}
+
+impl Default for ShardedHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for ShardedHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShardedHistogram")
+            .field("count", &self.counts().iter().sum::<u64>())
+            .finish_non_exhaustive()
+    }
+}
+
+#[test]
+fn sharded_histogram_merges_like_one_histogram() {
+    let sharded = ShardedHistogram::new();
+    let single = Histogram::new();
+    std::thread::scope(|s| {
+        for t in 0..4u64 {
+            let sharded = &sharded;
+            s.spawn(move || {
+                for i in 0..5_000 {
+                    sharded.record(t * i);
+                }
+            });
+        }
+    });
+    for t in 0..4u64 {
+        for i in 0..5_000 {
+            single.record(t * i);
+        }
+    }
+    assert_eq!(sharded.counts(), single.counts());
+    assert_eq!(
+        sharded.percentile(0.99),
+        histogram::percentile(&single.counts(), 0.99)
+    );
+}
@@ -0,0 +1,83 @@
+//! Statistics that many threads update and few read.
+//!
+//! A single `AtomicU64` that every worker hits with `fetch_add` is correct but doesn't scale: each
+//! increment has to take the cache line away from whichever core wrote it last, so the more
+//! threads record, the longer each record takes. The types here give every thread its own shard
+//! on its own cache line (see [`CachePadded`]) and only add the shards up when someone reads.
+//! Reads are slower and, with writers still running, not a consistent snapshot across shards;
+//! for statistics that must agree with each other, [`progress::Tracker`](crate::progress::Tracker)
+//! keeps them together in a [`SeqLock`](crate::sync::SeqLock) per shard.
+//!
+//! Threads pick their shard round-robin, in the order they first touch any sharded statistic.
+//! With more threads than shards some share one, which is still correct, just contended again.
+
+mod counter;
+mod histogram;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::sync::CachePadded;
+pub use counter::{ShardedCounter, ShardedMax};
+pub use histogram::ShardedHistogram;
+
+/// The next thread's shard index, before masking.
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static SHARD: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed);
+}
+
+/// One `T` per shard, each on its own cache line.
+pub(crate) struct Shards<T> {
+    shards: Box<[CachePadded<T>]>,
+}
+
+impl<T> Shards<T> {
+    /// Twice as many shards as CPUs, so that threads running at the same time rarely share one
+    /// even though they aren't assigned by CPU.
+    pub(crate) fn new(init: impl Fn() -> T) -> Self {
+        let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+        let shards = (0..(2 * cpus).next_power_of_two())
+            .map(|_| CachePadded::new(init()))
+            .collect();
+        Self { shards }
+    }
+
+    /// The current thread's shard.
+    pub(crate) fn local(&self) -> &T {
+        let index = SHARD.with(|&i| i) & (self.shards.len() - 1);
+        &self.shards[index]
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &T> {
+        self.shards.iter().map(|shard| &**shard)
+    }
+}
+
+#[test]
+fn sharded_stats_add_up_across_threads() {
+    let counter = ShardedCounter::new();
+    let max = ShardedMax::new();
+    std::thread::scope(|s| {
+        for t in 0..8u64 {
+            let (counter, max) = (&counter, &max);
+            s.spawn(move || {
+                for i in 0..1_000 {
+                    counter.increment();
+                    max.record(t * 1_000 + i);
+                }
+            });
+        }
+    });
+    counter.add(5);
+    assert_eq!(counter.sum(), 8_005);
+    assert_eq!(max.max(), Some(7_999));
+    assert_eq!(ShardedMax::new().max(), None);
+
+    let extremes = ShardedMax::new();
+    extremes.record(0);
+    assert_eq!(extremes.max(), Some(0));
+    extremes.record(u64::MAX);
+    assert_eq!(extremes.max(), Some(u64::MAX));
+}
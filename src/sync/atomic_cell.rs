@@ -0,0 +1,450 @@
+//! A `Cell` that threads can share, for any `Copy` type.
+//!
+//! A cell made with [`AtomicCell::new_native`] (an `AtomicCell<T, true>`), whose `T` has the size
+//! of a native atomic and at least its alignment, is that atomic, and its operations are single
+//! atomic instructions on `T`'s bytes. That takes `T: NoPadding`, which the cell can't check for
+//! itself, hence the separate constructor. Anything else is guarded by one of a fixed table of
+//! sequence locks, picked by the cell's address. A lock works like [`SeqLock`](super::SeqLock)
+//! without a value of its own: loads copy the cell optimistically and retry if a writer got in
+//! between, so they never block writers, and writers only contend with writers to cells that
+//! happen to share their lock.
+//!
+//! No code of the caller's runs under a lock. `compare_exchange` and `fetch_update` copy the cell
+//! like a load, compare or compute outside the lock, and only write if the lock's sequence number
+//! hasn't moved since the copy, so `T::eq` and `f` can panic or touch the cell (or another cell on
+//! the same lock) without leaving it held.
+//!
+//! Either way, loads acquire and everything that writes releases, as if the cell were behind a
+//! mutex.
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::mem::{self, MaybeUninit};
+use std::sync::atomic::{fence, AtomicU16, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+use super::CachePadded;
+
+/// `NATIVE` cells use a native atomic where one fits; only [`AtomicCell::new_native`] makes them.
+#[repr(transparent)]
+pub struct AtomicCell<T, const NATIVE: bool = false> {
+    value: UnsafeCell<T>,
+}
+
+// Safety: values are only ever copied in and out whole, by atomics or under a lock. They move
+// between threads, so `T: Send`.
+unsafe impl<T: Send, const NATIVE: bool> Sync for AtomicCell<T, NATIVE> {}
+
+/// Types whose values initialise every byte, so they can be reinterpreted as integers of the same
+/// size. Only these can have an [`AtomicCell`] that uses a native atomic.
+///
+/// # Safety
+///
+/// Every value of the type must be free of padding and other uninitialised bytes.
+/// `#[repr(align(4))] struct S(u16, u8)` has a padding byte, for example, while
+/// `#[repr(align(4))] struct S(u16, u16)` has none.
+pub unsafe trait NoPadding: Copy {}
+
+macro_rules! no_padding {
+    ($($t:ty),*) => {
+        $(unsafe impl NoPadding for $t {})*
+    };
+}
+
+no_padding! {
+    u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, bool, char, ()
+}
+
+// Safety: an address, plus a length or vtable pointer if `T` is unsized.
+unsafe impl<T: ?Sized> NoPadding for *const T {}
+unsafe impl<T: ?Sized> NoPadding for *mut T {}
+
+// Safety: elements follow each other with no gaps, since an element's size is a multiple of its
+// alignment.
+unsafe impl<T: NoPadding, const N: usize> NoPadding for [T; N] {}
+
+/// Whether a `T` can be accessed as an `A`.
+const fn fits<T, A>() -> bool {
+    mem::size_of::<T>() == mem::size_of::<A>() && mem::align_of::<T>() >= mem::align_of::<A>()
+}
+
+/// Runs `$lock_free` with `$atomic` bound to the cell viewed as a native atomic, or `$locked` if
+/// there is no such atomic.
+macro_rules! dispatch {
+    ($cell:expr, |$atomic:ident| $lock_free:expr, || $locked:expr) => {
+        if !NATIVE {
+            $locked
+        } else if fits::<T, AtomicU8>() {
+            // Safety: same size, and at least the alignment.
+            let $atomic = unsafe { &*$cell.value.get().cast::<AtomicU8>() };
+            $lock_free
+        } else if fits::<T, AtomicU16>() {
+            // Safety: as above.
+            let $atomic = unsafe { &*$cell.value.get().cast::<AtomicU16>() };
+            $lock_free
+        } else if fits::<T, AtomicU32>() {
+            // Safety: as above.
+            let $atomic = unsafe { &*$cell.value.get().cast::<AtomicU32>() };
+            $lock_free
+        } else if fits::<T, AtomicU64>() {
+            // Safety: as above.
+            let $atomic = unsafe { &*$cell.value.get().cast::<AtomicU64>() };
+            $lock_free
+        } else {
+            $locked
+        }
+    };
+}
+
+/// Reinterprets `value`'s bytes as `U`, which has the same size.
+///
+/// # Safety
+///
+/// `U` must accept every bit pattern `T` has. Only called on `NATIVE` cells, so both `T` and the
+/// integers used here have no padding.
+unsafe fn cast<T: Copy, U: Copy>(value: T) -> U {
+    mem::transmute_copy(&value)
+}
+
+impl<T: Copy> AtomicCell<T> {
+    /// A cell behind a lock, whatever `T` is.
+    pub const fn new(value: T) -> Self {
+        Self {
+            value: UnsafeCell::new(value),
+        }
+    }
+}
+
+impl<T: NoPadding> AtomicCell<T, true> {
+    /// A cell that is a native atomic, if there is one the size of `T`.
+    pub const fn new_native(value: T) -> Self {
+        Self {
+            value: UnsafeCell::new(value),
+        }
+    }
+}
+
+impl<T: Copy, const NATIVE: bool> AtomicCell<T, NATIVE> {
+    /// Whether this cell uses a native atomic rather than a lock.
+    pub const fn is_lock_free() -> bool {
+        NATIVE
+            && (fits::<T, AtomicU8>()
+                || fits::<T, AtomicU16>()
+                || fits::<T, AtomicU32>()
+                || fits::<T, AtomicU64>())
+    }
+
+    pub fn load(&self) -> T {
+        dispatch!(self, |a| unsafe { cast(a.load(Ordering::Acquire)) }, || {
+            // Safety: the cell is always initialised, and the lock checks the copy.
+            unsafe { stripe(self).read(self.value.get()).0 }
+        })
+    }
+
+    pub fn store(&self, value: T) {
+        self.swap(value);
+    }
+
+    pub fn swap(&self, value: T) -> T {
+        // Safety: everything written to the cell is a `T`, and the lock serialises writers.
+        dispatch!(
+            self,
+            |a| unsafe { cast(a.swap(cast::<T, _>(value), Ordering::AcqRel)) },
+            || unsafe {
+                let _locked = stripe(self).lock();
+                // Volatile both ways, since readers may copy concurrently (and then discard the
+                // copy).
+                let old = self.value.get().read_volatile();
+                self.value.get().write_volatile(value);
+                old
+            }
+        )
+    }
+
+    /// Stores `new` if the cell holds `current`, and returns the previous value either way:
+    /// `Ok` if it was replaced, `Err` if not.
+    pub fn compare_exchange(&self, current: T, new: T) -> Result<T, T>
+    where
+        T: Eq,
+    {
+        dispatch!(
+            self,
+            |a| {
+                // Safety: as in `swap`.
+                let mut expected = unsafe { cast::<T, _>(current) };
+                let new = unsafe { cast::<T, _>(new) };
+                loop {
+                    match a.compare_exchange(expected, new, Ordering::AcqRel, Ordering::Acquire) {
+                        Ok(old) => return Ok(unsafe { cast(old) }),
+                        Err(actual) => {
+                            let actual_value = unsafe { cast::<_, T>(actual) };
+                            // Equal values can have different bytes (say, `-0.0` and `0.0` in
+                            // a newtype with its own `Eq`); those still count as a match.
+                            if actual_value != current {
+                                return Err(actual_value);
+                            }
+                            expected = actual;
+                        }
+                    }
+                }
+            },
+            || self.update_locked(|old| if old == current { Some(new) } else { None })
+        )
+    }
+
+    /// Replaces the value with `f(value)` until that succeeds or `f` returns `None`, and returns
+    /// the previous value: `Ok` if it was replaced, `Err` if not. `f` may run more than once.
+    pub fn fetch_update(&self, mut f: impl FnMut(T) -> Option<T>) -> Result<T, T> {
+        dispatch!(
+            self,
+            |a| {
+                let mut old = a.load(Ordering::Acquire);
+                loop {
+                    // Safety: as in `swap`.
+                    let Some(new) = f(unsafe { cast(old) }) else {
+                        return Err(unsafe { cast(old) });
+                    };
+                    // Comparing the bytes we loaded, so `T` needs no `Eq`.
+                    match a.compare_exchange_weak(
+                        old,
+                        unsafe { cast(new) },
+                        Ordering::AcqRel,
+                        Ordering::Acquire,
+                    ) {
+                        Ok(old) => return Ok(unsafe { cast(old) }),
+                        Err(actual) => old = actual,
+                    }
+                }
+            },
+            || self.update_locked(f)
+        )
+    }
+
+    /// `fetch_update` for cells behind a lock. `f` runs on a copy with the lock released, and its
+    /// result is only written if no writer took the lock in the meantime.
+    ///
+    /// That compares sequence numbers rather than values: `T` may have padding, whose bytes can't
+    /// be compared, and a writer to another cell on the same lock only costs a retry.
+    fn update_locked(&self, mut f: impl FnMut(T) -> Option<T>) -> Result<T, T> {
+        let stripe = stripe(self);
+        loop {
+            // Safety: as in `load`.
+            let (old, seq) = unsafe { stripe.read(self.value.get()) };
+            let new = f(old).ok_or(old)?;
+            if let Some(_locked) = stripe.try_lock(seq) {
+                // Safety: as in `swap`. The cell still holds `old`, since nothing has written
+                // under the lock since `seq`.
+                unsafe { self.value.get().write_volatile(new) };
+                return Ok(old);
+            }
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+/// A sequence lock guarding whichever cells hash to it. Odd while a writer holds it.
+struct Stripe {
+    seq: AtomicUsize,
+}
+
+/// A prime, so that cells spaced by a power of two still spread over all stripes.
+const STRIPES: usize = 67;
+
+static LOCKS: [CachePadded<Stripe>; STRIPES] = [const {
+    CachePadded::new(Stripe {
+        seq: AtomicUsize::new(0),
+    })
+}; STRIPES];
+
+fn stripe<T, const NATIVE: bool>(cell: &AtomicCell<T, NATIVE>) -> &'static Stripe {
+    &LOCKS[cell as *const _ as usize % STRIPES]
+}
+
+impl Stripe {
+    /// Copies `*src`, retrying while writers get in the way, and returns the copy with the
+    /// (even) sequence number it was taken at.
+    ///
+    /// # Safety
+    ///
+    /// `src` must point to an initialised `T` that is only written under this lock.
+    unsafe fn read<T: Copy>(&self, src: *const T) -> (T, usize) {
+        loop {
+            // Acquire: the copy sees at least the write that finished at `seq1`.
+            let seq1 = self.seq.load(Ordering::Acquire);
+            if seq1.is_multiple_of(2) {
+                // May be torn, hence `MaybeUninit` until the check below.
+                let value = src.cast::<MaybeUninit<T>>().read_volatile();
+                // Keeps the copy from moving after the second load of `seq`.
+                fence(Ordering::Acquire);
+                if self.seq.load(Ordering::Relaxed) == seq1 {
+                    return (value.assume_init(), seq1);
+                }
+            }
+            std::hint::spin_loop();
+        }
+    }
+
+    /// Locks out other writers and readers until the guard drops.
+    fn lock(&self) -> Locked<'_> {
+        let mut seq = self.seq.load(Ordering::Relaxed);
+        loop {
+            if seq.is_multiple_of(2) {
+                if let Some(locked) = self.try_lock(seq) {
+                    return locked;
+                }
+                seq = self.seq.load(Ordering::Relaxed);
+            } else {
+                std::hint::spin_loop();
+                seq = self.seq.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Locks as `lock` does, but only if the sequence number is still `seq`.
+    fn try_lock(&self, seq: usize) -> Option<Locked<'_>> {
+        // Acquire: the previous writer's write happens before ours.
+        self.seq
+            .compare_exchange(seq, seq + 1, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+        // Keeps the writes under the lock from moving before the odd sequence number.
+        fence(Ordering::Release);
+        Some(Locked {
+            seq: &self.seq,
+            next: seq + 2,
+        })
+    }
+}
+
+/// Releases a stripe, even if the holder unwinds.
+struct Locked<'a> {
+    seq: &'a AtomicUsize,
+    next: usize,
+}
+
+impl Drop for Locked<'_> {
+    fn drop(&mut self) {
+        // Release: a reader that sees the even number also sees the value.
+        self.seq.store(self.next, Ordering::Release);
+    }
+}
+
+impl<T: Copy + Default> Default for AtomicCell<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: Copy> From<T> for AtomicCell<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: Copy + fmt::Debug, const NATIVE: bool> fmt::Debug for AtomicCell<T, NATIVE> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AtomicCell").field(&self.load()).finish()
+    }
+}
+
+#[test]
+fn atomic_cell_operations() {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[repr(align(4))]
+    struct Pair(u16, u16);
+    // Safety: two `u16`s fill all four bytes.
+    unsafe impl NoPadding for Pair {}
+    #[derive(Clone, Copy)]
+    #[repr(align(4))]
+    struct Padded(u16, u8);
+    assert!(AtomicCell::<Pair, true>::is_lock_free());
+    assert!(AtomicCell::<u32, true>::is_lock_free());
+    assert!(!AtomicCell::<[u64; 3], true>::is_lock_free());
+    assert!(!AtomicCell::<u32>::is_lock_free());
+    assert!(!AtomicCell::<Padded>::is_lock_free());
+    assert!(!AtomicCell::<(u8, u16)>::is_lock_free());
+
+    let pair = AtomicCell::new_native(Pair(1, 2));
+    assert_eq!(pair.swap(Pair(3, 4)), Pair(1, 2));
+    assert_eq!(
+        pair.compare_exchange(Pair(1, 2), Pair(5, 6)),
+        Err(Pair(3, 4))
+    );
+    assert_eq!(
+        pair.compare_exchange(Pair(3, 4), Pair(5, 6)),
+        Ok(Pair(3, 4))
+    );
+    assert_eq!(pair.fetch_update(|p| Some(Pair(p.1, p.0))), Ok(Pair(5, 6)));
+    assert_eq!(pair.load(), Pair(6, 5));
+
+    let padded = AtomicCell::new(Padded(1, 2));
+    padded.store(Padded(3, 4));
+    let Padded(a, b) = padded.load();
+    assert_eq!((a, b), (3, 4));
+
+    let big = AtomicCell::new([1u64, 2, 3]);
+    big.store([4, 5, 6]);
+    assert_eq!(big.compare_exchange([4, 5, 6], [7, 8, 9]), Ok([4, 5, 6]));
+    assert_eq!(big.fetch_update(|_| None), Err([7, 8, 9]));
+    assert_eq!(big.into_inner(), [7, 8, 9]);
+}
+
+#[test]
+fn atomic_cell_locked_updates_are_atomic() {
+    // Too big for a native atomic. Every value keeps all four words equal, so a torn read or a
+    // lost update shows up.
+    let cell = AtomicCell::new([0u64; 4]);
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..5_000 {
+                    let v = cell.load();
+                    assert!(v.iter().all(|&x| x == v[0]), "torn read: {v:?}");
+                    cell.fetch_update(|v| Some([v[0] + 1; 4])).unwrap();
+                }
+            });
+        }
+    });
+    assert_eq!(cell.load(), [20_000; 4]);
+}
+
+#[test]
+fn atomic_cell_runs_no_user_code_under_the_lock() {
+    use std::panic::{self, AssertUnwindSafe};
+
+    // Locked, and `eq` loads the cell it is comparing against, which would spin forever if it
+    // ran under the cell's lock.
+    #[derive(Debug, Clone, Copy)]
+    struct Nosy([u64; 3]);
+    static CELL: AtomicCell<Nosy> = AtomicCell::new(Nosy([1, 2, 3]));
+    impl PartialEq for Nosy {
+        fn eq(&self, other: &Self) -> bool {
+            CELL.load();
+            self.0 == other.0
+        }
+    }
+    impl Eq for Nosy {}
+
+    assert_eq!(
+        CELL.compare_exchange(Nosy([1, 2, 3]), Nosy([4, 5, 6])),
+        Ok(Nosy([1, 2, 3]))
+    );
+    assert_eq!(
+        CELL.fetch_update(|v| Some(Nosy([CELL.load().0[0], v.0[1], 0]))),
+        Ok(Nosy([4, 5, 6]))
+    );
+
+    // A panicking `f` leaves the lock free for the next operation.
+    let cell = AtomicCell::new([7u64; 3]);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        cell.fetch_update(|_| panic!("no update")).ok();
+    }));
+    assert!(result.is_err());
+    assert_eq!(cell.swap([8; 3]), [7; 3]);
+    assert_eq!(CELL.load(), Nosy([4, 5, 0]));
+}
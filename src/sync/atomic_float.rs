@@ -0,0 +1,180 @@
+//! `f32` and `f64` atomics, stored as their bits in the unsigned atomics.
+//!
+//! There are no floating-point read-modify-write instructions, so the arithmetic ones are the
+//! `compare_exchange` loop from `increment()` in `atomics_compare_and_exchange_id_alloc.rs`: load,
+//! compute, and try to swap in the result until no other thread changed the value in between.
+//! `compare_exchange` compares bits, so `0.0` doesn't match `-0.0`, and a NaN matches itself.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+macro_rules! atomic_float {
+    ($(#[$doc:meta])* $name:ident, $float:ty, $atomic:ty) => {
+        $(#[$doc])*
+        #[repr(transparent)]
+        pub struct $name {
+            bits: $atomic,
+        }
+
+        impl $name {
+            pub const fn new(v: $float) -> Self {
+                Self {
+                    bits: <$atomic>::new(v.to_bits()),
+                }
+            }
+
+            pub fn load(&self, order: Ordering) -> $float {
+                <$float>::from_bits(self.bits.load(order))
+            }
+
+            pub fn store(&self, v: $float, order: Ordering) {
+                self.bits.store(v.to_bits(), order);
+            }
+
+            pub fn swap(&self, v: $float, order: Ordering) -> $float {
+                <$float>::from_bits(self.bits.swap(v.to_bits(), order))
+            }
+
+            pub fn compare_exchange(
+                &self,
+                current: $float,
+                new: $float,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<$float, $float> {
+                self.bits
+                    .compare_exchange(current.to_bits(), new.to_bits(), success, failure)
+                    .map(<$float>::from_bits)
+                    .map_err(<$float>::from_bits)
+            }
+
+            /// Replaces the value with `f(value)` until that succeeds or `f` returns `None`, and
+            /// returns the previous value: `Ok` if it was replaced, `Err` if not.
+            pub fn fetch_update(
+                &self,
+                set_order: Ordering,
+                fetch_order: Ordering,
+                mut f: impl FnMut($float) -> Option<$float>,
+            ) -> Result<$float, $float> {
+                let mut old = self.bits.load(fetch_order);
+                loop {
+                    let Some(new) = f(<$float>::from_bits(old)) else {
+                        return Err(<$float>::from_bits(old));
+                    };
+                    match self.bits.compare_exchange_weak(
+                        old,
+                        new.to_bits(),
+                        set_order,
+                        fetch_order,
+                    ) {
+                        Ok(_) => return Ok(<$float>::from_bits(old)),
+                        Err(x) => old = x,
+                    }
+                }
+            }
+
+            /// Adds `v` and returns the previous value.
+            pub fn fetch_add(&self, v: $float, order: Ordering) -> $float {
+                self.update(order, |old| old + v)
+            }
+
+            pub fn fetch_sub(&self, v: $float, order: Ordering) -> $float {
+                self.update(order, |old| old - v)
+            }
+
+            /// Stores the maximum of the value and `v`, ignoring NaN like `max` does, and returns
+            /// the previous value.
+            pub fn fetch_max(&self, v: $float, order: Ordering) -> $float {
+                self.update(order, |old| old.max(v))
+            }
+
+            pub fn fetch_min(&self, v: $float, order: Ordering) -> $float {
+                self.update(order, |old| old.min(v))
+            }
+
+            fn update(&self, order: Ordering, f: impl Fn($float) -> $float) -> $float {
+                let mut old = self.bits.load(Ordering::Relaxed);
+                loop {
+                    let new = f(<$float>::from_bits(old)).to_bits();
+                    // The failure ordering only matters for the value we retry with, which the
+                    // successful exchange will order anyway.
+                    match self.bits.compare_exchange_weak(old, new, order, Ordering::Relaxed) {
+                        Ok(_) => return <$float>::from_bits(old),
+                        Err(x) => old = x,
+                    }
+                }
+            }
+
+            pub fn get_mut(&mut self) -> &mut $float {
+                // Safety: `repr(transparent)` over an atomic with the same size and at least the
+                // alignment of the float, and every bit pattern is a valid float.
+                unsafe { &mut *(self.bits.as_ptr().cast::<$float>()) }
+            }
+
+            pub fn into_inner(self) -> $float {
+                <$float>::from_bits(self.bits.into_inner())
+            }
+        }
+
+        impl Default for $name {
+            fn default() -> Self {
+                Self::new(0.0)
+            }
+        }
+
+        impl From<$float> for $name {
+            fn from(v: $float) -> Self {
+                Self::new(v)
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Debug::fmt(&self.load(Ordering::Relaxed), f)
+            }
+        }
+    };
+}
+
+atomic_float!(
+    /// An `f32` that threads can share, like `AtomicU32`.
+    AtomicF32,
+    f32,
+    AtomicU32
+);
+atomic_float!(
+    /// An `f64` that threads can share, like `AtomicU64`.
+    AtomicF64,
+    f64,
+    AtomicU64
+);
+
+#[test]
+fn atomic_float_arithmetic_from_many_threads() {
+    let sum = AtomicF64::new(0.0);
+    let max = AtomicF32::new(f32::NEG_INFINITY);
+    std::thread::scope(|s| {
+        for t in 0..4 {
+            let (sum, max) = (&sum, &max);
+            s.spawn(move || {
+                for i in 0..10_000 {
+                    // Small integers add up exactly, in any order.
+                    sum.fetch_add(1.0, Ordering::Relaxed);
+                    max.fetch_max((t * 10_000 + i) as f32, Ordering::Relaxed);
+                }
+            });
+        }
+    });
+    assert_eq!(sum.load(Ordering::Relaxed), 40_000.0);
+    assert_eq!(max.fetch_max(f32::NAN, Ordering::Relaxed), 39_999.0);
+    assert_eq!(max.load(Ordering::Relaxed), 39_999.0);
+
+    let mut x = AtomicF32::new(-0.0);
+    assert_eq!(
+        x.compare_exchange(0.0, 1.0, Ordering::Relaxed, Ordering::Relaxed),
+        Err(-0.0)
+    );
+    *x.get_mut() += 2.5;
+    assert_eq!(x.fetch_sub(0.5, Ordering::Relaxed), 2.5);
+    assert_eq!(x.into_inner(), 2.0);
+}
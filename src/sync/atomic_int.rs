@@ -0,0 +1,89 @@
+//! A common interface over the unsigned integer atomics, so algorithms written against
+//! `AtomicU8` in the experiments can be reused at any width.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU16, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+pub trait AtomicInt: Send + Sync {
+    type Int: Copy + Eq + Ord + fmt::Debug + fmt::Display;
+
+    /// The largest value of `Int`, widened.
+    const MAX: u64;
+
+    fn new(v: Self::Int) -> Self;
+
+    fn load(&self, order: Ordering) -> Self::Int;
+
+    fn store(&self, v: Self::Int, order: Ordering);
+
+    fn compare_exchange_weak(
+        &self,
+        current: Self::Int,
+        new: Self::Int,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<Self::Int, Self::Int>;
+
+    fn fetch_add(&self, v: Self::Int, order: Ordering) -> Self::Int;
+
+    fn widen(v: Self::Int) -> u64;
+
+    /// Narrows `v` back to `Int`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `v` doesn't fit.
+    fn narrow(v: u64) -> Self::Int;
+}
+
+macro_rules! impl_atomic_int {
+    ($($atomic:ty => $int:ty),* $(,)?) => {$(
+        impl AtomicInt for $atomic {
+            type Int = $int;
+
+            const MAX: u64 = <$int>::MAX as u64;
+
+            fn new(v: $int) -> Self {
+                <$atomic>::new(v)
+            }
+
+            fn load(&self, order: Ordering) -> $int {
+                <$atomic>::load(self, order)
+            }
+
+            fn store(&self, v: $int, order: Ordering) {
+                <$atomic>::store(self, v, order)
+            }
+
+            fn compare_exchange_weak(
+                &self,
+                current: $int,
+                new: $int,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<$int, $int> {
+                <$atomic>::compare_exchange_weak(self, current, new, success, failure)
+            }
+
+            fn fetch_add(&self, v: $int, order: Ordering) -> $int {
+                <$atomic>::fetch_add(self, v, order)
+            }
+
+            fn widen(v: $int) -> u64 {
+                v as u64
+            }
+
+            fn narrow(v: u64) -> $int {
+                <$int>::try_from(v).expect("value out of range")
+            }
+        }
+    )*};
+}
+
+impl_atomic_int! {
+    AtomicU8 => u8,
+    AtomicU16 => u16,
+    AtomicU32 => u32,
+    AtomicU64 => u64,
+    AtomicUsize => usize,
+}
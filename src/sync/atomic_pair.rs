@@ -0,0 +1,155 @@
+//! Two 32-bit values updated together through one `AtomicU64`.
+//!
+//! A `(u32, u32)` is only 4-aligned, so an [`AtomicCell`](super::AtomicCell) of one falls back to
+//! a lock. Packing the halves into a `u64` keeps it a single atomic, which is what a value with a
+//! version or a count next to it usually needs: the halves can't be seen out of step.
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A 32-bit type that can be one half of an [`AtomicPair`].
+pub trait PairHalf: Copy {
+    fn to_bits(self) -> u32;
+    fn from_bits(bits: u32) -> Self;
+}
+
+impl PairHalf for u32 {
+    fn to_bits(self) -> u32 {
+        self
+    }
+
+    fn from_bits(bits: u32) -> Self {
+        bits
+    }
+}
+
+impl PairHalf for i32 {
+    fn to_bits(self) -> u32 {
+        self as u32
+    }
+
+    fn from_bits(bits: u32) -> Self {
+        bits as i32
+    }
+}
+
+impl PairHalf for f32 {
+    fn to_bits(self) -> u32 {
+        f32::to_bits(self)
+    }
+
+    fn from_bits(bits: u32) -> Self {
+        f32::from_bits(bits)
+    }
+}
+
+pub struct AtomicPair<A, B> {
+    /// `A` in the low half, `B` in the high half.
+    bits: AtomicU64,
+    _halves: PhantomData<(A, B)>,
+}
+
+fn pack<A: PairHalf, B: PairHalf>((a, b): (A, B)) -> u64 {
+    u64::from(a.to_bits()) | u64::from(b.to_bits()) << 32
+}
+
+fn unpack<A: PairHalf, B: PairHalf>(bits: u64) -> (A, B) {
+    (A::from_bits(bits as u32), B::from_bits((bits >> 32) as u32))
+}
+
+impl<A: PairHalf, B: PairHalf> AtomicPair<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        Self {
+            bits: AtomicU64::new(pack((a, b))),
+            _halves: PhantomData,
+        }
+    }
+
+    pub fn load(&self, order: Ordering) -> (A, B) {
+        unpack(self.bits.load(order))
+    }
+
+    pub fn store(&self, pair: (A, B), order: Ordering) {
+        self.bits.store(pack(pair), order);
+    }
+
+    pub fn swap(&self, pair: (A, B), order: Ordering) -> (A, B) {
+        unpack(self.bits.swap(pack(pair), order))
+    }
+
+    /// Compares both halves bitwise, like the `AtomicU64` underneath.
+    pub fn compare_exchange(
+        &self,
+        current: (A, B),
+        new: (A, B),
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<(A, B), (A, B)> {
+        self.bits
+            .compare_exchange(pack(current), pack(new), success, failure)
+            .map(unpack)
+            .map_err(unpack)
+    }
+
+    /// Replaces the pair with `f(pair)` until that succeeds or `f` returns `None`, and returns
+    /// the previous pair: `Ok` if it was replaced, `Err` if not.
+    pub fn fetch_update(
+        &self,
+        set_order: Ordering,
+        fetch_order: Ordering,
+        mut f: impl FnMut((A, B)) -> Option<(A, B)>,
+    ) -> Result<(A, B), (A, B)> {
+        self.bits
+            .fetch_update(set_order, fetch_order, |bits| f(unpack(bits)).map(pack))
+            .map(unpack)
+            .map_err(unpack)
+    }
+
+    pub fn into_inner(self) -> (A, B) {
+        unpack(self.bits.into_inner())
+    }
+}
+
+impl<A: PairHalf + Default, B: PairHalf + Default> Default for AtomicPair<A, B> {
+    fn default() -> Self {
+        Self::new(A::default(), B::default())
+    }
+}
+
+impl<A: PairHalf + fmt::Debug, B: PairHalf + fmt::Debug> fmt::Debug for AtomicPair<A, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AtomicPair")
+            .field(&self.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+#[test]
+fn atomic_pair_halves_stay_in_step() {
+    // (value, version): every update bumps both, so they must always be equal.
+    let pair = AtomicPair::<u32, u32>::new(0, 0);
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..5_000 {
+                    let (value, version) = pair.load(Ordering::Acquire);
+                    assert_eq!(value, version);
+                    pair.fetch_update(Ordering::AcqRel, Ordering::Acquire, |(v, n)| {
+                        Some((v + 1, n + 1))
+                    })
+                    .unwrap();
+                }
+            });
+        }
+    });
+    assert_eq!(pair.load(Ordering::Relaxed), (20_000, 20_000));
+
+    let signed = AtomicPair::new(-1i32, 0.5f32);
+    assert_eq!(
+        signed.compare_exchange((-1, 0.5), (2, -0.5), Ordering::AcqRel, Ordering::Acquire),
+        Ok((-1, 0.5))
+    );
+    assert_eq!(signed.swap((3, 1.5), Ordering::AcqRel), (2, -0.5));
+    assert_eq!(signed.into_inner(), (3, 1.5));
+}
@@ -0,0 +1,63 @@
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+/// Aligns and pads `T` to a cache line, so that it never shares one with its neighbours.
+///
+/// Two atomics written by different threads on the same line make every write on one core
+/// invalidate the line in the other core's cache (false sharing), even though the threads never
+/// touch each other's data. On x86-64 the prefetcher pulls in lines in pairs, and some ARM cores
+/// have 128-byte lines, so there this pads to 128 bytes.
+#[cfg_attr(any(target_arch = "x86_64", target_arch = "aarch64"), repr(align(128)))]
+#[cfg_attr(
+    not(any(target_arch = "x86_64", target_arch = "aarch64")),
+    repr(align(64))
+)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct CachePadded<T> {
+    value: T,
+}
+
+impl<T> CachePadded<T> {
+    pub const fn new(value: T) -> Self {
+        Self { value }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for CachePadded<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T> From<T> for CachePadded<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for CachePadded<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CachePadded").field(&self.value).finish()
+    }
+}
+
+#[test]
+fn cache_padded_neighbours_are_a_line_apart() {
+    let pair = [CachePadded::new(1u8), CachePadded::new(2u8)];
+    let distance = &*pair[1] as *const u8 as usize - &*pair[0] as *const u8 as usize;
+    assert!(distance >= 64);
+    assert_eq!(distance, std::mem::align_of::<CachePadded<u8>>());
+    assert_eq!(*pair[1], 2);
+}
@@ -0,0 +1,254 @@
+//! Cooperative cancellation.
+//!
+//! `example_load_store_stop_flag()` in `atomics_locks/atomics.rs` stops its worker with a global
+//! `AtomicBool` that the worker polls between 500ms naps, so stopping can take up to 500ms. A
+//! [`CancellationToken`] is that flag plus a futex: [`CancellationToken::sleep_or_cancel`] sleeps
+//! on the flag itself, so `cancel()` wakes the sleeper immediately.
+//!
+//! Tokens form a tree. Cancelling a token cancels all of its children (and theirs), but never its
+//! parent, so a subsystem can be shut down on its own or together with everything else.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, PoisonError, Weak};
+use std::time::{Duration, Instant};
+
+use super::futex;
+use super::Mutex;
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+#[derive(Clone)]
+pub struct CancellationToken {
+    node: Arc<Node>,
+}
+
+struct Node {
+    /// 0 until cancelled, then 1. Sleepers wait on it with a futex.
+    cancelled: AtomicU32,
+    /// Emptied by the (single) `cancel` that flips `cancelled`.
+    pending: Mutex<Pending>,
+}
+
+#[derive(Default)]
+struct Pending {
+    children: Vec<Weak<Node>>,
+    /// Keyed by the ID in their [`CancelRegistration`], so dropping that can remove them.
+    callbacks: Vec<(u64, Callback)>,
+    next_id: u64,
+}
+
+/// Keeps a callback registered with [`CancellationToken::on_cancel`]. Dropping it before the
+/// token is cancelled unregisters the callback; afterwards it does nothing.
+#[must_use = "dropping the registration unregisters the callback"]
+pub struct CancelRegistration {
+    node: Weak<Node>,
+    id: u64,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self {
+            node: Arc::new(Node {
+                cancelled: AtomicU32::new(0),
+                pending: Mutex::new(Pending::default()),
+            }),
+        }
+    }
+
+    /// A new token that is cancelled together with `self`, but can also be cancelled on its own.
+    pub fn child_token(&self) -> Self {
+        let child = Self::new();
+        let mut pending = self.node.lock();
+        if self.is_cancelled() {
+            drop(pending);
+            child.cancel();
+        } else {
+            // Forget children that have been dropped in the meantime.
+            pending.children.retain(|c| c.strong_count() > 0);
+            pending.children.push(Arc::downgrade(&child.node));
+        }
+        child
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.node.cancelled.load(Ordering::Acquire) != 0
+    }
+
+    /// Cancels this token and all of its descendants, waking every thread blocked in
+    /// [`CancellationToken::wait`] or [`CancellationToken::sleep_or_cancel`] and running the
+    /// registered callbacks on the calling thread. Cancelling twice does nothing.
+    pub fn cancel(&self) {
+        Node::cancel(&self.node);
+    }
+
+    /// Runs `f` once the token is cancelled, or right away if it already is, unless the
+    /// returned registration is dropped first.
+    pub fn on_cancel(&self, f: impl FnOnce() + Send + 'static) -> CancelRegistration {
+        let mut pending = self.node.lock();
+        let id = pending.next_id;
+        pending.next_id += 1;
+        if self.is_cancelled() {
+            drop(pending);
+            f();
+        } else {
+            pending.callbacks.push((id, Box::new(f)));
+        }
+        CancelRegistration {
+            node: Arc::downgrade(&self.node),
+            id,
+        }
+    }
+
+    /// Blocks until the token is cancelled.
+    pub fn wait(&self) {
+        while !self.is_cancelled() {
+            futex::wait(&self.node.cancelled, 0);
+        }
+    }
+
+    /// Sleeps for `duration`, waking up early if the token is cancelled.
+    /// Returns whether the token was cancelled.
+    pub fn sleep_or_cancel(&self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        while !self.is_cancelled() {
+            let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+                return false;
+            };
+            futex::wait_timeout(&self.node.cancelled, 0, Some(remaining));
+        }
+        true
+    }
+}
+
+impl Node {
+    /// Nothing runs user code while holding the lock, so poison carries no meaning here.
+    fn lock(&self) -> super::MutexGuard<'_, Pending> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn cancel(node: &Node) {
+        let (children, callbacks) = {
+            let mut pending = node.lock();
+            // Flipping the flag under the lock means a concurrent `on_cancel` or `child_token`
+            // either registers before this and gets taken below, or sees the flag afterwards.
+            if node.cancelled.swap(1, Ordering::Release) != 0 {
+                return;
+            }
+            (
+                std::mem::take(&mut pending.children),
+                std::mem::take(&mut pending.callbacks),
+            )
+        };
+        futex::wake_all(&node.cancelled);
+        for (_, callback) in callbacks {
+            callback();
+        }
+        for child in children.iter().filter_map(Weak::upgrade) {
+            Node::cancel(&child);
+        }
+    }
+}
+
+impl Drop for CancelRegistration {
+    fn drop(&mut self) {
+        // If the token is gone, so is the callback.
+        let Some(node) = self.node.upgrade() else {
+            return;
+        };
+        let mut pending = node.lock();
+        if let Some(i) = pending.callbacks.iter().position(|(id, _)| *id == self.id) {
+            // Dropped outside the lock: the callback's captures may have destructors of their
+            // own.
+            let callback = pending.callbacks.swap_remove(i);
+            drop(pending);
+            drop(callback);
+        }
+    }
+}
+
+impl fmt::Debug for CancelRegistration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancelRegistration")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("is_cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+#[test]
+fn cancel_propagates_to_children_only() {
+    let root = CancellationToken::new();
+    let child = root.child_token();
+    let grandchild = child.child_token();
+    let sibling = root.child_token();
+
+    child.cancel();
+    assert!(child.is_cancelled() && grandchild.is_cancelled());
+    assert!(!root.is_cancelled() && !sibling.is_cancelled());
+
+    root.cancel();
+    assert!(sibling.is_cancelled());
+    // Children of an already cancelled token start out cancelled.
+    assert!(root.child_token().is_cancelled());
+}
+
+#[test]
+fn cancel_wakes_sleepers_immediately() {
+    let token = CancellationToken::new();
+    let start = Instant::now();
+    std::thread::scope(|s| {
+        let sleeper = s.spawn(|| token.sleep_or_cancel(Duration::from_secs(30)));
+        let waiter = s.spawn(|| token.child_token().wait());
+        std::thread::sleep(Duration::from_millis(20));
+        token.cancel();
+        assert!(sleeper.join().unwrap());
+        waiter.join().unwrap();
+    });
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert!(!CancellationToken::new().sleep_or_cancel(Duration::from_millis(1)));
+}
+
+#[test]
+fn cancel_runs_callbacks_once() {
+    use std::sync::atomic::AtomicUsize;
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let token = CancellationToken::new();
+    let child = token.child_token();
+    let count = |calls: &Arc<AtomicUsize>| {
+        let calls = calls.clone();
+        move || {
+            calls.fetch_add(1, Ordering::Relaxed);
+        }
+    };
+    let _registrations = [
+        token.on_cancel(count(&calls)),
+        child.on_cancel(count(&calls)),
+    ];
+    // Dropped registrations don't run, and don't pile up on a long-lived token either.
+    for _ in 0..100 {
+        drop(token.on_cancel(count(&calls)));
+    }
+    assert_eq!(token.node.lock().callbacks.len(), 1);
+
+    token.cancel();
+    token.cancel();
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+    let _late = token.on_cancel(count(&calls));
+    assert_eq!(calls.load(Ordering::Relaxed), 3);
+}
@@ -0,0 +1,552 @@
+//! Multi-producer, multi-consumer channels.
+//!
+//! This packages up the `Mutex<VecDeque>` + `Condvar` producer/consumer queue from
+//! `thread_condvar()` in `atomics_locks/threads.rs`, adding the two things the hand-written
+//! version lacks: a way to stop (the channel is *disconnected* once every `Sender` or every
+//! `Receiver` is gone) and, for [`bounded`] channels, back-pressure on fast producers.
+//!
+//! Both ends can be cloned. Each message is received by exactly one receiver.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, PoisonError};
+use std::time::{Duration, Instant};
+
+use super::{Condvar, Mutex, MutexGuard};
+
+/// Creates a channel that holds at most `cap` messages; senders block while it is full.
+///
+/// # Panics
+///
+/// Panics if `cap` is zero.
+pub fn bounded<T>(cap: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(cap > 0, "channel capacity must be non-zero");
+    channel(Some(cap))
+}
+
+/// Creates a channel with no capacity limit; sending never blocks.
+pub fn unbounded<T>() -> (Sender<T>, Receiver<T>) {
+    channel(None)
+}
+
+fn channel<T>(cap: Option<usize>) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            queue: VecDeque::new(),
+            senders: 1,
+            receivers: 1,
+        }),
+        cap,
+        not_empty: Condvar::new(),
+        not_full: Condvar::new(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    senders: usize,
+    receivers: usize,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    cap: Option<usize>,
+    not_empty: Condvar,
+    not_full: Condvar,
+}
+
+impl<T> Shared<T> {
+    /// Nothing panics while holding the lock, so poison carries no meaning here.
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn is_full(&self, state: &State<T>) -> bool {
+        self.cap.is_some_and(|cap| state.queue.len() >= cap)
+    }
+
+    /// Waits until there is room for a message or every receiver is gone. Gives up once
+    /// `deadline` passes and returns the guard regardless; the caller re-checks the state.
+    fn wait_not_full<'a>(
+        &self,
+        state: MutexGuard<'a, State<T>>,
+        deadline: Option<Instant>,
+    ) -> MutexGuard<'a, State<T>> {
+        let full = |s: &mut State<T>| s.receivers > 0 && self.is_full(s);
+        match deadline {
+            None => self
+                .not_full
+                .wait_while(state, full)
+                .unwrap_or_else(PoisonError::into_inner),
+            Some(deadline) => {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                if timeout.is_zero() {
+                    return state;
+                }
+                self.not_full
+                    .wait_timeout_while(state, timeout, full)
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0
+            }
+        }
+    }
+
+    fn wait_not_empty<'a>(
+        &self,
+        state: MutexGuard<'a, State<T>>,
+        deadline: Option<Instant>,
+    ) -> MutexGuard<'a, State<T>> {
+        let empty = |s: &mut State<T>| s.senders > 0 && s.queue.is_empty();
+        match deadline {
+            None => self
+                .not_empty
+                .wait_while(state, empty)
+                .unwrap_or_else(PoisonError::into_inner),
+            Some(deadline) => {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                if timeout.is_zero() {
+                    return state;
+                }
+                self.not_empty
+                    .wait_timeout_while(state, timeout, empty)
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0
+            }
+        }
+    }
+
+    fn send(&self, msg: T, deadline: Option<Instant>) -> Result<(), SendTimeoutError<T>> {
+        let mut state = self.lock();
+        if self.is_full(&state) {
+            state = self.wait_not_full(state, deadline);
+        }
+        if state.receivers == 0 {
+            return Err(SendTimeoutError::Disconnected(msg));
+        }
+        if self.is_full(&state) {
+            return Err(SendTimeoutError::Timeout(msg));
+        }
+        state.queue.push_back(msg);
+        drop(state);
+        self.not_empty.notify_one();
+        Ok(())
+    }
+
+    fn recv(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        let mut state = self.lock();
+        if state.queue.is_empty() {
+            state = self.wait_not_empty(state, deadline);
+        }
+        match state.queue.pop_front() {
+            Some(msg) => {
+                drop(state);
+                if self.cap.is_some() {
+                    self.not_full.notify_one();
+                }
+                Ok(msg)
+            }
+            // Messages sent before the last sender went away are still delivered.
+            None if state.senders == 0 => Err(RecvTimeoutError::Disconnected),
+            None => Err(RecvTimeoutError::Timeout),
+        }
+    }
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends a message, blocking while a bounded channel is full.
+    ///
+    /// Fails, handing the message back, if every receiver has been dropped.
+    pub fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        self.shared
+            .send(msg, None)
+            .map_err(|e| SendError(e.into_inner()))
+    }
+
+    pub fn try_send(&self, msg: T) -> Result<(), TrySendError<T>> {
+        // An elapsed deadline never waits, it only checks the state once.
+        self.shared
+            .send(msg, Some(Instant::now()))
+            .map_err(|e| match e {
+                SendTimeoutError::Timeout(msg) => TrySendError::Full(msg),
+                SendTimeoutError::Disconnected(msg) => TrySendError::Disconnected(msg),
+            })
+    }
+
+    pub fn send_timeout(&self, msg: T, timeout: Duration) -> Result<(), SendTimeoutError<T>> {
+        self.shared.send(msg, Some(Instant::now() + timeout))
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.lock().queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> Option<usize> {
+        self.shared.cap
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            drop(state);
+            // Wake every blocked receiver so it can observe the disconnect.
+            self.shared.not_empty.notify_all();
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("Sender { .. }")
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Blocks until a message arrives. Fails once the channel is empty and every sender has been
+    /// dropped.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.shared.recv(None).map_err(|_| RecvError)
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.shared.recv(Some(Instant::now())).map_err(|e| match e {
+            RecvTimeoutError::Timeout => TryRecvError::Empty,
+            RecvTimeoutError::Disconnected => TryRecvError::Disconnected,
+        })
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.shared.recv(Some(Instant::now() + timeout))
+    }
+
+    /// A blocking iterator that ends when the channel is disconnected.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { rx: self }
+    }
+
+    /// An iterator over the messages already in the channel; never blocks.
+    pub fn try_iter(&self) -> TryIter<'_, T> {
+        TryIter { rx: self }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.lock().queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> Option<usize> {
+        self.shared.cap
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().receivers += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.receivers -= 1;
+        if state.receivers == 0 {
+            drop(state);
+            self.shared.not_full.notify_all();
+        }
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("Receiver { .. }")
+    }
+}
+
+pub struct Iter<'a, T> {
+    rx: &'a Receiver<T>,
+}
+
+impl<T> Iterator for Iter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+pub struct TryIter<'a, T> {
+    rx: &'a Receiver<T>,
+}
+
+impl<T> Iterator for TryIter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.try_recv().ok()
+    }
+}
+
+pub struct IntoIter<T> {
+    rx: Receiver<T>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { rx: self }
+    }
+}
+
+/// The message could not be sent because every receiver is gone.
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub struct SendError<T>(pub T);
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum TrySendError<T> {
+    Full(T),
+    Disconnected(T),
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum SendTimeoutError<T> {
+    Timeout(T),
+    Disconnected(T),
+}
+
+impl<T> SendTimeoutError<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            Self::Timeout(msg) | Self::Disconnected(msg) => msg,
+        }
+    }
+}
+
+/// The channel is empty and every sender is gone.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct RecvError;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TryRecvError {
+    Empty,
+    Disconnected,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RecvTimeoutError {
+    Timeout,
+    Disconnected,
+}
+
+// The send errors carry the message back, but `T` need not be `Debug`, so like std we print only
+// the variant.
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("SendError { .. }")
+    }
+}
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full(_) => f.pad("Full(..)"),
+            Self::Disconnected(_) => f.pad("Disconnected(..)"),
+        }
+    }
+}
+
+impl<T> fmt::Debug for SendTimeoutError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout(_) => f.pad("Timeout(..)"),
+            Self::Disconnected(_) => f.pad("Disconnected(..)"),
+        }
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("sending on a disconnected channel")
+    }
+}
+
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full(_) => f.pad("sending on a full channel"),
+            Self::Disconnected(_) => f.pad("sending on a disconnected channel"),
+        }
+    }
+}
+
+impl<T> fmt::Display for SendTimeoutError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout(_) => f.pad("timed out waiting on send operation"),
+            Self::Disconnected(_) => f.pad("sending on a disconnected channel"),
+        }
+    }
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("receiving on an empty and disconnected channel")
+    }
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => f.pad("receiving on an empty channel"),
+            Self::Disconnected => f.pad("receiving on an empty and disconnected channel"),
+        }
+    }
+}
+
+impl fmt::Display for RecvTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout => f.pad("timed out waiting on receive operation"),
+            Self::Disconnected => f.pad("channel is empty and sending half is closed"),
+        }
+    }
+}
+
+impl<T> Error for SendError<T> {}
+impl<T> Error for TrySendError<T> {}
+impl<T> Error for SendTimeoutError<T> {}
+impl Error for RecvError {}
+impl Error for TryRecvError {}
+impl Error for RecvTimeoutError {}
+
+#[test]
+fn channel_mpmc_delivers_each_message_once() {
+    const PRODUCERS: u64 = 4;
+    const ITEMS: u64 = 10_000;
+
+    let (tx, rx) = bounded(16);
+    let total = std::thread::scope(|s| {
+        for p in 0..PRODUCERS {
+            let tx = tx.clone();
+            s.spawn(move || {
+                for i in 0..ITEMS {
+                    tx.send(p * ITEMS + i).unwrap();
+                }
+            });
+        }
+        drop(tx);
+
+        let consumers: Vec<_> = (0..4)
+            .map(|_| {
+                let rx = rx.clone();
+                s.spawn(move || rx.iter().sum::<u64>())
+            })
+            .collect();
+        drop(rx);
+        consumers
+            .into_iter()
+            .map(|c| c.join().unwrap())
+            .sum::<u64>()
+    });
+
+    let n = PRODUCERS * ITEMS;
+    assert_eq!(total, n * (n - 1) / 2);
+}
+
+#[test]
+fn channel_disconnect() {
+    let (tx, rx) = unbounded();
+    tx.send(1).unwrap();
+    drop(tx);
+    // Buffered messages survive the disconnect.
+    assert_eq!(rx.recv(), Ok(1));
+    assert_eq!(rx.recv(), Err(RecvError));
+    assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+
+    let (tx, rx) = bounded(1);
+    drop(rx);
+    assert_eq!(tx.send(2).unwrap_err().0, 2);
+}
+
+#[test]
+fn channel_bounded_try_and_timeout() {
+    let (tx, rx) = bounded(1);
+    assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    tx.try_send(1).unwrap();
+    assert!(matches!(tx.try_send(2), Err(TrySendError::Full(2))));
+    assert!(matches!(
+        tx.send_timeout(3, Duration::from_millis(10)),
+        Err(SendTimeoutError::Timeout(3))
+    ));
+    assert_eq!(rx.recv_timeout(Duration::from_millis(10)), Ok(1));
+    assert_eq!(
+        rx.recv_timeout(Duration::from_millis(10)),
+        Err(RecvTimeoutError::Timeout)
+    );
+}
+
+#[test]
+fn channel_blocked_sender_wakes_on_receiver_drop() {
+    let (tx, rx) = bounded(1);
+    tx.send(0).unwrap();
+    std::thread::scope(|s| {
+        let h = s.spawn(|| tx.send(1));
+        std::thread::sleep(Duration::from_millis(10));
+        drop(rx);
+        assert_eq!(h.join().unwrap().unwrap_err().0, 1);
+    });
+}
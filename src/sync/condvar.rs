@@ -0,0 +1,223 @@
+//! A condition variable to pair with [`Mutex`].
+//!
+//! Waiters sleep on a futex whose value is a notification counter rather than a flag. A waiter
+//! reads the counter *before* unlocking the mutex and then asks the kernel to sleep only if the
+//! counter still has that value. A `notify_*` that slips in between the unlock and the sleep has
+//! already bumped the counter, so the `FUTEX_WAIT` returns immediately instead of missing it.
+//!
+//! The number of waiters is tracked separately, so notifying a condition variable nobody is
+//! waiting on costs a single load and no syscall.
+
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::LockResult;
+use std::time::{Duration, Instant};
+
+use super::futex;
+use super::mutex::{Mutex, MutexGuard};
+
+pub struct Condvar {
+    counter: AtomicU32,
+    num_waiters: AtomicUsize,
+}
+
+/// Whether a [`Condvar::wait_timeout`] returned because its time ran out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            counter: AtomicU32::new(0),
+            num_waiters: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn notify_one(&self) {
+        if self.num_waiters.load(Ordering::Relaxed) > 0 {
+            self.counter.fetch_add(1, Ordering::Relaxed);
+            futex::wake_one(&self.counter);
+        }
+    }
+
+    pub fn notify_all(&self) {
+        if self.num_waiters.load(Ordering::Relaxed) > 0 {
+            self.counter.fetch_add(1, Ordering::Relaxed);
+            futex::wake_all(&self.counter);
+        }
+    }
+
+    /// Unlocks the mutex, sleeps until notified, and locks it again.
+    ///
+    /// Like every condition variable this may wake spuriously; prefer [`Condvar::wait_while`].
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> LockResult<MutexGuard<'a, T>> {
+        self.wait_until(guard, None).0
+    }
+
+    /// Waits for as long as `condition` holds for the protected value.
+    pub fn wait_while<'a, T, F>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: F,
+    ) -> LockResult<MutexGuard<'a, T>>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard)?;
+        }
+        Ok(guard)
+    }
+
+    /// Like [`Condvar::wait`], but gives up after `timeout`.
+    pub fn wait_timeout<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Duration,
+    ) -> LockResult<(MutexGuard<'a, T>, WaitTimeoutResult)> {
+        let (guard, timed_out) = self.wait_until(guard, Some(timeout));
+        match guard {
+            Ok(guard) => Ok((guard, WaitTimeoutResult(timed_out))),
+            Err(e) => Err(std::sync::PoisonError::new((
+                e.into_inner(),
+                WaitTimeoutResult(timed_out),
+            ))),
+        }
+    }
+
+    /// Like [`Condvar::wait_while`], but gives up after `timeout`, returning the guard even if
+    /// `condition` still holds.
+    pub fn wait_timeout_while<'a, T, F>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        timeout: Duration,
+        mut condition: F,
+    ) -> LockResult<(MutexGuard<'a, T>, WaitTimeoutResult)>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        let deadline = Instant::now() + timeout;
+        while condition(&mut *guard) {
+            let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+                return Ok((guard, WaitTimeoutResult(true)));
+            };
+            guard = self.wait_timeout(guard, remaining)?.0;
+        }
+        Ok((guard, WaitTimeoutResult(false)))
+    }
+
+    fn wait_until<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Option<Duration>,
+    ) -> (LockResult<MutexGuard<'a, T>>, bool) {
+        self.num_waiters.fetch_add(1, Ordering::Relaxed);
+
+        // Read the counter while still holding the lock, so any notification sent after we
+        // unlock will make the futex value differ from `counter_value`.
+        let counter_value = self.counter.load(Ordering::Relaxed);
+
+        let mutex: &'a Mutex<T> = guard.mutex;
+        drop(guard);
+
+        let woken = futex::wait_timeout(&self.counter, counter_value, timeout);
+
+        self.num_waiters.fetch_sub(1, Ordering::Relaxed);
+
+        (mutex.lock(), !woken)
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn condvar_no_lost_notifications() {
+    use std::collections::VecDeque;
+
+    const PRODUCERS: usize = 4;
+    const ITEMS: usize = 10_000;
+
+    let queue = Mutex::new(VecDeque::new());
+    let not_empty = Condvar::new();
+    let consumed = AtomicUsize::new(0);
+
+    std::thread::scope(|s| {
+        for _ in 0..PRODUCERS {
+            s.spawn(|| {
+                for i in 0..ITEMS {
+                    queue.lock().unwrap().push_back(i);
+                    not_empty.notify_one();
+                }
+            });
+        }
+        for _ in 0..PRODUCERS {
+            s.spawn(|| {
+                for _ in 0..ITEMS {
+                    let mut q = not_empty
+                        .wait_while(queue.lock().unwrap(), |q| q.is_empty())
+                        .unwrap();
+                    q.pop_front().unwrap();
+                    consumed.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        }
+    });
+
+    assert_eq!(consumed.into_inner(), PRODUCERS * ITEMS);
+    assert!(queue.into_inner().unwrap().is_empty());
+}
+
+#[test]
+fn condvar_notify_all_wakes_every_waiter() {
+    let ready = Mutex::new(false);
+    let cv = Condvar::new();
+    let woken = AtomicUsize::new(0);
+
+    std::thread::scope(|s| {
+        for _ in 0..8 {
+            s.spawn(|| {
+                let _g = cv.wait_while(ready.lock().unwrap(), |r| !*r).unwrap();
+                woken.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+        std::thread::sleep(Duration::from_millis(20));
+        *ready.lock().unwrap() = true;
+        cv.notify_all();
+    });
+
+    assert_eq!(woken.into_inner(), 8);
+}
+
+#[test]
+fn condvar_wait_timeout() {
+    let m = Mutex::new(0);
+    let cv = Condvar::new();
+
+    let (g, r) = cv
+        .wait_timeout(m.lock().unwrap(), Duration::from_millis(10))
+        .unwrap();
+    assert!(r.timed_out());
+    drop(g);
+
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            std::thread::sleep(Duration::from_millis(10));
+            *m.lock().unwrap() = 1;
+            cv.notify_one();
+        });
+        let (g, r) = cv
+            .wait_timeout_while(m.lock().unwrap(), Duration::from_secs(10), |n| *n == 0)
+            .unwrap();
+        assert!(!r.timed_out());
+        assert_eq!(*g, 1);
+    });
+}
@@ -0,0 +1,58 @@
+//! Thin wrappers around the Linux `futex(2)` syscall.
+//!
+//! A futex lets a thread sleep until another thread wakes it, but only if an `AtomicU32` still
+//! holds the value the sleeper expects. The check and the sleep happen atomically inside the
+//! kernel, which is what makes it impossible to miss a wake-up that races with going to sleep.
+
+use std::sync::atomic::AtomicU32;
+use std::time::Duration;
+
+/// Blocks while `*a == expected`. May return spuriously.
+pub fn wait(a: &AtomicU32, expected: u32) {
+    wait_timeout(a, expected, None);
+}
+
+/// Like [`wait`], but gives up after `timeout`. Returns `false` if the timeout elapsed.
+pub fn wait_timeout(a: &AtomicU32, expected: u32, timeout: Option<Duration>) -> bool {
+    let ts = timeout.map(|t| libc::timespec {
+        tv_sec: t.as_secs().min(libc::time_t::MAX as u64) as libc::time_t,
+        tv_nsec: t.subsec_nanos() as _,
+    });
+    let ts_ptr = ts
+        .as_ref()
+        .map_or(std::ptr::null(), |ts| ts as *const libc::timespec);
+    // Safety: `a` is a valid, aligned u32 for the duration of the call and `ts_ptr` is either
+    // null or points to a timespec that outlives the syscall.
+    let r = unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            a as *const AtomicU32,
+            libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+            expected,
+            ts_ptr,
+        )
+    };
+    !(r < 0 && std::io::Error::last_os_error().raw_os_error() == Some(libc::ETIMEDOUT))
+}
+
+/// Wakes at most one thread blocked in [`wait`] on `a`.
+pub fn wake_one(a: &AtomicU32) {
+    wake(a, 1);
+}
+
+/// Wakes every thread blocked in [`wait`] on `a`.
+pub fn wake_all(a: &AtomicU32) {
+    wake(a, i32::MAX);
+}
+
+fn wake(a: &AtomicU32, n: i32) {
+    // Safety: FUTEX_WAKE only uses the address as a key; it never dereferences it.
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            a as *const AtomicU32,
+            libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+            n,
+        );
+    }
+}
@@ -0,0 +1,311 @@
+//! Atomic ID allocation, generalising `allocate_new_id()` from
+//! `atomics_compare_and_exchange_id_alloc.rs`.
+//!
+//! IDs are handed out from `0..limit` by a `compare_exchange_weak` loop on a counter, exactly as
+//! in the experiment. What happens once the counter reaches `limit` is up to the [`Overflow`]
+//! policy instead of a hard-coded `assert!`.
+//!
+//! With recycling enabled, released IDs go to a free-list bitmap, one bit per ID. Allocation
+//! prefers recycled IDs, so a table indexed by ID stays dense. Setting and clearing bits are
+//! single `fetch_or`/`compare_exchange` operations, so the free list is lock-free.
+
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+use super::atomic_int::AtomicInt;
+use super::futex;
+
+/// What [`IdAllocator::allocate`] does when every ID in `0..limit` is in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Panic, like the original `assert!(current < 100, "ID overflowed")`.
+    Panic,
+    /// Return [`IdExhausted`].
+    Error,
+    /// Start again from 0. IDs are then only unique among the last `limit` allocations. With a
+    /// `limit` of 0 there is nothing to wrap around to, so this returns [`IdExhausted`].
+    Wrap,
+    /// Sleep until another thread releases an ID. Requires recycling.
+    Block,
+}
+
+/// Every ID is in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdExhausted;
+
+impl fmt::Display for IdExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("no IDs left to allocate")
+    }
+}
+
+impl Error for IdExhausted {}
+
+/// The largest `limit` that may be combined with recycling: one bit per ID, so 2 MiB of bitmap.
+pub const MAX_RECYCLING_LIMIT: u64 = 1 << 24;
+
+pub struct IdAllocator<A: AtomicInt> {
+    next: A,
+    limit: u64,
+    on_overflow: Overflow,
+    free: Option<FreeList>,
+}
+
+impl<A: AtomicInt> IdAllocator<A> {
+    /// Hands out each ID in `0..limit` at most once (or cyclically, with [`Overflow::Wrap`]).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` doesn't fit in `A`, or if `on_overflow` is [`Overflow::Block`]: without
+    /// recycling nothing could ever wake the blocked thread.
+    pub fn new(limit: u64, on_overflow: Overflow) -> Self {
+        assert!(
+            on_overflow != Overflow::Block,
+            "Overflow::Block needs a recycling allocator"
+        );
+        Self::with_free_list(limit, on_overflow, None)
+    }
+
+    /// Like [`IdAllocator::new`], but IDs passed to [`IdAllocator::release`] are handed out again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` doesn't fit in `A` or exceeds [`MAX_RECYCLING_LIMIT`], or if
+    /// `on_overflow` is [`Overflow::Wrap`], which would hand out IDs still on the free list.
+    pub fn recycling(limit: u64, on_overflow: Overflow) -> Self {
+        assert!(
+            on_overflow != Overflow::Wrap,
+            "Overflow::Wrap can't be combined with recycling"
+        );
+        assert!(
+            limit <= MAX_RECYCLING_LIMIT,
+            "limit too large for recycling"
+        );
+        Self::with_free_list(limit, on_overflow, Some(FreeList::new(limit)))
+    }
+
+    fn with_free_list(limit: u64, on_overflow: Overflow, free: Option<FreeList>) -> Self {
+        // The counter has to be able to hold `limit` itself, to mark the IDs as exhausted.
+        assert!(limit <= A::MAX, "limit doesn't fit in the atomic type");
+        Self {
+            next: A::new(A::narrow(0)),
+            limit,
+            on_overflow,
+            free,
+        }
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    pub fn allocate(&self) -> Result<A::Int, IdExhausted> {
+        loop {
+            // Read the release count before looking for a free ID, so that a release racing with
+            // our search makes the futex wait below return immediately.
+            let releases = self.free.as_ref().map(FreeList::releases);
+            if let Some(id) = self.free.as_ref().and_then(FreeList::pop) {
+                return Ok(A::narrow(id));
+            }
+            if let Some(id) = self.fresh() {
+                return Ok(id);
+            }
+            match self.on_overflow {
+                Overflow::Panic => panic!("ID overflowed"),
+                Overflow::Error => return Err(IdExhausted),
+                // `fresh` wraps around by itself, so it only runs out if there are no IDs at all.
+                Overflow::Wrap => return Err(IdExhausted),
+                Overflow::Block => self.free.as_ref().unwrap().wait(releases.unwrap()),
+            }
+        }
+    }
+
+    /// Returns `id` to the allocator. Without recycling this does nothing.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` was never allocated or is released twice.
+    pub fn release(&self, id: A::Int) {
+        let Some(free) = &self.free else {
+            return;
+        };
+        let id = A::widen(id);
+        assert!(
+            id < A::widen(self.next.load(Ordering::Relaxed)),
+            "ID {id} was never allocated"
+        );
+        free.push(id);
+    }
+
+    fn fresh(&self) -> Option<A::Int> {
+        let mut current = self.next.load(Ordering::Relaxed);
+        loop {
+            let id = A::widen(current);
+            if id >= self.limit {
+                return None;
+            }
+            let next = if self.on_overflow == Overflow::Wrap && id + 1 == self.limit {
+                0
+            } else {
+                id + 1
+            };
+            match self.next.compare_exchange_weak(
+                current,
+                A::narrow(next),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(id) => return Some(id),
+                Err(x) => current = x,
+            }
+        }
+    }
+}
+
+impl<A: AtomicInt> fmt::Debug for IdAllocator<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IdAllocator")
+            .field("next", &self.next.load(Ordering::Relaxed))
+            .field("limit", &self.limit)
+            .field("on_overflow", &self.on_overflow)
+            .field("recycling", &self.free.is_some())
+            .finish()
+    }
+}
+
+struct FreeList {
+    /// Bit `i % 64` of word `i / 64` is set while ID `i` is free for reuse.
+    bits: Box<[AtomicU64]>,
+    /// How many bits are set. Lets `pop` skip scanning the bitmap when it's empty.
+    len: AtomicUsize,
+    /// Bumped on every release; blocked allocators sleep on it.
+    releases: AtomicU32,
+    waiters: AtomicUsize,
+}
+
+impl FreeList {
+    fn new(limit: u64) -> Self {
+        Self {
+            bits: (0..limit.div_ceil(64)).map(|_| AtomicU64::new(0)).collect(),
+            len: AtomicUsize::new(0),
+            releases: AtomicU32::new(0),
+            waiters: AtomicUsize::new(0),
+        }
+    }
+
+    fn releases(&self) -> u32 {
+        self.releases.load(Ordering::SeqCst)
+    }
+
+    fn push(&self, id: u64) {
+        let mask = 1 << (id % 64);
+        // Release: whatever the previous owner did with the ID happens-before the next owner
+        // claims it with the Acquire in `pop`.
+        let old = self.bits[(id / 64) as usize].fetch_or(mask, Ordering::Release);
+        assert!(old & mask == 0, "ID {id} released twice");
+        self.len.fetch_add(1, Ordering::Relaxed);
+
+        // SeqCst pairs with `wait`: either the waiter sees the new release count and doesn't
+        // sleep, or we see its waiter count and wake it.
+        self.releases.fetch_add(1, Ordering::SeqCst);
+        if self.waiters.load(Ordering::SeqCst) > 0 {
+            futex::wake_one(&self.releases);
+        }
+    }
+
+    fn pop(&self) -> Option<u64> {
+        if self.len.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+        for (i, word) in self.bits.iter().enumerate() {
+            let mut bits = word.load(Ordering::Relaxed);
+            while bits != 0 {
+                let bit = bits.trailing_zeros();
+                match word.compare_exchange_weak(
+                    bits,
+                    bits & !(1 << bit),
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        self.len.fetch_sub(1, Ordering::Relaxed);
+                        return Some(i as u64 * 64 + bit as u64);
+                    }
+                    Err(x) => bits = x,
+                }
+            }
+        }
+        None
+    }
+
+    fn wait(&self, releases: u32) {
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        futex::wait(&self.releases, releases);
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn id_alloc_error_and_wrap() {
+    use std::sync::atomic::AtomicU8;
+
+    let ids = IdAllocator::<AtomicU8>::new(100, Overflow::Error);
+    for i in 0..100 {
+        assert_eq!(ids.allocate(), Ok(i));
+    }
+    assert_eq!(ids.allocate(), Err(IdExhausted));
+
+    let ids = IdAllocator::<AtomicU8>::new(3, Overflow::Wrap);
+    let got: Vec<_> = (0..7).map(|_| ids.allocate().unwrap()).collect();
+    assert_eq!(got, [0, 1, 2, 0, 1, 2, 0]);
+
+    let ids = IdAllocator::<AtomicU8>::new(0, Overflow::Wrap);
+    assert_eq!(ids.allocate(), Err(IdExhausted));
+}
+
+#[test]
+#[should_panic(expected = "ID overflowed")]
+fn id_alloc_panic() {
+    let ids = IdAllocator::<std::sync::atomic::AtomicU8>::new(1, Overflow::Panic);
+    ids.allocate().unwrap();
+    let _ = ids.allocate();
+}
+
+#[test]
+fn id_alloc_recycles_unique_ids() {
+    use std::collections::HashSet;
+
+    let ids = IdAllocator::<AtomicU32>::recycling(64, Overflow::Error);
+    let held = super::Mutex::new(HashSet::new());
+    std::thread::scope(|s| {
+        for _ in 0..8 {
+            s.spawn(|| {
+                for _ in 0..10_000 {
+                    let Ok(id) = ids.allocate() else { continue };
+                    assert!(held.lock().unwrap().insert(id), "ID {id} handed out twice");
+                    assert!(held.lock().unwrap().remove(&id));
+                    ids.release(id);
+                }
+            });
+        }
+    });
+    // Recycling keeps the IDs dense: no more than one per thread was ever needed at once.
+    assert!(ids.next.load(Ordering::Relaxed) <= 8);
+}
+
+#[test]
+fn id_alloc_block_waits_for_release() {
+    use std::time::Duration;
+
+    let ids = IdAllocator::<AtomicU32>::recycling(2, Overflow::Block);
+    let a = ids.allocate().unwrap();
+    let _b = ids.allocate().unwrap();
+    std::thread::scope(|s| {
+        let waiter = s.spawn(|| ids.allocate().unwrap());
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!waiter.is_finished());
+        ids.release(a);
+        assert_eq!(waiter.join().unwrap(), a);
+    });
+}
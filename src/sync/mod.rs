@@ -0,0 +1,54 @@
+//! Synchronisation primitives built on top of atomics.
+
+mod atomic_cell;
+mod atomic_float;
+mod atomic_int;
+mod atomic_pair;
+mod cache_padded;
+#[cfg(target_os = "linux")]
+mod cancel;
+#[cfg(target_os = "linux")]
+pub mod channel;
+#[cfg(target_os = "linux")]
+mod condvar;
+#[cfg(target_os = "linux")]
+mod futex;
+#[cfg(target_os = "linux")]
+mod id_alloc;
+#[cfg(target_os = "linux")]
+mod mutex;
+#[cfg(target_os = "linux")]
+mod once_lock;
+pub mod oneshot;
+#[cfg(target_os = "linux")]
+mod publish_array;
+mod race_once;
+#[cfg(target_os = "linux")]
+mod rwlock;
+mod seq_lock;
+mod spin_lock;
+mod tagged_ptr;
+
+pub use atomic_cell::{AtomicCell, NoPadding};
+pub use atomic_float::{AtomicF32, AtomicF64};
+pub use atomic_int::AtomicInt;
+pub use atomic_pair::{AtomicPair, PairHalf};
+pub use cache_padded::CachePadded;
+#[cfg(target_os = "linux")]
+pub use cancel::{CancelRegistration, CancellationToken};
+#[cfg(target_os = "linux")]
+pub use condvar::{Condvar, WaitTimeoutResult};
+#[cfg(target_os = "linux")]
+pub use id_alloc::{IdAllocator, IdExhausted, Overflow, MAX_RECYCLING_LIMIT};
+#[cfg(target_os = "linux")]
+pub use mutex::{Mutex, MutexGuard};
+#[cfg(target_os = "linux")]
+pub use once_lock::{LazyLock, OnceLock};
+#[cfg(target_os = "linux")]
+pub use publish_array::{PublishArray, Snapshot as PublishSnapshot};
+pub use race_once::RaceOnce;
+#[cfg(target_os = "linux")]
+pub use rwlock::{ReadGuard as RwLockReadGuard, RwLock, WriteGuard as RwLockWriteGuard};
+pub use seq_lock::SeqLock;
+pub use spin_lock::{Guard as SpinLockGuard, SpinLock};
+pub use tagged_ptr::{AtomicTaggedPtr, TaggedPtr};
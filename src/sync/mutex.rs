@@ -0,0 +1,235 @@
+//! A blocking mutex that parks waiting threads in the kernel.
+//!
+//! The lock word has three states:
+//!
+//! * `0` - unlocked
+//! * `1` - locked, no other thread is waiting
+//! * `2` - locked, and other threads may be sleeping on the futex
+//!
+//! Keeping "waiters" separate from "locked" lets an uncontended `unlock` be a single atomic
+//! swap: the `FUTEX_WAKE` syscall is only made when the state says somebody might be asleep.
+//!
+//! Like `std::sync::Mutex`, the lock is poisoned when a guard is dropped during a panic, since the
+//! protected data may have been left half-updated. Poisoning is reported, not enforced: every
+//! accessor hands back the guard inside a [`PoisonError`] so callers can decide to recover.
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{LockResult, PoisonError, TryLockError, TryLockResult};
+
+use super::futex;
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+const CONTENDED: u32 = 2;
+
+/// How many times a contended `lock` re-checks the state before going to sleep. Critical sections
+/// are usually short, so a brief spin often avoids the syscall entirely.
+const SPIN_LIMIT: u32 = 100;
+
+pub struct Mutex<T> {
+    state: AtomicU32,
+    poisoned: AtomicBool,
+    value: UnsafeCell<T>,
+}
+
+// Safety: only one thread at a time can reach `value`, through a guard.
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicU32::new(UNLOCKED),
+            poisoned: AtomicBool::new(false),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        self.raw_lock();
+        self.guard()
+    }
+
+    pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
+        if self
+            .state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(TryLockError::WouldBlock);
+        }
+        Ok(self.guard()?)
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Relaxed)
+    }
+
+    pub fn clear_poison(&self) {
+        self.poisoned.store(false, Ordering::Relaxed);
+    }
+
+    pub fn into_inner(self) -> LockResult<T> {
+        let poisoned = self.is_poisoned();
+        let value = self.value.into_inner();
+        if poisoned {
+            Err(PoisonError::new(value))
+        } else {
+            Ok(value)
+        }
+    }
+
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        let poisoned = self.is_poisoned();
+        let value = self.value.get_mut();
+        if poisoned {
+            Err(PoisonError::new(value))
+        } else {
+            Ok(value)
+        }
+    }
+
+    /// Wraps an already-acquired lock in a guard, reporting poison.
+    fn guard(&self) -> LockResult<MutexGuard<'_, T>> {
+        let guard = MutexGuard {
+            mutex: self,
+            panicking: std::thread::panicking(),
+        };
+        if self.is_poisoned() {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+
+    pub(crate) fn raw_lock(&self) {
+        if self
+            .state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            self.lock_contended();
+        }
+    }
+
+    #[cold]
+    fn lock_contended(&self) {
+        let mut spin_count = 0;
+        while self.state.load(Ordering::Relaxed) == LOCKED && spin_count < SPIN_LIMIT {
+            spin_count += 1;
+            std::hint::spin_loop();
+        }
+
+        if self
+            .state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            return;
+        }
+
+        // We don't know whether we are the only waiter, so pessimistically mark the lock as
+        // contended. The thread that eventually unlocks will then always issue a wake-up.
+        while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            futex::wait(&self.state, CONTENDED);
+        }
+    }
+
+    pub(crate) fn raw_unlock(&self) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            futex::wake_one(&self.state);
+        }
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    pub(crate) mutex: &'a Mutex<T>,
+    /// Whether the thread was already panicking when it took the lock. Only a panic that starts
+    /// while the guard is held poisons the mutex.
+    panicking: bool,
+}
+
+// Safety: a `&MutexGuard` only gives out `&T`.
+unsafe impl<T: Sync> Sync for MutexGuard<'_, T> {}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: the very existence of this Guard guarantees we've exclusively locked the lock.
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: the very existence of this Guard guarantees we've exclusively locked the lock.
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        if !self.panicking && std::thread::panicking() {
+            self.mutex.poisoned.store(true, Ordering::Relaxed);
+        }
+        self.mutex.raw_unlock();
+    }
+}
+
+#[test]
+fn mutex_counts_every_increment() {
+    let n = Mutex::new(0);
+    std::thread::scope(|s| {
+        for _ in 0..10 {
+            s.spawn(|| {
+                for _ in 0..1000 {
+                    *n.lock().unwrap() += 1;
+                }
+            });
+        }
+    });
+    assert_eq!(n.into_inner().unwrap(), 10_000);
+}
+
+#[test]
+fn mutex_poisoned_by_panicking_holder() {
+    let m = Mutex::new(vec![1]);
+    let r = std::thread::scope(|s| {
+        s.spawn(|| {
+            let mut v = m.lock().unwrap();
+            v.push(2);
+            panic!("oops");
+        })
+        .join()
+    });
+    assert!(r.is_err());
+    assert!(m.is_poisoned());
+    let v = m.lock().unwrap_err().into_inner();
+    assert_eq!(*v, [1, 2]);
+    drop(v);
+
+    m.clear_poison();
+    assert!(m.lock().is_ok());
+}
+
+#[test]
+fn mutex_try_lock_would_block() {
+    let m = Mutex::new(());
+    let _g = m.lock().unwrap();
+    assert!(matches!(m.try_lock(), Err(TryLockError::WouldBlock)));
+}
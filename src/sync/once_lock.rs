@@ -0,0 +1,274 @@
+//! Blocking one-time initialisation.
+//!
+//! `get_key()` in `atomics_compare_and_exchange.rs` lets every thread that sees an uninitialised
+//! key compute one, and all but the winner of the `compare_exchange` throw theirs away. That is
+//! fine for a constant, but wasteful when initialisation is expensive. [`OnceLock`] instead lets
+//! exactly one thread run the initialiser while the others sleep on a futex until it is done.
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use super::futex;
+
+const INCOMPLETE: u32 = 0;
+/// An initialiser is running and nobody is waiting for it.
+const RUNNING: u32 = 1;
+/// An initialiser is running and other threads are asleep waiting for it.
+const QUEUED: u32 = 2;
+const COMPLETE: u32 = 3;
+
+pub struct OnceLock<T> {
+    state: AtomicU32,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+// Safety: the value is written by exactly one thread before COMPLETE is published, and only read
+// afterwards; any thread may end up initialising (T: Send) and all may read it (T: Sync).
+unsafe impl<T: Send + Sync> Sync for OnceLock<T> {}
+unsafe impl<T: Send> Send for OnceLock<T> {}
+
+impl<T> OnceLock<T> {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU32::new(INCOMPLETE),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Returns the value if it has been initialised; never blocks.
+    pub fn get(&self) -> Option<&T> {
+        if self.state.load(Ordering::Acquire) == COMPLETE {
+            // Safety: COMPLETE is only stored after the value was written.
+            Some(unsafe { (*self.value.get()).assume_init_ref() })
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        if *self.state.get_mut() == COMPLETE {
+            // Safety: as in `get`, and `&mut self` rules out other accesses.
+            Some(unsafe { self.value.get_mut().assume_init_mut() })
+        } else {
+            None
+        }
+    }
+
+    /// Initialises the lock with `value`, or hands it back if it was already initialised.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let mut value = Some(value);
+        self.get_or_init(|| value.take().unwrap());
+        match value {
+            None => Ok(()),
+            Some(value) => Err(value),
+        }
+    }
+
+    /// Returns the value, running `f` to create it if no thread has done so yet.
+    ///
+    /// If several threads call this at once, exactly one of them runs its `f`; the others block
+    /// until it returns. If `f` panics, the lock stays uninitialised and a waiting thread gets to
+    /// try its own initialiser instead.
+    pub fn get_or_init(&self, f: impl FnOnce() -> T) -> &T {
+        if let Some(value) = self.get() {
+            return value;
+        }
+        self.initialize(f);
+        // Safety: `initialize` only returns once the state is COMPLETE.
+        unsafe { (*self.value.get()).assume_init_ref() }
+    }
+
+    #[cold]
+    fn initialize(&self, f: impl FnOnce() -> T) {
+        let mut state = self.state.load(Ordering::Acquire);
+        loop {
+            match state {
+                COMPLETE => return,
+                INCOMPLETE => {
+                    if let Err(s) = self.state.compare_exchange_weak(
+                        INCOMPLETE,
+                        RUNNING,
+                        Ordering::Acquire,
+                        Ordering::Acquire,
+                    ) {
+                        state = s;
+                        continue;
+                    }
+                    // Resets the state if `f` unwinds, so waiters don't sleep forever.
+                    let reset = ResetOnUnwind { state: &self.state };
+                    let value = f();
+                    std::mem::forget(reset);
+                    // Safety: we won the INCOMPLETE -> RUNNING race, so we have exclusive access.
+                    unsafe { (*self.value.get()).write(value) };
+                    if self.state.swap(COMPLETE, Ordering::Release) == QUEUED {
+                        futex::wake_all(&self.state);
+                    }
+                    return;
+                }
+                RUNNING => {
+                    if let Err(s) = self.state.compare_exchange_weak(
+                        RUNNING,
+                        QUEUED,
+                        Ordering::Relaxed,
+                        Ordering::Acquire,
+                    ) {
+                        state = s;
+                        continue;
+                    }
+                    futex::wait(&self.state, QUEUED);
+                    state = self.state.load(Ordering::Acquire);
+                }
+                _ => {
+                    futex::wait(&self.state, QUEUED);
+                    state = self.state.load(Ordering::Acquire);
+                }
+            }
+        }
+    }
+
+    pub fn into_inner(mut self) -> Option<T> {
+        if *self.state.get_mut() == COMPLETE {
+            *self.state.get_mut() = INCOMPLETE;
+            // Safety: COMPLETE means initialised, and resetting the state stops `Drop` from
+            // dropping the value a second time.
+            Some(unsafe { self.value.get_mut().assume_init_read() })
+        } else {
+            None
+        }
+    }
+}
+
+struct ResetOnUnwind<'a> {
+    state: &'a AtomicU32,
+}
+
+impl Drop for ResetOnUnwind<'_> {
+    fn drop(&mut self) {
+        if self.state.swap(INCOMPLETE, Ordering::Release) == QUEUED {
+            futex::wake_all(self.state);
+        }
+    }
+}
+
+impl<T> Default for OnceLock<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for OnceLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.get() {
+            Some(v) => f.debug_tuple("OnceLock").field(v).finish(),
+            None => f.pad("OnceLock(<uninit>)"),
+        }
+    }
+}
+
+impl<T> Drop for OnceLock<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == COMPLETE {
+            // Safety: COMPLETE means initialised.
+            unsafe { self.value.get_mut().assume_init_drop() }
+        }
+    }
+}
+
+/// A value that is computed by `F` on first access, then shared.
+pub struct LazyLock<T, F = fn() -> T> {
+    once: OnceLock<T>,
+    init: UnsafeCell<Option<F>>,
+}
+
+// Safety: `init` is only taken by the one thread that runs the initialiser, under the OnceLock.
+unsafe impl<T: Send + Sync, F: Send> Sync for LazyLock<T, F> {}
+
+impl<T, F: FnOnce() -> T> LazyLock<T, F> {
+    pub const fn new(init: F) -> Self {
+        Self {
+            once: OnceLock::new(),
+            init: UnsafeCell::new(Some(init)),
+        }
+    }
+
+    /// Forces evaluation and returns the value.
+    pub fn force(this: &Self) -> &T {
+        this.once.get_or_init(|| {
+            // Safety: only the thread running the OnceLock initialiser gets here.
+            let init = unsafe { (*this.init.get()).take() };
+            match init {
+                Some(f) => f(),
+                None => panic!("LazyLock instance has previously been poisoned"),
+            }
+        })
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for LazyLock<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        Self::force(self)
+    }
+}
+
+impl<T: fmt::Debug, F> fmt::Debug for LazyLock<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.once.get() {
+            Some(v) => f.debug_tuple("LazyLock").field(v).finish(),
+            None => f.pad("LazyLock(<uninit>)"),
+        }
+    }
+}
+
+#[test]
+fn once_lock_runs_initialiser_once() {
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    let calls = AtomicUsize::new(0);
+    let lock = OnceLock::new();
+    std::thread::scope(|s| {
+        for i in 0..16 {
+            let (calls, lock) = (&calls, &lock);
+            s.spawn(move || {
+                let v = lock.get_or_init(|| {
+                    calls.fetch_add(1, Ordering::Relaxed);
+                    std::thread::sleep(Duration::from_millis(20));
+                    i
+                });
+                // Every thread sees the single winner's value.
+                assert_eq!(lock.get(), Some(v));
+            });
+        }
+    });
+    assert_eq!(calls.into_inner(), 1);
+    assert!(lock.into_inner().is_some());
+}
+
+#[test]
+fn once_lock_set_and_panic_recovery() {
+    let lock = OnceLock::new();
+    let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        lock.get_or_init(|| panic!("first initialiser fails"));
+    }));
+    assert!(r.is_err());
+    assert_eq!(lock.get(), None);
+    assert_eq!(lock.set(1), Ok(()));
+    assert_eq!(lock.set(2), Err(2));
+    assert_eq!(*lock.get_or_init(|| 3), 1);
+}
+
+#[test]
+fn lazy_lock_initialises_on_first_deref() {
+    static LAZY: LazyLock<Vec<u32>> = LazyLock::new(|| (0..4).collect());
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| assert_eq!(LAZY.len(), 4));
+        }
+    });
+    assert_eq!(*LAZY, [0, 1, 2, 3]);
+}
@@ -0,0 +1,278 @@
+//! One-shot channels: exactly one message, sent once, received once.
+//!
+//! Both ends are consumed by their operation (`send(self, ..)`, `recv(self)`), so "sent twice" or
+//! "received twice" are type errors rather than runtime panics.
+//!
+//! The message is stored inline in the channel next to a one-byte state. [`channel`] puts that in
+//! a single `Arc` so the ends can be moved anywhere; [`Channel`] can instead live on the caller's
+//! stack and lend its ends out to scoped threads, in which case nothing is allocated at all.
+//!
+//! ```text
+//! EMPTY --(receiver registers and parks)--> WAITING
+//!   |                                          |
+//!   +------(sender writes the message)---------+--> READY
+//!   +------(sender dropped without sending)----+--> DISCONNECTED
+//! ```
+
+use std::cell::UnsafeCell;
+use std::error::Error;
+use std::fmt;
+use std::mem::{ManuallyDrop, MaybeUninit};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::thread::{self, Thread};
+
+const EMPTY: u8 = 0;
+const WAITING: u8 = 1;
+const READY: u8 = 2;
+const DISCONNECTED: u8 = 3;
+
+/// Creates a one-shot channel whose ends can be sent to other threads.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let channel = Arc::new(Channel::new());
+    (
+        Sender {
+            channel: channel.clone(),
+        },
+        Receiver { channel },
+    )
+}
+
+/// The sender went away without sending anything.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct RecvError;
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("sender dropped without sending")
+    }
+}
+
+impl Error for RecvError {}
+
+/// The shared slot. Only used directly for the borrowing variant, see [`Channel::split`].
+pub struct Channel<T> {
+    message: UnsafeCell<MaybeUninit<T>>,
+    state: AtomicU8,
+    /// Written by the receiver before it moves the state to `WAITING`, read by the sender only
+    /// after it has seen `WAITING`.
+    waiter: UnsafeCell<Option<Thread>>,
+}
+
+// Safety: the state machine ensures only one thread touches `message` (and `waiter`) at a time.
+unsafe impl<T: Send> Sync for Channel<T> {}
+
+impl<T> Channel<T> {
+    pub const fn new() -> Self {
+        Self {
+            message: UnsafeCell::new(MaybeUninit::uninit()),
+            state: AtomicU8::new(EMPTY),
+            waiter: UnsafeCell::new(None),
+        }
+    }
+
+    /// Hands out both ends, borrowing the channel for as long as either of them lives.
+    ///
+    /// Splitting again after a previous round resets the channel, dropping any message that was
+    /// sent but never received.
+    pub fn split(&mut self) -> (BorrowedSender<'_, T>, BorrowedReceiver<'_, T>) {
+        *self = Self::new();
+        let channel: &Self = self;
+        (BorrowedSender { channel }, BorrowedReceiver { channel })
+    }
+
+    /// Safety: may only be called once, by the sending end.
+    unsafe fn send(&self, message: T) {
+        (*self.message.get()).write(message);
+        // Release publishes the message; Acquire makes the receiver's `waiter` visible.
+        if self.state.swap(READY, Ordering::AcqRel) == WAITING {
+            (*self.waiter.get()).as_ref().unwrap().unpark();
+        }
+    }
+
+    /// Safety: may only be called once, by the sending end, and not after `send`.
+    unsafe fn disconnect(&self) {
+        if self.state.swap(DISCONNECTED, Ordering::AcqRel) == WAITING {
+            (*self.waiter.get()).as_ref().unwrap().unpark();
+        }
+    }
+
+    /// Safety: may only be called once, by the receiving end.
+    unsafe fn recv(&self) -> Result<T, RecvError> {
+        let mut state = self.state.load(Ordering::Acquire);
+        if state == EMPTY {
+            *self.waiter.get() = Some(thread::current());
+            state = match self.state.compare_exchange(
+                EMPTY,
+                WAITING,
+                Ordering::Release,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => WAITING,
+                Err(state) => state,
+            };
+        }
+        // `park` may return spuriously, so the state is the source of truth.
+        while state == WAITING {
+            thread::park();
+            state = self.state.load(Ordering::Acquire);
+        }
+        if state == DISCONNECTED {
+            return Err(RecvError);
+        }
+        // The message is moved out; make sure `Drop` doesn't drop it a second time.
+        self.state.store(EMPTY, Ordering::Relaxed);
+        Ok((*self.message.get()).assume_init_read())
+    }
+
+    fn is_ready(&self) -> bool {
+        self.state.load(Ordering::Relaxed) == READY
+    }
+}
+
+impl<T> Default for Channel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for Channel<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == READY {
+            // Safety: READY means a message was written and nobody has taken it.
+            unsafe { self.message.get_mut().assume_init_drop() }
+        }
+    }
+}
+
+pub struct Sender<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> Sender<T> {
+    pub fn send(self, message: T) {
+        // Skip our own `Drop`, which would otherwise disconnect the channel.
+        let this = ManuallyDrop::new(self);
+        // Safety: `this` is never used again, so the Arc is moved out exactly once.
+        let channel = unsafe { std::ptr::read(&this.channel) };
+        // Safety: `send` consumes the only Sender.
+        unsafe { channel.send(message) }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // Safety: we are the only Sender, and we never sent.
+        unsafe { self.channel.disconnect() }
+    }
+}
+
+pub struct Receiver<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Blocks until the message arrives, or fails if the sender is dropped without sending.
+    pub fn recv(self) -> Result<T, RecvError> {
+        // Safety: `recv` consumes the only Receiver.
+        unsafe { self.channel.recv() }
+    }
+
+    /// Whether `recv` would return a message without blocking.
+    pub fn is_ready(&self) -> bool {
+        self.channel.is_ready()
+    }
+}
+
+pub struct BorrowedSender<'a, T> {
+    channel: &'a Channel<T>,
+}
+
+impl<T> BorrowedSender<'_, T> {
+    pub fn send(self, message: T) {
+        let channel = self.channel;
+        std::mem::forget(self);
+        // Safety: `send` consumes the only BorrowedSender for this split.
+        unsafe { channel.send(message) }
+    }
+}
+
+impl<T> Drop for BorrowedSender<'_, T> {
+    fn drop(&mut self) {
+        // Safety: we are the only BorrowedSender, and we never sent.
+        unsafe { self.channel.disconnect() }
+    }
+}
+
+pub struct BorrowedReceiver<'a, T> {
+    channel: &'a Channel<T>,
+}
+
+impl<T> BorrowedReceiver<'_, T> {
+    pub fn recv(self) -> Result<T, RecvError> {
+        // Safety: `recv` consumes the only BorrowedReceiver for this split.
+        unsafe { self.channel.recv() }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.channel.is_ready()
+    }
+}
+
+#[test]
+fn oneshot_send_and_recv() {
+    let (tx, rx) = channel();
+    let t = thread::spawn(move || {
+        thread::sleep(std::time::Duration::from_millis(10));
+        tx.send(String::from("hello"));
+    });
+    assert_eq!(rx.recv().unwrap(), "hello");
+    t.join().unwrap();
+}
+
+#[test]
+fn oneshot_sender_dropped() {
+    let (tx, rx) = channel::<i32>();
+    thread::spawn(move || drop(tx));
+    assert_eq!(rx.recv(), Err(RecvError));
+}
+
+#[test]
+fn oneshot_unreceived_message_is_dropped() {
+    use std::sync::atomic::AtomicUsize;
+
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+    struct Counted;
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let (tx, rx) = channel();
+    tx.send(Counted);
+    drop(rx);
+    assert_eq!(DROPS.load(Ordering::Relaxed), 1);
+
+    let mut ch = Channel::new();
+    let (tx, _rx) = ch.split();
+    tx.send(Counted);
+    // Re-splitting resets the channel, dropping the stale message.
+    let (tx, rx) = ch.split();
+    assert_eq!(DROPS.load(Ordering::Relaxed), 2);
+    tx.send(Counted);
+    drop(rx.recv().unwrap());
+    drop(ch);
+    assert_eq!(DROPS.load(Ordering::Relaxed), 3);
+}
+
+#[test]
+fn oneshot_borrowed_in_scope() {
+    let numbers = [1, 2, 3];
+    let mut ch = Channel::new();
+    thread::scope(|s| {
+        let (tx, rx) = ch.split();
+        s.spawn(move || tx.send(numbers.iter().sum::<i32>()));
+        assert_eq!(rx.recv(), Ok(6));
+    });
+}
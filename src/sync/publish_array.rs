@@ -0,0 +1,207 @@
+//! Batch publication of independent slots.
+//!
+//! `fences.rs` has a hundred threads that each fill one slot of a `static mut` array and then set
+//! that slot's `READY` flag with Release. The main thread loads all the flags with Relaxed and,
+//! if any is set, issues a single `fence(Acquire)` instead of paying for an acquire load per slot.
+//! [`PublishArray`] is that pattern with the `unsafe` moved inside: a slot can be published once,
+//! and [`PublishArray::snapshot`] hands out references only to slots it saw ready before its
+//! fence.
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{fence, AtomicU32, AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+
+use super::futex;
+
+const EMPTY: u8 = 0;
+/// A `publish` is writing the value.
+const WRITING: u8 = 1;
+const READY: u8 = 2;
+
+pub struct PublishArray<T, const N: usize> {
+    slots: [UnsafeCell<MaybeUninit<T>>; N],
+    states: [AtomicU8; N],
+    /// How many slots are READY. `wait_all` sleeps on it; the last `publish` wakes it.
+    published: AtomicU32,
+}
+
+// Safety: a slot is written by the one `publish` that moved it out of EMPTY, before READY is
+// released, and only read (through `&T`) after READY is acquired. Values are moved in from any
+// thread (T: Send) and shared between threads (T: Sync).
+unsafe impl<T: Send + Sync, const N: usize> Sync for PublishArray<T, N> {}
+unsafe impl<T: Send, const N: usize> Send for PublishArray<T, N> {}
+
+impl<T, const N: usize> PublishArray<T, N> {
+    pub const fn new() -> Self {
+        assert!(N <= u32::MAX as usize, "too many slots");
+        Self {
+            slots: [const { UnsafeCell::new(MaybeUninit::uninit()) }; N],
+            states: [const { AtomicU8::new(EMPTY) }; N],
+            published: AtomicU32::new(0),
+        }
+    }
+
+    /// Publishes `value` in slot `i`, or hands it back if the slot was already published.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `i >= N`.
+    pub fn publish(&self, i: usize, value: T) -> Result<(), T> {
+        if self.states[i]
+            .compare_exchange(EMPTY, WRITING, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(value);
+        }
+        // Safety: we moved the slot out of EMPTY, so nobody else writes it, and nobody reads it
+        // before READY.
+        unsafe { (*self.slots[i].get()).write(value) };
+        self.states[i].store(READY, Ordering::Release);
+        if self.published.fetch_add(1, Ordering::Relaxed) + 1 == N as u32 {
+            futex::wake_all(&self.published);
+        }
+        Ok(())
+    }
+
+    /// The slots that are ready right now. Checking them costs one relaxed load each plus a
+    /// single acquire fence.
+    pub fn snapshot(&self) -> Snapshot<'_, T, N> {
+        let ready: [bool; N] =
+            std::array::from_fn(|i| self.states[i].load(Ordering::Relaxed) == READY);
+        if ready.contains(&true) {
+            // Pairs with the Release store of every READY we saw above.
+            fence(Ordering::Acquire);
+        }
+        Snapshot { array: self, ready }
+    }
+
+    /// Waits up to `timeout` for every slot to be published, then takes a snapshot of whichever
+    /// slots are ready by then.
+    pub fn wait_all(&self, timeout: Duration) -> Snapshot<'_, T, N> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let published = self.published.load(Ordering::Relaxed);
+            if published == N as u32 {
+                break;
+            }
+            let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+                break;
+            };
+            futex::wait_timeout(&self.published, published, Some(remaining));
+        }
+        self.snapshot()
+    }
+
+    pub fn len(&self) -> usize {
+        N
+    }
+
+    pub fn is_empty(&self) -> bool {
+        N == 0
+    }
+}
+
+/// The slots of a [`PublishArray`] that were ready when the snapshot was taken.
+pub struct Snapshot<'a, T, const N: usize> {
+    array: &'a PublishArray<T, N>,
+    ready: [bool; N],
+}
+
+impl<'a, T, const N: usize> Snapshot<'a, T, N> {
+    /// The value in slot `i`, if it was ready.
+    pub fn get(&self, i: usize) -> Option<&'a T> {
+        // Safety: the slot was READY before the snapshot's acquire fence, so its value is fully
+        // written, visible to us, and never written again.
+        self.ready[i].then(|| unsafe { (*self.array.slots[i].get()).assume_init_ref() })
+    }
+
+    pub fn is_ready(&self, i: usize) -> bool {
+        self.ready[i]
+    }
+
+    /// How many slots were ready.
+    pub fn count(&self) -> usize {
+        self.ready.iter().filter(|&&r| r).count()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.count() == N
+    }
+
+    /// The ready slots, with their indices.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &'a T)> + '_ {
+        (0..N).filter_map(|i| Some((i, self.get(i)?)))
+    }
+}
+
+impl<T, const N: usize> Default for PublishArray<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: fmt::Debug, const N: usize> fmt::Debug for PublishArray<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.snapshot().fmt(f)
+    }
+}
+
+impl<T: fmt::Debug, const N: usize> fmt::Debug for Snapshot<'_, T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<T, const N: usize> Drop for PublishArray<T, N> {
+    fn drop(&mut self) {
+        for (slot, state) in self.slots.iter_mut().zip(&mut self.states) {
+            if *state.get_mut() == READY {
+                // Safety: READY slots are initialised, and `&mut self` means nobody borrows them.
+                unsafe { slot.get_mut().assume_init_drop() };
+            }
+        }
+    }
+}
+
+#[test]
+fn publish_array_publishes_each_slot_once() {
+    let array = PublishArray::<String, 3>::new();
+    assert_eq!(array.snapshot().count(), 0);
+    assert_eq!(array.publish(1, "one".into()), Ok(()));
+    assert_eq!(array.publish(1, "uno".into()), Err("uno".into()));
+
+    let snapshot = array.snapshot();
+    assert_eq!(snapshot.get(0), None);
+    assert_eq!(snapshot.get(1).map(String::as_str), Some("one"));
+    assert!(!snapshot.is_complete());
+    // Slots published later don't show up in an earlier snapshot.
+    array.publish(0, "zero".into()).unwrap();
+    assert_eq!(snapshot.count(), 1);
+    assert_eq!(array.snapshot().count(), 2);
+}
+
+#[test]
+fn publish_array_wait_all() {
+    let array = PublishArray::<usize, 8>::new();
+    std::thread::scope(|s| {
+        for i in 0..8 {
+            let array = &array;
+            s.spawn(move || {
+                std::thread::sleep(Duration::from_millis(i as u64 * 5));
+                array.publish(i, i * i).unwrap();
+            });
+        }
+        let snapshot = array.wait_all(Duration::from_secs(30));
+        assert!(snapshot.is_complete());
+        assert!(snapshot.iter().all(|(i, &v)| v == i * i));
+    });
+
+    // Nothing more is coming, so this returns after the timeout with what there is.
+    let empty = PublishArray::<usize, 2>::new();
+    empty.publish(0, 0).unwrap();
+    let start = Instant::now();
+    assert_eq!(empty.wait_all(Duration::from_millis(20)).count(), 1);
+    assert!(start.elapsed() >= Duration::from_millis(20));
+}
@@ -0,0 +1,138 @@
+//! Racy one-time initialisation through a pointer.
+//!
+//! This is `get_data()` from `lazy_initialisation_with_indirection_rel_acq.rs` made generic: every
+//! thread that finds the slot empty builds its own `Box<T>` and tries to `compare_exchange` it in.
+//! The winner's box is published with Release; the losers free theirs and use the winner's,
+//! which they see through the Acquire on the failed exchange.
+//!
+//! Nobody ever blocks, which makes this usable where [`OnceLock`](super::OnceLock) isn't (no
+//! futex, no risk of waiting on a thread that got descheduled), at the cost of possibly running
+//! the initialiser more than once.
+
+use std::fmt;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+pub struct RaceOnce<T> {
+    ptr: AtomicPtr<T>,
+}
+
+// Safety: a `RaceOnce<T>` owns a `Box<T>` that can be built on one thread and dropped on another
+// (T: Send), and it hands out `&T` to every thread (T: Sync).
+unsafe impl<T: Send + Sync> Sync for RaceOnce<T> {}
+unsafe impl<T: Send> Send for RaceOnce<T> {}
+
+impl<T> RaceOnce<T> {
+    pub const fn new() -> Self {
+        Self {
+            ptr: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        let p = self.ptr.load(Ordering::Acquire);
+        // Safety: a non-null pointer came from `Box::into_raw` and lives as long as `self`.
+        unsafe { p.as_ref() }
+    }
+
+    /// Publishes `value`, or hands it back if another value was published first.
+    pub fn set(&self, value: Box<T>) -> Result<&T, Box<T>> {
+        let p = Box::into_raw(value);
+        match self.ptr.compare_exchange(
+            ptr::null_mut(),
+            p,
+            Ordering::Release, // success ordering (store)
+            Ordering::Acquire, // failure ordering (load)
+        ) {
+            // Safety: we just published `p`; it lives as long as `self`.
+            Ok(_) => Ok(unsafe { &*p }),
+            // Safety: p comes from Box::into_raw, and wasn't shared with other threads.
+            Err(_) => Err(unsafe { Box::from_raw(p) }),
+        }
+    }
+
+    /// Returns the published value, creating one with `f` if there is none yet.
+    ///
+    /// Racing threads may each run `f`; only one result is kept and the rest are dropped.
+    pub fn get_or_init(&self, f: impl FnOnce() -> Box<T>) -> &T {
+        if let Some(value) = self.get() {
+            return value;
+        }
+        match self.set(f()) {
+            Ok(value) => value,
+            // Another thread has already published a value, so it must be there now.
+            Err(_) => self.get().unwrap(),
+        }
+    }
+
+    pub fn into_inner(self) -> Option<Box<T>> {
+        let p = self.ptr.swap(ptr::null_mut(), Ordering::Acquire);
+        // Safety: non-null pointers came from `Box::into_raw`, and we nulled the slot so `Drop`
+        // won't free it again.
+        (!p.is_null()).then(|| unsafe { Box::from_raw(p) })
+    }
+}
+
+impl<T> Default for RaceOnce<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RaceOnce<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.get() {
+            Some(v) => f.debug_tuple("RaceOnce").field(v).finish(),
+            None => f.pad("RaceOnce(<uninit>)"),
+        }
+    }
+}
+
+impl<T> Drop for RaceOnce<T> {
+    fn drop(&mut self) {
+        let p = *self.ptr.get_mut();
+        if !p.is_null() {
+            // Safety: p came from `Box::into_raw` and nobody can borrow it any more.
+            drop(unsafe { Box::from_raw(p) });
+        }
+    }
+}
+
+#[test]
+fn race_once_keeps_one_value_and_drops_the_rest() {
+    use std::sync::atomic::AtomicUsize;
+
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+    struct Counted(usize);
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let once = RaceOnce::new();
+    let built = AtomicUsize::new(0);
+    let winner = std::thread::scope(|s| {
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let (once, built) = (&once, &built);
+                s.spawn(move || {
+                    once.get_or_init(|| {
+                        built.fetch_add(1, Ordering::Relaxed);
+                        Box::new(Counted(i))
+                    })
+                    .0
+                })
+            })
+            .collect();
+        let seen: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert!(seen.iter().all(|&v| v == seen[0]));
+        seen[0]
+    });
+
+    // Every loser's box was freed, only the winner's is still alive.
+    let built = built.into_inner();
+    assert_eq!(DROPS.load(Ordering::Relaxed), built - 1);
+    assert_eq!(once.into_inner().unwrap().0, winner);
+    assert_eq!(DROPS.load(Ordering::Relaxed), built);
+}
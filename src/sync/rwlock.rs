@@ -0,0 +1,308 @@
+//! A writer-preferring reader-writer lock, the concurrent counterpart of `RefCell`.
+//!
+//! The whole lock state lives in a single `AtomicU32`:
+//!
+//! * `u32::MAX` - write-locked
+//! * `2 * n` - read-locked by `n` readers, no writer waiting
+//! * `2 * n + 1` - read-locked by `n` readers, and a writer is waiting
+//!
+//! A waiting writer makes the state odd, and new readers refuse to join an odd state, so a steady
+//! stream of readers cannot starve a writer. Readers sleep on `state`; writers sleep on a separate
+//! wake counter so that waking one writer never has to wake all the readers as well.
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use super::futex;
+
+const WRITE_LOCKED: u32 = u32::MAX;
+
+pub struct RwLock<T> {
+    state: AtomicU32,
+    /// Incremented to wake up writers.
+    writer_wake_counter: AtomicU32,
+    value: UnsafeCell<T>,
+}
+
+// Safety: readers on several threads may share `&T`, hence `T: Sync`; writers move exclusive
+// access between threads, hence `T: Send`.
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicU32::new(0),
+            writer_wake_counter: AtomicU32::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn read(&self) -> ReadGuard<'_, T> {
+        let mut s = self.state.load(Ordering::Relaxed);
+        loop {
+            if s.is_multiple_of(2) {
+                assert!(s < WRITE_LOCKED - 2, "too many readers");
+                match self.state.compare_exchange_weak(
+                    s,
+                    s + 2,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => return ReadGuard { rwlock: self },
+                    Err(e) => s = e,
+                }
+            }
+            if !s.is_multiple_of(2) {
+                futex::wait(&self.state, s);
+                s = self.state.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Takes a read lock unless a writer holds the lock or is waiting for it.
+    pub fn try_read(&self) -> Option<ReadGuard<'_, T>> {
+        let mut s = self.state.load(Ordering::Relaxed);
+        while s.is_multiple_of(2) {
+            assert!(s < WRITE_LOCKED - 2, "too many readers");
+            match self
+                .state
+                .compare_exchange_weak(s, s + 2, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => return Some(ReadGuard { rwlock: self }),
+                Err(e) => s = e,
+            }
+        }
+        None
+    }
+
+    pub fn write(&self) -> WriteGuard<'_, T> {
+        let mut s = self.state.load(Ordering::Relaxed);
+        loop {
+            // Try to lock if unlocked.
+            if s <= 1 {
+                match self.state.compare_exchange(
+                    s,
+                    WRITE_LOCKED,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => return WriteGuard { rwlock: self },
+                    Err(e) => {
+                        s = e;
+                        continue;
+                    }
+                }
+            }
+            // Block new readers by making sure the state is odd.
+            if s.is_multiple_of(2) {
+                if let Err(e) =
+                    self.state
+                        .compare_exchange(s, s + 1, Ordering::Relaxed, Ordering::Relaxed)
+                {
+                    s = e;
+                    continue;
+                }
+            }
+            // Wait, if it's still locked.
+            let w = self.writer_wake_counter.load(Ordering::Acquire);
+            s = self.state.load(Ordering::Relaxed);
+            if s >= 2 {
+                futex::wait(&self.writer_wake_counter, w);
+                s = self.state.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn try_write(&self) -> Option<WriteGuard<'_, T>> {
+        let mut s = self.state.load(Ordering::Relaxed);
+        while s <= 1 {
+            match self
+                .state
+                .compare_exchange(s, WRITE_LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => return Some(WriteGuard { rwlock: self }),
+                Err(e) => s = e,
+            }
+        }
+        None
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    fn wake_writer(&self) {
+        self.writer_wake_counter.fetch_add(1, Ordering::Release);
+        futex::wake_one(&self.writer_wake_counter);
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+pub struct ReadGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+}
+
+impl<T> Deref for ReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: no writer can hold the lock while this guard exists.
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for ReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        // Decrement the state by 2 to remove one read-lock. If we were the last reader and a
+        // writer is waiting (state goes from 3 to 1), wake it up.
+        if self.rwlock.state.fetch_sub(2, Ordering::Release) == 3 {
+            self.rwlock.wake_writer();
+        }
+    }
+}
+
+pub struct WriteGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+}
+
+impl<'a, T> WriteGuard<'a, T> {
+    /// Atomically turns the write lock into a read lock, so no other writer can get in between.
+    pub fn downgrade(self) -> ReadGuard<'a, T> {
+        let rwlock = self.rwlock;
+        std::mem::forget(self);
+        // One reader (us), and the "writer waiting" bit cleared. Writers that were sleeping have
+        // to be woken so they set the bit again; otherwise our read unlock would not wake them.
+        rwlock.state.store(2, Ordering::Release);
+        rwlock.wake_writer();
+        futex::wake_all(&rwlock.state);
+        ReadGuard { rwlock }
+    }
+}
+
+impl<T> Deref for WriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: the very existence of this Guard guarantees we've exclusively locked the lock.
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> DerefMut for WriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: the very existence of this Guard guarantees we've exclusively locked the lock.
+        unsafe { &mut *self.rwlock.value.get() }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for WriteGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> Drop for WriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.rwlock.state.store(0, Ordering::Release);
+        self.rwlock.wake_writer();
+        futex::wake_all(&self.rwlock.state);
+    }
+}
+
+#[test]
+fn rwlock_readers_share() {
+    use std::sync::Barrier;
+
+    let lock = RwLock::new(5);
+    let barrier = Barrier::new(4);
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                let r = lock.read();
+                // Every reader reaches the barrier while holding its guard, which can only
+                // happen if all four read locks are held at once.
+                barrier.wait();
+                assert_eq!(*r, 5);
+            });
+        }
+    });
+}
+
+#[test]
+fn rwlock_writers_exclude() {
+    let lock = RwLock::new((0u64, 0u64));
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..10_000 {
+                    let mut w = lock.write();
+                    w.0 += 1;
+                    w.1 += 1;
+                }
+            });
+            s.spawn(|| {
+                for _ in 0..10_000 {
+                    let r = lock.read();
+                    assert_eq!(r.0, r.1, "reader saw a half-finished write");
+                }
+            });
+        }
+    });
+    assert_eq!(lock.into_inner(), (40_000, 40_000));
+}
+
+#[test]
+fn rwlock_waiting_writer_blocks_new_readers() {
+    use std::time::Duration;
+
+    let lock = RwLock::new(0);
+    std::thread::scope(|s| {
+        let r = lock.read();
+        s.spawn(|| *lock.write() += 1);
+        // Give the writer time to announce itself.
+        while lock.state.load(Ordering::Relaxed).is_multiple_of(2) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(lock.try_read().is_none());
+        drop(r);
+    });
+    assert_eq!(*lock.read(), 1);
+}
+
+#[test]
+fn rwlock_downgrade() {
+    let lock = RwLock::new(0);
+    let mut w = lock.write();
+    *w = 1;
+    let r = w.downgrade();
+    assert!(lock.try_write().is_none());
+    assert_eq!(*lock.try_read().unwrap(), 1);
+    assert_eq!(*r, 1);
+    drop(r);
+
+    // A writer queued behind the downgraded guard must still get in once it is released.
+    std::thread::scope(|s| {
+        let r = lock.write().downgrade();
+        s.spawn(|| *lock.write() += 1);
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        drop(r);
+    });
+    assert_eq!(*lock.read(), 2);
+}
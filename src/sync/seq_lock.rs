@@ -0,0 +1,184 @@
+//! A sequence lock for small `Copy` values that are read far more often than they are written.
+//!
+//! Readers never write to shared memory: they read the sequence number, copy the value, and read
+//! the sequence number again. If it was odd (a write was in progress) or changed (a write
+//! happened in between), the copy may be torn and they simply retry. Writers take a spin lock
+//! among themselves and make the sequence odd while they write.
+//!
+//! A torn copy is still a data race on the value, so both sides copy it with volatile accesses
+//! into a `MaybeUninit<T>`, and the reader only assumes it initialised once the sequence check
+//! passed. The fences are the ones from Boehm's "Can seqlocks get along with programming language
+//! memory models?".
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{fence, AtomicUsize, Ordering};
+
+use super::SpinLock;
+
+pub struct SeqLock<T> {
+    seq: AtomicUsize,
+    writer: SpinLock<()>,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+// Safety: readers only ever get copies of the value, and only copies that no write overlapped;
+// writers are serialised by `writer`. Values move between threads, so `T: Send`.
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+
+impl<T: Copy> SeqLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            seq: AtomicUsize::new(0),
+            writer: SpinLock::new(()),
+            value: UnsafeCell::new(MaybeUninit::new(value)),
+        }
+    }
+
+    /// A copy of the value, retrying for as long as writers keep getting in the way.
+    pub fn read(&self) -> T {
+        loop {
+            if let Some(value) = self.try_read() {
+                return value;
+            }
+            std::hint::spin_loop();
+        }
+    }
+
+    /// A copy of the value, or `None` if a write overlapped the attempt.
+    pub fn try_read(&self) -> Option<T> {
+        // Acquire: the copy below sees at least the write that finished at `seq1`.
+        let seq1 = self.seq.load(Ordering::Acquire);
+        if seq1 % 2 == 1 {
+            return None;
+        }
+        // Safety: the pointer is valid; the copy may be torn, which is why it stays a
+        // `MaybeUninit` until the sequence check below.
+        let value = unsafe { self.value.get().read_volatile() };
+        // Keeps the copy above from moving after the second load of `seq`.
+        fence(Ordering::Acquire);
+        let seq2 = self.seq.load(Ordering::Relaxed);
+        // Safety: no write started or finished during the copy, so it is a whole `T`.
+        (seq1 == seq2).then(|| unsafe { value.assume_init() })
+    }
+
+    /// Replaces the value.
+    pub fn store(&self, value: T) {
+        self.write(|v| *v = value);
+    }
+
+    /// Updates the value with `f`, which sees the current value. Readers don't see anything until
+    /// `f` returns. If `f` panics, the value is left as it was.
+    pub fn write<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let _writer = self.writer.lock();
+        // Only writers change `seq`, and we're the only writer.
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq + 1, Ordering::Relaxed);
+        // Makes `seq` even again when we're done, even if `f` panics; otherwise readers would
+        // spin forever. Dropped before `_writer`, so the next writer starts from an even number.
+        let _done = Done {
+            seq: &self.seq,
+            next: seq + 2,
+        };
+        // Keeps the write below from moving before the odd sequence number.
+        fence(Ordering::Release);
+        // Safety: the value is always initialised, and we're the only writer. Readers may copy it
+        // concurrently, but they'll discard the copy.
+        let mut value = unsafe { self.value.get().read_volatile().assume_init() };
+        let result = f(&mut value);
+        unsafe { self.value.get().write_volatile(MaybeUninit::new(value)) };
+        result
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        // Safety: the value is always initialised.
+        unsafe { self.value.get_mut().assume_init_mut() }
+    }
+
+    pub fn into_inner(self) -> T {
+        // Safety: the value is always initialised.
+        unsafe { self.value.into_inner().assume_init() }
+    }
+}
+
+/// Ends a write by storing the next even sequence number.
+struct Done<'a> {
+    seq: &'a AtomicUsize,
+    next: usize,
+}
+
+impl Drop for Done<'_> {
+    fn drop(&mut self) {
+        // Release: a reader that sees the even number also sees the value.
+        self.seq.store(self.next, Ordering::Release);
+    }
+}
+
+impl<T: Copy + Default> Default for SeqLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: Copy + std::fmt::Debug> std::fmt::Debug for SeqLock<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("SeqLock").field(&self.read()).finish()
+    }
+}
+
+#[test]
+fn seq_lock_read_write() {
+    let mut lock = SeqLock::new((1, 2));
+    assert_eq!(lock.read(), (1, 2));
+    lock.store((3, 4));
+    assert_eq!(lock.write(|v| std::mem::replace(&mut v.0, 5)), 3);
+    assert_eq!(lock.try_read(), Some((5, 4)));
+    lock.get_mut().1 = 6;
+    assert_eq!(lock.into_inner(), (5, 6));
+}
+
+#[test]
+fn seq_lock_survives_a_panicking_writer() {
+    let lock = SeqLock::new(1);
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        lock.write(|v| {
+            *v = 2;
+            panic!("writer failed");
+        })
+    }));
+    assert!(panicked.is_err());
+    // The half-done write is discarded, and neither readers nor writers are locked out.
+    assert_eq!(lock.read(), 1);
+    lock.store(3);
+    assert_eq!(lock.try_read(), Some(3));
+}
+
+#[test]
+fn seq_lock_readers_never_see_torn_values() {
+    // Every value written keeps all four words equal, so any mix of two writes shows up.
+    let lock = SeqLock::new([0u64; 4]);
+    let done = std::sync::atomic::AtomicBool::new(false);
+    std::thread::scope(|s| {
+        for _ in 0..2 {
+            s.spawn(|| {
+                for _ in 0..10_000 {
+                    lock.write(|v| *v = [v[0] + 1; 4]);
+                }
+            });
+        }
+        s.spawn(|| {
+            let mut last = 0;
+            while !done.load(Ordering::Relaxed) {
+                let v = lock.read();
+                assert!(v.iter().all(|&x| x == v[0]), "torn read: {v:?}");
+                assert!(v[0] >= last);
+                last = v[0];
+            }
+        });
+        while lock.read()[0] < 20_000 {
+            std::thread::yield_now();
+        }
+        done.store(true, Ordering::Relaxed);
+    });
+    assert_eq!(lock.read(), [20_000; 4]);
+}
@@ -0,0 +1,115 @@
+//! A spin lock generalising the `LOCKED` flag from `release_acquire_ordering.rs`.
+//!
+//! Instead of a bare `AtomicBool` next to a `static mut`, the flag and the data it protects live
+//! in the same struct, and the only way to reach the data is through a [`Guard`] that releases
+//! the lock when it is dropped.
+
+use std::cell::UnsafeCell;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub struct SpinLock<T> {
+    locked: AtomicBool,
+    value: UnsafeCell<T>,
+}
+
+// Safety: the lock hands out access to `value` to at most one thread at a time, so sharing a
+// `SpinLock<T>` only requires that `T` can be sent to whichever thread currently holds it.
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Spins until the lock is acquired.
+    pub fn lock(&self) -> Guard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+            // Wait on a plain load so the cache line isn't bounced around by failing swaps.
+            while self.locked.load(Ordering::Relaxed) {
+                std::hint::spin_loop();
+            }
+        }
+    }
+
+    /// Acquires the lock only if it is currently free.
+    pub fn try_lock(&self) -> Option<Guard<'_, T>> {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| Guard { lock: self })
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// Exclusive access through `&mut self` needs no locking at all.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: Default> Default for SpinLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// Proof that the lock is held; unlocks on drop.
+pub struct Guard<'a, T> {
+    lock: &'a SpinLock<T>,
+}
+
+// Safety: a `&Guard` only gives out `&T`, so it may be shared when `T` may be.
+unsafe impl<T: Sync> Sync for Guard<'_, T> {}
+
+impl<T> Deref for Guard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: the very existence of this Guard guarantees we've exclusively locked the lock.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for Guard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: the very existence of this Guard guarantees we've exclusively locked the lock.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for Guard<'_, T> {
+    fn drop(&mut self) {
+        // Everything written through the guard happens-before the next successful `lock`.
+        self.lock.locked.store(false, Ordering::Release);
+    }
+}
+
+#[test]
+fn spin_lock_all_increments_land() {
+    let lock = SpinLock::new(String::new());
+    std::thread::scope(|s| {
+        for _ in 0..100 {
+            s.spawn(|| lock.lock().push('!'));
+        }
+    });
+    assert_eq!(lock.into_inner(), "!".repeat(100));
+}
+
+#[test]
+fn spin_lock_try_lock() {
+    let lock = SpinLock::new(0);
+    let mut guard = lock.try_lock().unwrap();
+    assert!(lock.try_lock().is_none());
+    *guard += 1;
+    drop(guard);
+    assert_eq!(*lock.try_lock().unwrap(), 1);
+}
@@ -0,0 +1,216 @@
+//! Pointers with a version tag in their unused bits, for `compare_exchange` without ABA.
+//!
+//! A `compare_exchange` on a bare pointer succeeds whenever the pointer is the same address,
+//! even if the object there was freed and a new one allocated in its place since the value was
+//! read (see [`lockfree`](crate::lockfree)). Bumping a tag on every store makes each store a new
+//! `(pointer, tag)` pair, so a stale `compare_exchange` fails, unless the tag wrapped all the way
+//! around in between.
+//!
+//! The tag lives in the low bits that `T`'s alignment keeps zero and, on x86-64, in the top 16
+//! bits, which user-space addresses there don't use (the kernel only hands out addresses above
+//! 47 bits to programs that ask for them). So a `TaggedPtr<u64>` has a 19-bit tag on x86-64, and
+//! a `TaggedPtr<u8>` 16 bits. Elsewhere the top bits may be in use even on 64-bit targets (on
+//! AArch64, memory tagging and Android's allocator put tags in the top byte), so the tag only
+//! gets the alignment bits: 3 for a `TaggedPtr<u64>`, none for a `TaggedPtr<u8>`.
+
+use std::fmt;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+/// Address bits at the top that are free for the tag.
+const HIGH_BITS: u32 = if cfg!(all(target_arch = "x86_64", target_pointer_width = "64")) {
+    16
+} else {
+    0
+};
+
+pub struct TaggedPtr<T> {
+    /// The pointer with the tag mixed in, kept as a pointer so that it keeps its provenance.
+    raw: *mut T,
+}
+
+impl<T> TaggedPtr<T> {
+    /// How many bits of tag fit.
+    pub const TAG_BITS: u32 = Self::LOW_BITS + HIGH_BITS;
+    const LOW_BITS: u32 = mem::align_of::<T>().trailing_zeros();
+    const LOW_MASK: usize = (1 << Self::LOW_BITS) - 1;
+    const ADDR_MASK: usize = (usize::MAX >> HIGH_BITS) & !Self::LOW_MASK;
+
+    /// Tags `ptr` with `tag`, modulo `2^TAG_BITS`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `ptr` is misaligned or uses the top bits.
+    pub fn new(ptr: *mut T, tag: usize) -> Self {
+        assert_eq!(
+            ptr.addr() & !Self::ADDR_MASK,
+            0,
+            "pointer {ptr:p} overlaps the tag bits"
+        );
+        let low = tag & Self::LOW_MASK;
+        let high = (tag >> Self::LOW_BITS)
+            .checked_shl(usize::BITS - HIGH_BITS)
+            .unwrap_or(0);
+        Self::from_raw(ptr.map_addr(|addr| addr | low | high))
+    }
+
+    pub const fn null() -> Self {
+        Self::from_raw(ptr::null_mut())
+    }
+
+    const fn from_raw(raw: *mut T) -> Self {
+        Self { raw }
+    }
+
+    pub fn ptr(self) -> *mut T {
+        self.raw.map_addr(|addr| addr & Self::ADDR_MASK)
+    }
+
+    pub fn tag(self) -> usize {
+        let addr = self.raw.addr();
+        let high = addr.checked_shr(usize::BITS - HIGH_BITS).unwrap_or(0);
+        (high << Self::LOW_BITS) | (addr & Self::LOW_MASK)
+    }
+
+    pub fn is_null(self) -> bool {
+        self.ptr().is_null()
+    }
+
+    /// `ptr` with the next tag: what to store in place of `self`.
+    pub fn next(self, ptr: *mut T) -> Self {
+        Self::new(ptr, self.tag().wrapping_add(1))
+    }
+}
+
+impl<T> Clone for TaggedPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for TaggedPtr<T> {}
+
+impl<T> PartialEq for TaggedPtr<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.raw == other.raw
+    }
+}
+
+impl<T> Eq for TaggedPtr<T> {}
+
+impl<T> fmt::Debug for TaggedPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaggedPtr")
+            .field("ptr", &self.ptr())
+            .field("tag", &self.tag())
+            .finish()
+    }
+}
+
+/// An `AtomicPtr` holding a [`TaggedPtr`]. Its operations compare and replace the pointer and
+/// the tag together.
+pub struct AtomicTaggedPtr<T> {
+    raw: AtomicPtr<T>,
+}
+
+impl<T> AtomicTaggedPtr<T> {
+    pub const fn new(value: TaggedPtr<T>) -> Self {
+        Self {
+            raw: AtomicPtr::new(value.raw),
+        }
+    }
+
+    pub const fn null() -> Self {
+        Self::new(TaggedPtr::null())
+    }
+
+    pub fn load(&self, order: Ordering) -> TaggedPtr<T> {
+        TaggedPtr::from_raw(self.raw.load(order))
+    }
+
+    pub fn store(&self, value: TaggedPtr<T>, order: Ordering) {
+        self.raw.store(value.raw, order);
+    }
+
+    pub fn swap(&self, value: TaggedPtr<T>, order: Ordering) -> TaggedPtr<T> {
+        TaggedPtr::from_raw(self.raw.swap(value.raw, order))
+    }
+
+    pub fn compare_exchange(
+        &self,
+        current: TaggedPtr<T>,
+        new: TaggedPtr<T>,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<TaggedPtr<T>, TaggedPtr<T>> {
+        self.raw
+            .compare_exchange(current.raw, new.raw, success, failure)
+            .map(TaggedPtr::from_raw)
+            .map_err(TaggedPtr::from_raw)
+    }
+
+    pub fn compare_exchange_weak(
+        &self,
+        current: TaggedPtr<T>,
+        new: TaggedPtr<T>,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<TaggedPtr<T>, TaggedPtr<T>> {
+        self.raw
+            .compare_exchange_weak(current.raw, new.raw, success, failure)
+            .map(TaggedPtr::from_raw)
+            .map_err(TaggedPtr::from_raw)
+    }
+
+    pub fn into_inner(self) -> TaggedPtr<T> {
+        TaggedPtr::from_raw(self.raw.into_inner())
+    }
+}
+
+impl<T> Default for AtomicTaggedPtr<T> {
+    fn default() -> Self {
+        Self::null()
+    }
+}
+
+impl<T> fmt::Debug for AtomicTaggedPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.load(Ordering::Relaxed), f)
+    }
+}
+
+#[test]
+fn tagged_ptr_round_trips_and_wraps() {
+    let mut x = 5u64;
+    let p = TaggedPtr::new(&mut x, 6);
+    assert_eq!((p.ptr(), p.tag()), (&mut x as *mut u64, 6));
+    assert_eq!(TaggedPtr::<u64>::TAG_BITS, 3 + HIGH_BITS);
+    // Safety: `x` is alive, and the pointer came from a reference to it.
+    assert_eq!(unsafe { *p.ptr() }, 5);
+
+    let max = (1 << TaggedPtr::<u64>::TAG_BITS) - 1;
+    let last = TaggedPtr::new(&mut x, max);
+    assert_eq!(last.tag(), max);
+    assert_eq!(last.next(last.ptr()).tag(), 0);
+    assert!(TaggedPtr::<u64>::null().next(ptr::null_mut()).is_null());
+}
+
+#[test]
+fn tagged_ptr_compare_exchange_sees_reuse() {
+    let a = Box::into_raw(Box::new(1u32));
+    let shared = AtomicTaggedPtr::new(TaggedPtr::new(a, 0));
+    let seen = shared.load(Ordering::Acquire);
+
+    // Another thread replaces `a` and then puts the same address back: ABA.
+    let b = Box::into_raw(Box::new(2u32));
+    shared.store(seen.next(b), Ordering::Release);
+    shared.store(seen.next(b).next(a), Ordering::Release);
+    assert_eq!(shared.load(Ordering::Relaxed).ptr(), seen.ptr());
+
+    // The address matches, but the version doesn't.
+    let stale = shared.compare_exchange(seen, seen.next(b), Ordering::AcqRel, Ordering::Acquire);
+    assert_eq!(stale.unwrap_err().tag(), 2);
+    // Safety: both boxes are only reachable through these pointers.
+    unsafe { drop((Box::from_raw(a), Box::from_raw(b))) };
+}
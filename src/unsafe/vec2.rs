@@ -5,6 +5,10 @@ use std::mem;
 use std::ops::{Deref, DerefMut};
 use std::ptr::{self, NonNull};
 
+use plygnd::pool::iter::{
+    self as par, FromParallelIterator, IntoParallelIterator, ParallelIterator,
+};
+
 struct RawVec<T> {
     ptr: NonNull<T>,
     cap: usize,
@@ -26,22 +30,19 @@ impl<T> RawVec<T> {
     }
 
     fn grow(&mut self) {
+        // This can't overflow because we ensure self.cap <= isize:MAX.
+        let new_cap = if self.cap == 0 { 1 } else { 2 * self.cap };
+        self.grow_to(new_cap);
+    }
+
+    fn grow_to(&mut self, new_cap: usize) {
         // since we set the capacity to usize::MAX when T has size 0,
         // getting to here necessarily means the Vec is overfull.
         assert!(mem::size_of::<T>() != 0, "capacity overflow");
 
-        let (new_cap, new_layout) = if self.cap == 0 {
-            (1, Layout::array::<T>(1).unwrap())
-        } else {
-            // This can't overflow because we ensure self.cap <= isize:MAX.
-            let new_cap = 2 * self.cap;
-
-            // Layout::array checks that the number of bytes is <= usize::MAX
-            // but this is redundant since old_layout.size() <= isize::MAX,
-            // so the `unwrap` should never fail.
-            let new_layout = Layout::array::<T>(new_cap).unwrap();
-            (new_cap, new_layout)
-        };
+        // Layout::array checks that the number of bytes is <= usize::MAX,
+        // which fails for capacities that can't be allocated anyway.
+        let new_layout = Layout::array::<T>(new_cap).expect("capacity overflow");
 
         // Ensure that the new allocation doesn't exceed `isize::MAX` bytes.
         assert!(
@@ -116,6 +117,15 @@ impl<T> Vec<T> {
         self.len += 1;
     }
 
+    /// Makes room for at least `additional` more elements without reallocating.
+    pub fn reserve(&mut self, additional: usize) {
+        let needed = self.len.checked_add(additional).expect("capacity overflow");
+        if needed > self.cap() {
+            // At least doubling, like `push`, so repeated reserves stay amortised.
+            self.buf.grow_to(needed.max(2 * self.cap()));
+        }
+    }
+
     pub fn pop(&mut self) -> Option<T> {
         if self.len == 0 {
             None
@@ -335,6 +345,34 @@ impl<'a, T> Drop for Drain<'a, T> {
     }
 }
 
+// `par_iter`, `par_iter_mut` and `par_sort` come from the slice through `Deref`.
+impl<T: Send> IntoParallelIterator for Vec<T> {
+    type Item = T;
+    type Iter = par::IntoIter<T, Vec<T>>;
+
+    fn into_par_iter(mut self) -> Self::Iter {
+        let (ptr, len) = (self.ptr(), self.len);
+        // With `len` at zero the Vec no longer drops the elements, but still owns the buffer,
+        // which doesn't move with it.
+        self.len = 0;
+        unsafe { par::IntoIter::from_raw_parts(self, ptr, len) }
+    }
+}
+
+impl<T: Send> FromParallelIterator<T> for Vec<T> {
+    fn from_par_iter<I: ParallelIterator<Item = T>>(iter: I) -> Self {
+        let chunks = iter.collect_vec_list();
+        let mut v = Vec::new();
+        v.reserve(chunks.iter().map(|chunk| chunk.len()).sum());
+        for chunk in chunks {
+            for elem in chunk {
+                v.push(elem);
+            }
+        }
+        v
+    }
+}
+
 #[test]
 fn vec() {
     let mut v: Vec<u32> = Vec::new();
@@ -346,3 +384,31 @@ fn vec() {
         println!("{:?}", e)
     }
 }
+
+#[test]
+fn par_iter_matches_sequential() {
+    use plygnd::pool::iter::ParallelSlice;
+
+    let mut v: Vec<u64> = Vec::new();
+    for i in 0..50_000u64 {
+        v.push(i.wrapping_mul(0x9E37_79B9) % 10_007);
+    }
+
+    let sum: u64 = v.par_iter().map(|&x| x * 2).sum();
+    assert_eq!(sum, v.iter().map(|&x| x * 2).sum::<u64>());
+
+    let odd: Vec<u64> = v.par_iter().filter(|x| *x % 2 == 1).map(|&x| x).collect();
+    assert!(odd.iter().eq(v.iter().filter(|x| *x % 2 == 1)));
+
+    v.par_iter_mut().for_each(|x| *x += 1);
+    let mut sorted = v.to_vec();
+    sorted.sort();
+    v.par_sort();
+    assert_eq!(&v[..], &sorted[..]);
+
+    let strings: Vec<String> = v.into_par_iter().map(|x| x.to_string()).collect();
+    assert!(strings
+        .iter()
+        .zip(&sorted)
+        .all(|(s, x)| *s == x.to_string()));
+}